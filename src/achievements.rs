@@ -0,0 +1,154 @@
+//! Achievements and the run progress that unlocks them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Move interval at or below which `SpeedDemon` unlocks, in seconds.
+const SPEED_DEMON_INTERVAL: f32 = 0.08;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Achievement {
+    FirstBite,
+    Length10,
+    Length25,
+    Survive2Minutes,
+    SpeedDemon,
+}
+
+impl Achievement {
+    pub const ALL: [Achievement; 5] = [
+        Self::FirstBite,
+        Self::Length10,
+        Self::Length25,
+        Self::Survive2Minutes,
+        Self::SpeedDemon,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::FirstBite => "First Bite",
+            Self::Length10 => "Growing Up",
+            Self::Length25 => "Long Boi",
+            Self::Survive2Minutes => "Survivor",
+            Self::SpeedDemon => "Speed Demon",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::FirstBite => "Eat your first food",
+            Self::Length10 => "Reach a length of 10",
+            Self::Length25 => "Reach a length of 25",
+            Self::Survive2Minutes => "Survive for two minutes in one run",
+            Self::SpeedDemon => "Move at 80 ms per tick or faster",
+        }
+    }
+
+    pub fn is_met(self, progress: &Progress) -> bool {
+        match self {
+            Self::FirstBite => progress.food_eaten >= 1,
+            Self::Length10 => progress.length >= 10,
+            Self::Length25 => progress.length >= 25,
+            Self::Survive2Minutes => progress.time_survived >= 120.0,
+            Self::SpeedDemon => progress
+                .fastest_interval
+                .is_some_and(|interval| interval <= SPEED_DEMON_INTERVAL),
+        }
+    }
+}
+
+/// What the current run has achieved so far.
+#[derive(Default, Clone, Debug)]
+pub struct Progress {
+    pub food_eaten: u32,
+    pub length: usize,
+    pub time_survived: f32,
+    pub fastest_interval: Option<f32>,
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Achievements {
+    unlocked: BTreeSet<Achievement>,
+}
+
+impl Achievements {
+    /// `achievements.json` in the platform data directory.
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("bevy-snake").join("achievements.json"))
+    }
+
+    /// Reads the achievements at `path`; a missing or corrupt file gives none.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn is_unlocked(&self, achievement: Achievement) -> bool {
+        self.unlocked.contains(&achievement)
+    }
+
+    /// Unlocks every achievement `progress` meets and returns the ones that
+    /// were not unlocked before.
+    pub fn unlock(&mut self, progress: &Progress) -> Vec<Achievement> {
+        Achievement::ALL
+            .iter()
+            .copied()
+            .filter(|achievement| achievement.is_met(progress))
+            .filter(|achievement| self.unlocked.insert(*achievement))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_follow_progress() {
+        let progress = Progress {
+            food_eaten: 9,
+            length: 10,
+            time_survived: 119.0,
+            fastest_interval: Some(0.1),
+        };
+        let met: Vec<Achievement> = Achievement::ALL
+            .iter()
+            .copied()
+            .filter(|achievement| achievement.is_met(&progress))
+            .collect();
+        assert_eq!(met, [Achievement::FirstBite, Achievement::Length10]);
+        assert!(Achievement::SpeedDemon.is_met(&Progress {
+            fastest_interval: Some(SPEED_DEMON_INTERVAL),
+            ..Default::default()
+        }));
+        assert!(!Achievement::FirstBite.is_met(&Progress::default()));
+    }
+
+    #[test]
+    fn unlocks_only_once() {
+        let mut achievements = Achievements::default();
+        let progress = Progress {
+            food_eaten: 1,
+            time_survived: 120.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            achievements.unlock(&progress),
+            [Achievement::FirstBite, Achievement::Survive2Minutes]
+        );
+        assert!(achievements.unlock(&progress).is_empty());
+        assert!(achievements.is_unlocked(Achievement::Survive2Minutes));
+    }
+}
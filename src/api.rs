@@ -0,0 +1,490 @@
+//! A local WebSocket endpoint for bots, opt-in with `--bot-api PORT`, so a
+//! snake-playing program in any language can steer Player 1 in the running
+//! game. After every move each connected bot is sent the arena as a
+//! `server::Frame` in a JSON text message, and a bot turns the snake by
+//! sending `left`, `up`, `right` or `down` as a text message.
+//!
+//! Only as much of RFC 6455 is spoken as that takes: unfragmented text
+//! messages, pings and closing. The endpoint only listens on the loopback
+//! address.
+
+use crate::options::Options;
+use crate::server::{parse_direction, Frame};
+use crate::{AppState, Direction, Player, RunTick, SnakeHead};
+use bevy::prelude::*;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Appended to a client's key to prove the server speaks WebSocket.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Longest message a bot may send; a direction takes five bytes.
+const MAX_MESSAGE_LENGTH: u64 = 1024;
+
+/// Messages a bot may have waiting to be written before it counts as fallen
+/// behind and is dropped, so a stalled bot never holds the game up.
+const QUEUE_LENGTH: usize = 8;
+
+/// How long a new connection may take to send its upgrade request.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// SHA-1, which the handshake needs and nothing else here does.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (total, part) in h.iter_mut().zip(&[a, b, c, d, e]) {
+            *total = total.wrapping_add(*part);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(&h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+            bits | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() {
+                ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char
+            } else {
+                '='
+            });
+        }
+    }
+    encoded
+}
+
+/// The `Sec-WebSocket-Accept` answer to a client's `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(
+        format!("{}{}", key.trim(), HANDSHAKE_GUID).as_bytes(),
+    ))
+}
+
+/// Reads the client's upgrade request and agrees to it.
+fn handshake(stream: &mut TcpStream) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut byte = [0; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte)? == 0 || request.len() > 8192 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no end of headers",
+            ));
+        }
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let key = request.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        Some(value).filter(|_| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+    });
+    match key {
+        Some(key) => write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        ),
+        None => {
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")?;
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a WebSocket request",
+            ))
+        }
+    }
+}
+
+/// Reads the next message from a client: its opcode and unmasked payload.
+fn read_message(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut bytes = [0; 2];
+            stream.read_exact(&mut bytes)?;
+            u16::from_be_bytes(bytes) as u64
+        }
+        127 => {
+            let mut bytes = [0; 8];
+            stream.read_exact(&mut bytes)?;
+            u64::from_be_bytes(bytes)
+        }
+        length => length as u64,
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too long",
+        ));
+    }
+    let mut mask = [0; 4];
+    if head[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((head[0] & 0x0f, payload))
+}
+
+/// `payload` as a single unmasked message, as a server sends them.
+fn message(opcode: u8, payload: &[u8]) -> Arc<[u8]> {
+    let mut message = vec![0x80 | opcode];
+    match payload.len() {
+        length if length < 126 => message.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            message.push(126);
+            message.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            message.push(127);
+            message.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    message.extend_from_slice(payload);
+    message.into()
+}
+
+/// A connected bot: its connection, and the queue its writer thread sends
+/// from.
+struct Bot {
+    stream: TcpStream,
+    queue: SyncSender<Arc<[u8]>>,
+}
+
+/// The bots connected to the endpoint, and the turns they have sent since
+/// the last frame.
+#[derive(Default)]
+pub struct BotApi {
+    bots: Arc<Mutex<Vec<Bot>>>,
+    turns: Arc<Mutex<Vec<Direction>>>,
+    /// The move and whether the run was over when the arena was last sent.
+    sent: Option<(u32, bool)>,
+}
+
+impl BotApi {
+    /// Listens for bots on `address`.
+    pub fn listen(address: SocketAddr) -> io::Result<Self> {
+        Ok(Self::serve(TcpListener::bind(address)?))
+    }
+
+    /// Takes the bots that connect to `listener`, each with a thread that
+    /// reads from it and one that writes to it.
+    fn serve(listener: TcpListener) -> Self {
+        let api = Self::default();
+        let (bots, turns) = (api.bots.clone(), api.turns.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (bots, turns) = (bots.clone(), turns.clone());
+                match stream {
+                    // Each connection is upgraded on a thread of its own, so
+                    // one that never finishes its request holds up no other.
+                    Ok(stream) => {
+                        thread::spawn(move || match join(&stream, &bots) {
+                            Ok(queue) => receive_turns(stream, &turns, &queue),
+                            Err(err) => eprintln!("could not connect a bot: {}", err),
+                        });
+                    }
+                    Err(err) => eprintln!("could not connect a bot: {}", err),
+                }
+            }
+        });
+        api
+    }
+
+    /// The turns bots have sent since the last call, oldest first.
+    pub fn take_turns(&self) -> Vec<Direction> {
+        std::mem::take(&mut *self.turns.lock().unwrap())
+    }
+
+    /// Queues `text` for every bot without waiting on any of them, dropping
+    /// those that have gone or fallen `QUEUE_LENGTH` messages behind.
+    pub fn broadcast(&self, text: &str) {
+        let message = message(OPCODE_TEXT, text.as_bytes());
+        self.bots.lock().unwrap().retain(|bot| {
+            let queued = bot.queue.try_send(message.clone()).is_ok();
+            if !queued {
+                // Unblocks the bot's threads so they end.
+                let _ = bot.stream.shutdown(Shutdown::Both);
+            }
+            queued
+        });
+    }
+}
+
+/// Upgrades a new connection and adds it to `bots`, returning the queue of
+/// messages for it.
+fn join(stream: &TcpStream, bots: &Mutex<Vec<Bot>>) -> io::Result<SyncSender<Arc<[u8]>>> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    handshake(&mut stream.try_clone()?)?;
+    stream.set_read_timeout(None)?;
+    let (queue, queued) = mpsc::sync_channel(QUEUE_LENGTH);
+    let writer = stream.try_clone()?;
+    bots.lock().unwrap().push(Bot {
+        stream: stream.try_clone()?,
+        queue: queue.clone(),
+    });
+    thread::spawn(move || send_messages(writer, queued));
+    Ok(queue)
+}
+
+/// Writes one bot's queued messages until it goes, or is dropped.
+fn send_messages(mut stream: TcpStream, queued: Receiver<Arc<[u8]>>) {
+    for message in queued {
+        if stream.write_all(&message).is_err() {
+            break;
+        }
+    }
+}
+
+/// Collects one bot's turns and answers its pings until it closes the
+/// connection.
+fn receive_turns(
+    mut stream: TcpStream,
+    turns: &Mutex<Vec<Direction>>,
+    queue: &SyncSender<Arc<[u8]>>,
+) {
+    while let Ok((opcode, payload)) = read_message(&mut stream) {
+        match opcode {
+            OPCODE_TEXT => {
+                if let Some(direction) = parse_direction(&String::from_utf8_lossy(&payload)) {
+                    turns.lock().unwrap().push(direction);
+                }
+            }
+            // Fails only once the writer has gone, and the next read with it.
+            OPCODE_PING => {
+                let _ = queue.send(message(OPCODE_PONG, &payload));
+            }
+            OPCODE_CLOSE => break,
+            _ => {}
+        }
+    }
+}
+
+/// Turns Player 1's snake the ways the bots said.
+pub fn bot_turns(
+    api: Res<BotApi>,
+    state: Res<AppState>,
+    mut heads: Query<(&Player, &mut SnakeHead)>,
+) {
+    let turns = api.take_turns();
+    if *state != AppState::Playing {
+        return;
+    }
+    for (player, mut head) in heads.iter_mut() {
+        if *player == Player::One {
+            for direction in &turns {
+                head.steer(*direction);
+            }
+        }
+    }
+}
+
+/// Sends the bots the arena after every move, and when the run ends.
+pub fn bot_frames(world: &mut World, resources: &mut Resources) {
+    let now = (
+        resources.get::<RunTick>().unwrap().0,
+        *resources.get::<AppState>().unwrap() == AppState::GameOver,
+    );
+    if resources.get::<BotApi>().unwrap().sent == Some(now) {
+        return;
+    }
+    let frame = Frame::observe(world, resources, Player::One);
+    let mut api = resources.get_mut::<BotApi>().unwrap();
+    api.sent = Some(now);
+    match serde_json::to_string(&frame) {
+        Ok(text) => api.broadcast(&text),
+        Err(err) => eprintln!("could not encode frame: {}", err),
+    }
+}
+
+/// Serves the bot API on the loopback port `--bot-api` gives, if any.
+pub struct BotApiPlugin;
+
+impl Plugin for BotApiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let port = app
+            .resources()
+            .get::<Options>()
+            .and_then(|options| options.bot_api);
+        let port = match port {
+            Some(port) => port,
+            None => return,
+        };
+        match BotApi::listen(SocketAddr::from((Ipv4Addr::LOCALHOST, port))) {
+            Ok(api) => {
+                app.add_resource(api)
+                    .add_system(bot_turns.system())
+                    .add_system_to_stage(stage::POST_UPDATE, bot_frames.thread_local_system());
+            }
+            Err(err) => eprintln!("could not serve the bot API on port {}: {}", port, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn answers_the_rfc_handshake_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    /// Serves the bot API on `listener` and connects a bot to it, returning
+    /// the bot and the API with the server's answer to the upgrade request.
+    fn connect(listener: TcpListener) -> (TcpStream, BotApi, String) {
+        let mut bot = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        bot.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let api = BotApi::serve(listener);
+        write!(
+            bot,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut answer = Vec::new();
+        let mut byte = [0; 1];
+        while !answer.ends_with(b"\r\n\r\n") {
+            bot.read_exact(&mut byte).unwrap();
+            answer.push(byte[0]);
+        }
+        (bot, api, String::from_utf8(answer).unwrap())
+    }
+
+    /// `payload` masked as a client must send it.
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut message = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        message.extend_from_slice(&mask);
+        message.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        message
+    }
+
+    /// A connection that never finishes its upgrade request does not keep
+    /// the bots after it out.
+    #[test]
+    fn a_silent_connection_does_not_block_the_next_bot() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _silent = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_bot, _api, answer) = connect(listener);
+        assert!(answer.starts_with("HTTP/1.1 101 "));
+    }
+
+    /// A bot that connects can turn the snake and is sent what the game
+    /// broadcasts.
+    #[test]
+    fn bots_send_turns_and_get_frames() {
+        let (mut bot, api, answer) = connect(TcpListener::bind("127.0.0.1:0").unwrap());
+        assert!(answer.starts_with("HTTP/1.1 101 "));
+        assert!(answer.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        bot.write_all(&masked(OPCODE_TEXT, b"left")).unwrap();
+        let mut turns = Vec::new();
+        for _ in 0..100 {
+            turns = api.take_turns();
+            if !turns.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(turns, vec![Direction::Left]);
+
+        api.broadcast("{}");
+        assert_eq!(
+            read_message(&mut bot).unwrap(),
+            (OPCODE_TEXT, b"{}".to_vec())
+        );
+    }
+
+    #[test]
+    fn pings_are_answered_with_pongs() {
+        let (mut bot, _api, _) = connect(TcpListener::bind("127.0.0.1:0").unwrap());
+        bot.write_all(&masked(OPCODE_PING, b"still there?"))
+            .unwrap();
+        assert_eq!(
+            read_message(&mut bot).unwrap(),
+            (OPCODE_PONG, b"still there?".to_vec())
+        );
+    }
+
+    /// A bot that stops reading is dropped once its queue fills, rather than
+    /// holding up the broadcasts.
+    #[test]
+    fn a_bot_that_falls_behind_is_dropped() {
+        let (_bot, api, _) = connect(TcpListener::bind("127.0.0.1:0").unwrap());
+        let frame = "x".repeat(1 << 16);
+        let started = Instant::now();
+        for _ in 0..1000 {
+            api.broadcast(&frame);
+            if api.bots.lock().unwrap().is_empty() {
+                break;
+            }
+        }
+        assert!(api.bots.lock().unwrap().is_empty());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
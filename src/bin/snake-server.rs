@@ -0,0 +1,18 @@
+//! The dedicated server: plays the game the usual options describe for
+//! clients started with `--connect`, e.g.
+//! `snake-server --versus --listen 0.0.0.0:7777`, or
+//! `snake-server --versus --players 4` for a four-snake battle.
+
+use bevy_snake::options::Options;
+use bevy_snake::server::{self, DEFAULT_ADDRESS};
+
+fn main() {
+    let options = Options::from_args();
+    let address = options
+        .listen
+        .unwrap_or_else(|| DEFAULT_ADDRESS.parse().unwrap());
+    if let Err(err) = server::run(&options, address) {
+        eprintln!("server stopped: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Which keys trigger which action, kept in a file the player can edit.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    TurnLeft,
+    TurnDown,
+    TurnUp,
+    TurnRight,
+    Boost,
+    /// Pauses and resumes a run.
+    Pause,
+    /// Leaves the game over screen for a fresh run, or resumes a paused one.
+    Restart,
+    /// Opens the settings from the pause screen.
+    Settings,
+}
+
+/// The keys bound to every action. Any of an action's keys triggers it.
+/// Actions missing from the file keep their default keys.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub turn_left: Vec<KeyCode>,
+    pub turn_down: Vec<KeyCode>,
+    pub turn_up: Vec<KeyCode>,
+    pub turn_right: Vec<KeyCode>,
+    pub boost: Vec<KeyCode>,
+    pub pause: Vec<KeyCode>,
+    pub restart: Vec<KeyCode>,
+    pub settings: Vec<KeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            turn_left: vec![KeyCode::Left, KeyCode::A],
+            turn_down: vec![KeyCode::Down, KeyCode::S],
+            turn_up: vec![KeyCode::Up, KeyCode::W],
+            turn_right: vec![KeyCode::Right, KeyCode::D],
+            boost: vec![KeyCode::LShift],
+            pause: vec![KeyCode::Escape, KeyCode::P],
+            restart: vec![KeyCode::Return, KeyCode::Space],
+            settings: vec![KeyCode::O],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// `keys.toml` next to the settings, e.g. `~/.config/bevy-snake` on
+    /// Linux.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("bevy-snake").join("keys.toml"))
+    }
+
+    /// Reads the bindings at `path`. A missing file is created with the
+    /// default bindings so there is something to edit; an unreadable one
+    /// falls back to the defaults and is left alone.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse().unwrap_or_else(|err| {
+                eprintln!("could not read key bindings {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let bindings = Self::default();
+                if let Err(err) = bindings.save(path) {
+                    eprintln!("could not save key bindings {}: {}", path.display(), err);
+                }
+                bindings
+            }
+            Err(err) => {
+                eprintln!("could not read key bindings {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text =
+            toml::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        match action {
+            Action::TurnLeft => &self.turn_left,
+            Action::TurnDown => &self.turn_down,
+            Action::TurnUp => &self.turn_up,
+            Action::TurnRight => &self.turn_right,
+            Action::Boost => &self.boost,
+            Action::Pause => &self.pause,
+            Action::Restart => &self.restart,
+            Action::Settings => &self.settings,
+        }
+    }
+
+    /// Whether any key bound to `action` is held.
+    pub fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        self.keys(action).iter().any(|key| input.pressed(*key))
+    }
+
+    /// Whether any key bound to `action` went down this frame.
+    pub fn just_pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        self.keys(action).iter().any(|key| input.just_pressed(*key))
+    }
+}
+
+impl std::str::FromStr for KeyBindings {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_toml() {
+        let bindings = KeyBindings {
+            pause: vec![KeyCode::Tab],
+            ..Default::default()
+        };
+        let text = toml::to_string(&bindings).unwrap();
+        assert_eq!(text.parse::<KeyBindings>().unwrap(), bindings);
+    }
+
+    #[test]
+    fn missing_actions_keep_their_defaults() {
+        let bindings: KeyBindings = "turn_left = [\"J\"]\n".parse().unwrap();
+        assert_eq!(bindings.keys(Action::TurnLeft), [KeyCode::J]);
+        assert_eq!(
+            bindings.keys(Action::TurnRight),
+            KeyBindings::default().keys(Action::TurnRight)
+        );
+    }
+
+    #[test]
+    fn a_missing_file_is_created_with_the_defaults() {
+        let path = std::env::temp_dir()
+            .join("bevy-snake-missing-keys")
+            .join("keys.toml");
+        let _ = fs::remove_file(&path);
+        assert_eq!(KeyBindings::load(&path), KeyBindings::default());
+        assert_eq!(KeyBindings::load(&path), KeyBindings::default());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Computer-driven snakes: the rival in `GameMode::VersusBot`, which races
+//! the player for food, and the autopilot F1 hands the player's snake to.
+
+use crate::food::Food;
+use crate::path::{direction_to, find_path, Grid};
+use crate::{
+    snake_stage, AddSnakeStep, Direction, GameMode, GameRules, GrowthEvent, Obstacles, Player,
+    Position, ReplayMode, SafeBounds, Score, ScoreEvent, SnakeHead, SnakeSegments,
+};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// Points paid on top of the usual ones for eating the food a bot was
+/// heading for.
+pub const STEAL_BONUS: u32 = 5;
+
+/// Marks a head the computer steers, and remembers the food it is after.
+#[derive(Default, Clone)]
+pub struct Bot {
+    target: Option<Position>,
+}
+
+/// Marks a player's head the solver steers in place of the keyboard.
+#[derive(Clone)]
+pub struct Autopilot;
+
+/// Whether F1 has handed Player 1's snake to the autopilot.
+#[derive(Default)]
+pub struct AutopilotEngaged(pub bool);
+
+/// F1 engages the autopilot, or hands control back. Replays steer
+/// themselves, so it stays off while one plays back.
+pub fn toggle_autopilot(
+    keyboard_input: Res<Input<KeyCode>>,
+    replay_mode: Res<ReplayMode>,
+    mut engaged: ResMut<AutopilotEngaged>,
+) {
+    if let ReplayMode::Playback { .. } = *replay_mode {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::F1) {
+        engaged.0 = !engaged.0;
+    }
+}
+
+/// Keeps `Autopilot` on Player 1's head exactly while it is engaged, heads
+/// spawned since included.
+pub fn engage_autopilot(
+    mut commands: Commands,
+    engaged: Res<AutopilotEngaged>,
+    heads: Query<(Entity, &Player, Option<&Autopilot>)>,
+) {
+    for (head, player, autopilot) in heads.iter() {
+        match (*player == Player::One && engaged.0, autopilot.is_some()) {
+            (true, false) => {
+                commands.insert_one(head, Autopilot);
+            }
+            (false, true) => {
+                commands.remove_one::<Autopilot>(head);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Hands Player 2's snake to the computer in `GameMode::VersusBot`, every
+/// time it spawns.
+pub fn enlist_bots(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    heads: Query<Without<Bot, (Entity, &Player)>>,
+) {
+    if *mode != GameMode::VersusBot {
+        return;
+    }
+    for (head, player) in heads.iter() {
+        if *player == Player::Two {
+            commands.insert_one(head, Bot::default());
+        }
+    }
+}
+
+/// The way a snake heading `heading` should turn to get closer to `target`:
+/// of the directions `cell` finds a free cell in, the one that ends up
+/// closest, going straight on when that is as close. Never reverses, and
+/// carries on straight when there is no free cell at all.
+pub fn greedy_direction(
+    heading: Direction,
+    target: Option<Position>,
+    cell: impl Fn(Direction) -> Option<Position>,
+) -> Direction {
+    let distance = |p: Position| target.map_or(0, |t| (p.x - t.x).abs() + (p.y - t.y).abs());
+    Direction::ALL
+        .iter()
+        .copied()
+        .filter(|direction| *direction != heading.opposite())
+        .filter_map(|direction| cell(direction).map(|next| (direction, next)))
+        .min_by_key(|(direction, next)| (distance(*next), *direction != heading))
+        .map_or(heading, |(direction, _)| direction)
+}
+
+/// Turns every bot and autopiloted head towards the nearest food each move
+/// tick, along the shortest route around walls and both snakes' bodies, or
+/// greedily when the food is cut off. Bots remember the food they are after.
+#[allow(clippy::type_complexity)]
+pub fn bot_steering(
+    (bounds, rules, obstacles): (Res<SafeBounds>, Res<GameRules>, Res<Obstacles>),
+    snakes: Query<(&Position, &SnakeSegments)>,
+    food: Query<With<Food, &Position>>,
+    mut steered: Query<(
+        Option<&mut Bot>,
+        Option<&Autopilot>,
+        &mut SnakeHead,
+        &Position,
+    )>,
+) {
+    let blocked: HashSet<Position> = snakes
+        .iter()
+        .flat_map(|(head, segments)| segments.positions.iter().chain(Some(head)))
+        .chain(&obstacles.0)
+        .copied()
+        .collect();
+    for (bot, autopilot, mut head, position) in steered.iter_mut() {
+        if bot.is_none() && autopilot.is_none() {
+            continue;
+        }
+        let target = food
+            .iter()
+            .copied()
+            .min_by_key(|food| (food.x - position.x).abs() + (food.y - position.y).abs());
+        if let Some(mut bot) = bot {
+            bot.target = target;
+        }
+        let grid = Grid {
+            bounds: &bounds,
+            wrap: rules.wrap_around,
+            blocked: &blocked,
+        };
+        let routed = target
+            .and_then(|target| find_path(&grid, *position, target))
+            .and_then(|path| direction_to(&grid, *position, *path.first()?));
+        head.try_direction = routed.unwrap_or_else(|| {
+            greedy_direction(head.direction, target, |direction| {
+                grid.step(*position, direction)
+            })
+        });
+        head.queued_turns.clear();
+    }
+}
+
+/// Pays `STEAL_BONUS` whenever the player eats the food a bot was heading
+/// for, with its own popup, and sends the bot looking for another.
+pub fn steal_bonus(
+    (mut reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    mut score: ResMut<Score>,
+    mut score_events: ResMut<Events<ScoreEvent>>,
+    mut bots: Query<&mut Bot>,
+) {
+    for growth in reader.iter(&growth_events) {
+        if bots.get_mut(growth.snake).is_ok() {
+            continue;
+        }
+        for mut bot in bots.iter_mut() {
+            if bot.target == Some(growth.position) {
+                bot.target = None;
+                score.0 += STEAL_BONUS;
+                score_events.send(ScoreEvent {
+                    points: STEAL_BONUS,
+                    position: growth.position,
+                });
+            }
+        }
+    }
+}
+
+/// Steers computer-driven snakes and the autopilot.
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<AutopilotEngaged>()
+            .add_step_system(snake_stage::STEER, bot_steering.system())
+            .add_system(toggle_autopilot.system())
+            .add_system(engage_autopilot.system())
+            .add_system(enlist_bots.system())
+            .add_system(steal_bonus.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FoodType;
+
+    #[test]
+    fn bots_head_for_the_target_around_what_blocks_them() {
+        let head = Position { x: 5, y: 5 };
+        let target = Some(Position { x: 5, y: 9 });
+        let open = |direction: Direction| Some(direction.step(head));
+        assert_eq!(
+            greedy_direction(Direction::Right, target, open),
+            Direction::Up
+        );
+        // Equally close either way, so it carries on.
+        let level = Some(Position { x: 5, y: 5 });
+        assert_eq!(
+            greedy_direction(Direction::Left, level, open),
+            Direction::Left
+        );
+        // Never straight back into its neck, even towards the target.
+        assert_eq!(
+            greedy_direction(Direction::Up, Some(Position { x: 5, y: 0 }), open),
+            Direction::Up
+        );
+        let above_blocked = |direction: Direction| {
+            Some(direction.step(head)).filter(|next| *next != Position { x: 5, y: 6 })
+        };
+        assert_eq!(
+            greedy_direction(Direction::Up, target, above_blocked),
+            Direction::Left
+        );
+        assert_eq!(
+            greedy_direction(Direction::Up, target, |_| None),
+            Direction::Up
+        );
+    }
+
+    #[test]
+    fn eating_a_bots_food_pays_a_bonus() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Score>()
+            .add_event::<GrowthEvent>()
+            .add_event::<ScoreEvent>()
+            .add_system(steal_bonus.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let food = Position { x: 3, y: 4 };
+        let bot = app.world.spawn((Bot { target: Some(food) },));
+        let player = app.world.spawn((Player::One,));
+        app.executor.initialize(&mut app.resources);
+        let eat = |app: &mut App, snake| {
+            app.resources
+                .get_mut::<Events<GrowthEvent>>()
+                .unwrap()
+                .send(GrowthEvent {
+                    food: FoodType::Normal,
+                    amount: 1,
+                    position: food,
+                    snake,
+                });
+            app.update();
+            app.resources.get::<Score>().unwrap().0
+        };
+
+        assert_eq!(eat(&mut app, bot), 0);
+        assert_eq!(eat(&mut app, player), STEAL_BONUS);
+        assert_eq!(app.world.get::<Bot>(bot).unwrap().target, None);
+        assert_eq!(eat(&mut app, player), STEAL_BONUS);
+    }
+
+    #[test]
+    fn f1_hands_player_one_to_the_autopilot_and_back() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<ReplayMode>()
+            .init_resource::<AutopilotEngaged>()
+            .add_system(toggle_autopilot.system())
+            .add_system(engage_autopilot.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let one = app.world.spawn((Player::One,));
+        let two = app.world.spawn((Player::Two,));
+        app.executor.initialize(&mut app.resources);
+        let press_f1 = |app: &mut App| {
+            let mut keyboard_input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            keyboard_input.release(KeyCode::F1);
+            keyboard_input.press(KeyCode::F1);
+            drop(keyboard_input);
+            app.update();
+            app.resources.get_mut::<Input<KeyCode>>().unwrap().update();
+            (
+                app.world.get::<Autopilot>(one).is_ok(),
+                app.world.get::<Autopilot>(two).is_ok(),
+            )
+        };
+
+        assert_eq!(press_f1(&mut app), (true, false));
+        let respawned = app.world.spawn((Player::One,));
+        app.update();
+        assert!(app.world.get::<Autopilot>(respawned).is_ok());
+        assert_eq!(press_f1(&mut app), (false, false));
+        app.update();
+        assert!(app.world.get::<Autopilot>(respawned).is_err());
+    }
+}
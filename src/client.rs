@@ -0,0 +1,372 @@
+//! The thin client of the dedicated server (see `server`): it sends the
+//! player's turns and draws the frames the server sends back, and runs none
+//! of the game itself. The drawing is shared with games against a peer (see
+//! `rollback`), which draw the frames their own simulation gives.
+
+use crate::bindings::{Action, KeyBindings};
+use crate::env::Cell;
+use crate::render::{cell_size, position_translation, size_scaling, CellSize};
+use crate::server::{direction_name, Frame};
+use crate::settings::Settings;
+use crate::theme::Themes;
+use crate::{Arena, Direction, Position, Size, Theme};
+use bevy::prelude::*;
+use bevy::render::pass::ClearColor;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What the server has sent that has not been drawn yet.
+#[derive(Default)]
+struct Inbox {
+    frame: Option<Frame>,
+    closed: bool,
+}
+
+/// The link to the server: turns go out on the stream, and a thread of its
+/// own reads the frames into the inbox.
+pub struct Connection {
+    stream: TcpStream,
+    inbox: Arc<Mutex<Inbox>>,
+    /// The player the last frame was for; 0 until one comes, and for
+    /// spectators.
+    player: u8,
+}
+
+impl Connection {
+    /// Joins the server at `address`, e.g. `example.org:7777`.
+    pub fn open(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let inbox = Arc::new(Mutex::new(Inbox::default()));
+        let received = inbox.clone();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                match line.map(|line| serde_json::from_str::<Frame>(&line)) {
+                    Ok(Ok(frame)) => received.lock().unwrap().frame = Some(frame),
+                    Ok(Err(err)) => eprintln!("could not read frame: {}", err),
+                    Err(_) => break,
+                }
+            }
+            received.lock().unwrap().closed = true;
+        });
+        Ok(Self {
+            stream,
+            inbox,
+            player: 0,
+        })
+    }
+
+    fn turn(&mut self, direction: Direction) -> io::Result<()> {
+        writeln!(self.stream, "{}", direction_name(direction))
+    }
+}
+
+/// The newest frame to draw, if one came in since the last was drawn, and
+/// whether the game it comes from has gone away.
+#[derive(Default)]
+pub struct NextFrame {
+    pub frame: Option<Frame>,
+    pub closed: bool,
+}
+
+/// A cell of the arena, by its index in `Frame::cells`, drawn in the color
+/// of whatever the last frame had there.
+pub struct CellSprite(usize);
+
+pub struct StatusText;
+
+/// Colors of the cells the server sends, by their `Cell` number.
+#[derive(Default)]
+pub struct CellMaterials(Vec<Handle<ColorMaterial>>);
+
+fn client_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands
+        .spawn(Camera2dComponents::default())
+        .spawn(UiCameraComponents::default())
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: "Waiting for players".to_string(),
+                font: asset_server.load("fonts/DejaVuSans-Bold.ttf"),
+                style: TextStyle {
+                    font_size: 32.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(StatusText);
+    let colors = [
+        theme.background(),
+        Color::rgb(0.55, 0.15, 0.1),
+        theme.head(),
+        theme.segment(),
+        Color::rgb(1.0, 0.55, 0.1),
+        theme.food(),
+        Color::rgb(0.3, 0.8, 1.0),
+        Color::rgb(0.2, 0.4, 1.0),
+        Color::rgb(1.0, 0.1, 0.1),
+    ];
+    commands.insert_resource(CellMaterials(
+        colors
+            .iter()
+            .map(|color| materials.add((*color).into()))
+            .collect(),
+    ));
+}
+
+/// The ways the turn keys pressed this frame turn the snake.
+pub fn turns_pressed(keyboard_input: &Input<KeyCode>, bindings: &KeyBindings) -> Vec<Direction> {
+    let turns = [
+        (Action::TurnLeft, Direction::Left),
+        (Action::TurnDown, Direction::Down),
+        (Action::TurnUp, Direction::Up),
+        (Action::TurnRight, Direction::Right),
+    ];
+    turns
+        .iter()
+        .filter(|(action, _)| bindings.just_pressed(keyboard_input, *action))
+        .map(|(_, direction)| *direction)
+        .collect()
+}
+
+/// Sends the server every turn the player presses, unless they are only
+/// watching.
+pub fn send_turns(
+    keyboard_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut connection: ResMut<Connection>,
+) {
+    if connection.player == 0 {
+        return;
+    }
+    for direction in turns_pressed(&keyboard_input, &bindings) {
+        if let Err(err) = connection.turn(direction) {
+            eprintln!("could not send turn: {}", err);
+        }
+    }
+}
+
+/// Hands the newest frame from the server to `draw_frame`.
+pub fn receive_frames(mut connection: ResMut<Connection>, mut next: ResMut<NextFrame>) {
+    let (frame, closed) = {
+        let mut inbox = connection.inbox.lock().unwrap();
+        (inbox.frame.take(), inbox.closed)
+    };
+    if let Some(frame) = frame {
+        connection.player = frame.player;
+        next.frame = Some(frame);
+    }
+    next.closed = closed;
+}
+
+/// Redraws the arena from the `NextFrame`, if there is one, and says how the
+/// run stands. Every cell has a sprite of its own, spawned when the first
+/// frame comes and again only when the arena changes size; the frames after
+/// that just recolor them, and hide the empty ones.
+pub fn draw_frame(
+    mut commands: Commands,
+    mut next: ResMut<NextFrame>,
+    materials: Res<CellMaterials>,
+    mut arena: ResMut<Arena>,
+    mut sprites: Query<(Entity, &CellSprite, &mut Handle<ColorMaterial>, &mut Draw)>,
+    mut status: Query<With<StatusText, &mut Text>>,
+) {
+    let (frame, closed) = (next.frame.take(), next.closed);
+    if let Some(frame) = &frame {
+        let cells = frame.cells();
+        let resized = (arena.width, arena.height) != (frame.width, frame.height);
+        if resized || sprites.iter_mut().next().is_none() {
+            *arena = Arena {
+                width: frame.width,
+                height: frame.height,
+            };
+            for (entity, _, _, _) in sprites.iter_mut() {
+                commands.despawn(entity);
+            }
+            for (index, cell) in cells.into_iter().enumerate() {
+                commands
+                    .spawn(SpriteComponents {
+                        material: materials.0[cell as usize].clone(),
+                        sprite: Sprite::new(Vec2::new(10.0, 10.0)),
+                        draw: Draw {
+                            is_visible: cell != Cell::Empty,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .with(CellSprite(index))
+                    .with(Position {
+                        x: index as i32 % frame.width as i32,
+                        y: index as i32 / frame.width as i32,
+                    })
+                    .with(Size::square(0.9));
+            }
+        } else {
+            for (_, sprite, mut material, mut draw) in sprites.iter_mut() {
+                let cell = cells.get(sprite.0).copied().unwrap_or(Cell::Empty);
+                if *material != materials.0[cell as usize] {
+                    *material = materials.0[cell as usize].clone();
+                }
+                let visible = cell != Cell::Empty;
+                if draw.is_visible != visible {
+                    draw.is_visible = visible;
+                }
+            }
+        }
+    }
+    for mut text in status.iter_mut() {
+        let value = match &frame {
+            _ if closed => "Disconnected".to_string(),
+            Some(frame) => {
+                let mut value = match frame.player {
+                    0 => format!("Watching  Score {}", frame.score),
+                    player => format!("Player {}  Score {}", player, frame.score),
+                };
+                if frame.rival_score > 0 {
+                    value += &format!("  Rival {}", frame.rival_score);
+                }
+                if frame.over {
+                    value += "  Game over";
+                }
+                value
+            }
+            None => continue,
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// Draws each `NextFrame` that is handed to it, in the saved theme, and
+/// loads the key bindings to steer with.
+pub struct FrameViewPlugin;
+
+impl Plugin for FrameViewPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let settings = Settings::path()
+            .map(|path| Settings::load(&path))
+            .unwrap_or_default();
+        let bindings = KeyBindings::path()
+            .map(|path| KeyBindings::load(&path))
+            .unwrap_or_default();
+        let theme = Themes::new(&settings.theme).current().clone();
+        app.add_resource(ClearColor(theme.letterbox()))
+            .add_resource(theme)
+            .add_resource(bindings)
+            .init_resource::<Arena>()
+            .init_resource::<CellSize>()
+            .init_resource::<NextFrame>()
+            .add_startup_system(client_setup.system())
+            .add_system(draw_frame.system())
+            .add_system_to_stage(stage::POST_UPDATE, cell_size.system())
+            .add_system_to_stage(stage::POST_UPDATE, position_translation.system())
+            .add_system_to_stage(stage::POST_UPDATE, size_scaling.system());
+    }
+}
+
+/// Plays on the dedicated server through the `Connection` resource, which
+/// must be added first, in place of the `SnakePlugin`.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(FrameViewPlugin)
+            .add_system_to_stage(stage::PRE_UPDATE, receive_frames.system())
+            .add_system(send_turns.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// The cell sprites are spawned with the first frame and recolored by
+    /// the frames after it, until the arena changes size.
+    #[test]
+    fn frames_recolor_the_cells_they_spawned() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connection = Connection::open(&listener.local_addr().unwrap().to_string()).unwrap();
+        let mut server = listener.accept().unwrap().0;
+        let mut builder = App::build();
+        builder
+            .add_resource(connection)
+            .add_resource(CellMaterials(vec![Handle::default(); 9]))
+            .init_resource::<Arena>()
+            .init_resource::<NextFrame>()
+            .add_system_to_stage(stage::PRE_UPDATE, receive_frames.system())
+            .add_system(draw_frame.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let mut draw = |app: &mut App, width: u32, cells: &str| {
+            let frame = Frame {
+                player: 1,
+                width,
+                height: cells.len() as u32 / width,
+                cells: cells.to_string(),
+                score: 0,
+                rival_score: 0,
+                over: false,
+            };
+            writeln!(server, "{}", serde_json::to_string(&frame).unwrap()).unwrap();
+            while app
+                .resources
+                .get::<Connection>()
+                .unwrap()
+                .inbox
+                .lock()
+                .unwrap()
+                .frame
+                .is_none()
+            {
+                thread::sleep(std::time::Duration::from_millis(5));
+            }
+            app.update();
+            let mut sprites: Vec<(Entity, usize, bool)> = app
+                .world
+                .query::<(Entity, &CellSprite, &Draw)>()
+                .map(|(entity, sprite, draw)| (entity, sprite.0, draw.is_visible))
+                .collect();
+            sprites.sort_by_key(|(_, index, _)| *index);
+            sprites
+        };
+
+        let first = draw(&mut app, 2, "2300");
+        assert_eq!(first.len(), 4);
+        assert_eq!(
+            first
+                .iter()
+                .map(|(_, _, visible)| *visible)
+                .collect::<Vec<_>>(),
+            [true, true, false, false]
+        );
+        let second = draw(&mut app, 2, "0235");
+        assert_eq!(
+            second,
+            first
+                .iter()
+                .zip(&[false, true, true, true])
+                .map(|((entity, index, _), visible)| (*entity, *index, *visible))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(draw(&mut app, 3, "000000200").len(), 9);
+    }
+}
@@ -0,0 +1,143 @@
+//! The daily challenge: one seed per UTC day, so everyone who picks Daily
+//! on the menu that day gets the same food in the same places, and the best
+//! daily score kept apart from the leaderboard.
+
+use crate::{AppState, ReplayMode, Score};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Days since the Unix epoch, in UTC.
+pub fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() / 86_400)
+        .unwrap_or_default()
+}
+
+/// The seed of `day`'s challenge. Spelled out rather than hashed with the
+/// standard library, whose hasher may change between Rust releases.
+pub fn daily_seed(day: u64) -> u64 {
+    // SplitMix64's finalizer.
+    let mut z = day.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The day whose challenge is being played, if the runs are daily ones.
+/// Picking Daily on the menu sets it and Play clears it.
+#[derive(Default)]
+pub struct DailyChallenge(pub Option<u64>);
+
+/// Best score of one day's challenge, kept on disk.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DailyBest {
+    pub day: u64,
+    pub score: u32,
+}
+
+impl DailyBest {
+    /// `daily.json` in the platform data directory, next to the leaderboard.
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("bevy-snake").join("daily.json"))
+    }
+
+    /// Reads the daily best at `path`; a missing or corrupt file gives none.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// The best score of `day`'s challenge so far, if it has been played.
+    pub fn on(&self, day: u64) -> Option<u32> {
+        Some(self.score).filter(|_| self.day == day)
+    }
+
+    /// Keeps `score` if it is the first or best of `day`. Returns whether it
+    /// was kept.
+    pub fn record(&mut self, day: u64, score: u32) -> bool {
+        if self.on(day).is_some_and(|best| best >= score) {
+            return false;
+        }
+        *self = Self { day, score };
+        true
+    }
+}
+
+/// Records the score of every daily run that ends, saving a new best.
+pub fn daily_best(
+    state: Res<AppState>,
+    mut state_before: Local<AppState>,
+    (daily, replay_mode, score): (Res<DailyChallenge>, Res<ReplayMode>, Res<Score>),
+    mut best: ResMut<DailyBest>,
+) {
+    let before = std::mem::replace(&mut *state_before, *state);
+    if before == AppState::GameOver || *state != AppState::GameOver {
+        return;
+    }
+    let day = match (daily.0, &*replay_mode) {
+        (Some(day), ReplayMode::Record(_)) => day,
+        _ => return,
+    };
+    if best.record(day, score.0) {
+        if let Some(path) = DailyBest::path() {
+            if let Err(err) = best.save(&path) {
+                eprintln!("could not save daily best {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Keeps track of the daily challenge and its best score.
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<DailyChallenge>()
+            .add_resource(
+                DailyBest::path()
+                    .map(|path| DailyBest::load(&path))
+                    .unwrap_or_default(),
+            )
+            .add_system(daily_best.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_day_has_its_own_fixed_seed() {
+        assert_eq!(daily_seed(20_000), daily_seed(20_000));
+        assert_ne!(daily_seed(20_000), daily_seed(20_001));
+        // 2024-01-01 or later.
+        assert!(today() >= 19_723);
+    }
+
+    #[test]
+    fn the_daily_best_starts_over_each_day() {
+        let mut best = DailyBest::default();
+        assert_eq!(best.on(5), None);
+        assert!(best.record(5, 30));
+        assert!(!best.record(5, 30));
+        assert!(!best.record(5, 12));
+        assert!(best.record(5, 31));
+        assert_eq!(best.on(5), Some(31));
+        assert!(best.record(6, 4));
+        assert_eq!((best.on(5), best.on(6)), (None, Some(4)));
+    }
+}
@@ -0,0 +1,244 @@
+//! A reinforcement-learning environment over the headless game, in the
+//! shape Gym made familiar: `reset` starts an episode, `step` plays one move
+//! and reports what the agent sees, what it earned and whether the episode
+//! is over.
+
+use crate::food::Food;
+use crate::headless::Simulation;
+use crate::options::Options;
+use crate::powerup::PowerUp;
+use crate::*;
+
+/// Reward for losing a life, on top of whatever the move scored.
+pub const DEATH_REWARD: f32 = -10.0;
+
+/// What the agent sees on a cell. The discriminants are the observation's
+/// numeric encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Cell {
+    Empty = 0,
+    /// An interior wall, or arena the shrinking edge has closed off.
+    Wall = 1,
+    /// The agent's own head.
+    Head = 2,
+    /// The agent's own body.
+    Body = 3,
+    /// Any part of the other snake.
+    Rival = 4,
+    Food = 5,
+    PowerUp = 6,
+    Portal = 7,
+    Hunter = 8,
+}
+
+/// The whole arena as the agent sees it after a move.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row from the bottom of the arena, `width` cells to a row.
+    pub cells: Vec<Cell>,
+    /// The way the agent's snake is heading; steering straight back is
+    /// ignored.
+    pub heading: Direction,
+}
+
+impl Cell {
+    /// The cell a byte of `Observation::encode` stands for.
+    pub fn decode(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0 => Self::Empty,
+            1 => Self::Wall,
+            2 => Self::Head,
+            3 => Self::Body,
+            4 => Self::Rival,
+            5 => Self::Food,
+            6 => Self::PowerUp,
+            7 => Self::Portal,
+            8 => Self::Hunter,
+            _ => return None,
+        })
+    }
+}
+
+impl Observation {
+    /// The cell `x` columns from the left and `y` rows from the bottom.
+    pub fn cell(&self, x: u32, y: u32) -> Cell {
+        self.cells[(y * self.width + x) as usize]
+    }
+
+    /// The cells as bytes, in the same order.
+    pub fn encode(&self) -> Vec<u8> {
+        self.cells.iter().map(|cell| *cell as u8).collect()
+    }
+}
+
+/// The game as an environment for an agent steering Player 1. Rewards are
+/// the points a move scores, plus `DEATH_REWARD` for each life it costs, and
+/// an episode is one run, lost or won.
+pub struct SnakeEnv {
+    simulation: Simulation,
+    seed: u64,
+    episodes: u64,
+}
+
+impl SnakeEnv {
+    /// An environment playing the game `options` describe. Episodes are
+    /// seeded one after another from `--seed`, or from a random seed.
+    pub fn new(options: &Options) -> Self {
+        let seed = options.seed.unwrap_or_else(rand::random);
+        Self {
+            simulation: Simulation::new(options, seed),
+            seed,
+            episodes: 0,
+        }
+    }
+
+    /// Starts the next episode and returns its first observation.
+    pub fn reset(&mut self) -> Observation {
+        self.simulation.reset(self.seed.wrapping_add(self.episodes));
+        self.episodes += 1;
+        self.observe()
+    }
+
+    /// Steers towards `action` and plays one move.
+    pub fn step(&mut self, action: Direction) -> (Observation, f32, bool) {
+        if self.simulation.game_over() {
+            return (self.observe(), 0.0, true);
+        }
+        let (score, deaths) = (self.simulation.score(), self.simulation.deaths());
+        self.simulation.steer(action);
+        let running = self.simulation.step();
+        let reward = self.simulation.score() as f32 - score as f32
+            + (self.simulation.deaths() - deaths) as f32 * DEATH_REWARD;
+        (self.observe(), reward, !running)
+    }
+
+    /// What the agent sees right now.
+    pub fn observe(&self) -> Observation {
+        observe(&self.simulation, Player::One)
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+}
+
+/// The arena as `player` sees it: their own snake as `Head` and `Body`, the
+/// other as `Rival`.
+pub fn observe(simulation: &Simulation, player: Player) -> Observation {
+    observe_world(simulation.world(), simulation.resources(), player)
+}
+
+/// `observe` for a game running in any app, windowed or not.
+pub fn observe_world(world: &World, resources: &Resources, player: Player) -> Observation {
+    let arena = *resources.get::<Arena>().unwrap();
+    let bounds = resources.get::<SafeBounds>().unwrap();
+    let mut observation = Observation {
+        width: arena.width,
+        height: arena.height,
+        cells: vec![Cell::Empty; (arena.width * arena.height) as usize],
+        heading: Direction::Up,
+    };
+    let mut mark = |position: &Position, cell| {
+        if arena.contains(position) {
+            observation.cells[(position.y as u32 * arena.width + position.x as u32) as usize] =
+                cell;
+        }
+    };
+    for y in 0..arena.height as i32 {
+        for x in 0..arena.width as i32 {
+            let position = Position { x, y };
+            if !bounds.contains(&position) {
+                mark(&position, Cell::Wall);
+            }
+        }
+    }
+    for (_, position) in world.query::<(&Wall, &Position)>() {
+        mark(position, Cell::Wall);
+    }
+    for (_, position) in world.query::<(&Portal, &Position)>() {
+        mark(position, Cell::Portal);
+    }
+    for (_, position) in world.query::<(&Food, &Position)>() {
+        mark(position, Cell::Food);
+    }
+    for (_, position) in world.query::<(&PowerUp, &Position)>() {
+        mark(position, Cell::PowerUp);
+    }
+    for (_, position) in world.query::<(&Hunter, &Position)>() {
+        mark(position, Cell::Hunter);
+    }
+    let mut heading = None;
+    for (owner, head, segments, position) in
+        world.query::<(&Player, &SnakeHead, &SnakeSegments, &Position)>()
+    {
+        let (head_cell, body_cell) = if *owner == player {
+            heading = Some(head.direction);
+            (Cell::Head, Cell::Body)
+        } else {
+            (Cell::Rival, Cell::Rival)
+        };
+        for segment in &segments.positions {
+            mark(segment, body_cell);
+        }
+        mark(position, head_cell);
+    }
+    observation.heading = heading.unwrap_or(observation.heading);
+    observation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn episodes_observe_and_reward_the_game() {
+        let options = Options {
+            seed: Some(5),
+            ..Default::default()
+        };
+        let mut env = SnakeEnv::new(&options);
+        let first = env.reset();
+        let (x, y) = (SNAKE_START.x as u32, SNAKE_START.y as u32);
+        assert_eq!(first.cell(x, y), Cell::Head);
+        assert_eq!(first.cell(x, y - 1), Cell::Body);
+        assert_eq!(first.heading, Direction::Up);
+        assert_eq!(first.encode()[(y * first.width + x) as usize], 2);
+        assert_eq!(
+            first
+                .cells
+                .iter()
+                .filter(|cell| **cell == Cell::Head)
+                .count(),
+            1
+        );
+
+        let (moved, reward, done) = env.step(Direction::Left);
+        assert_eq!(moved.cell(x - 1, y), Cell::Head);
+        assert_eq!(moved.cell(x, y), Cell::Body);
+        assert_eq!(moved.heading, Direction::Left);
+        assert!(!done);
+        assert!(reward >= 0.0);
+
+        // Straight on into the left edge.
+        for _ in 1..x {
+            assert_eq!(env.step(Direction::Left).1, 0.0);
+        }
+        let (_, reward, done) = env.step(Direction::Left);
+        assert_eq!(reward, DEATH_REWARD);
+        assert!(!done);
+
+        // Losing every life ends the episode, and the next one is seeded
+        // differently.
+        let mut done = false;
+        while !done {
+            done = env.step(Direction::Left).2;
+        }
+        assert_eq!(env.step(Direction::Up), (env.observe(), 0.0, true));
+        let second = env.reset();
+        assert_eq!(second.cell(x, y), Cell::Head);
+        assert_ne!(second, first);
+    }
+}
@@ -0,0 +1,492 @@
+//! Food, power-up pickups and how they spawn and wander.
+
+use crate::powerup::{spawn_power_up, ActiveEffects, PowerUp};
+use crate::{
+    hunter_step, snake_stage, AddSnakeStep, AppState, Arena, Countdown, Difficulty, GameClock,
+    GameRng, GhostSnake, GrowthEvent, Materials, Position, RunTick, SafeBounds, Size, SnakeHead,
+};
+use bevy::prelude::*;
+use rand::prelude::{Rng, SliceRandom, StdRng};
+use serde::Deserialize;
+use std::collections::HashSet;
+
+pub const MOBILE_FOOD_CHANCE: f32 = 0.25;
+pub const FOOD_WANDER_TICKS: u32 = 2;
+pub const MIN_FOOD_SPAWN_INTERVAL: f32 = 2.0;
+pub const GHOST_PICKUP_CHANCE: f32 = 0.05;
+pub const SLOW_MOTION_PICKUP_CHANCE: f32 = 0.08;
+pub const SPEED_BOOST_PICKUP_CHANCE: f32 = 0.08;
+pub const SHIELD_PICKUP_CHANCE: f32 = 0.04;
+pub const SHRINK_POTION_CHANCE: f32 = 0.03;
+pub const SCORE_MULTIPLIER_CHANCE: f32 = 0.04;
+pub const FOOD_MAGNET_CHANCE: f32 = 0.04;
+/// Furthest a food can be from the head, in moves, for the magnet to pull it.
+pub const FOOD_MAGNET_RANGE: i32 = 3;
+pub const FOOD_POINTS: u32 = 10;
+pub const DOUBLE_FOOD_GROWTH: i32 = 2;
+pub const DOUBLE_FOOD_POINTS: u32 = 2 * FOOD_POINTS;
+pub const GOLDEN_FOOD_GROWTH: i32 = 3;
+pub const GOLDEN_FOOD_POINTS: u32 = 10 * FOOD_POINTS;
+pub const GOLDEN_FOOD_LIFETIME: f32 = 6.0;
+pub const POISON_FOOD_SHRINK: i32 = 2;
+pub const FOOD_LIFETIME: f32 = 15.0;
+pub const FOOD_BLINK_DURATION: f32 = 3.0;
+pub const FOOD_BLINK: f32 = 0.15;
+pub const FOOD_SIZE: f32 = 0.8;
+pub const FOOD_TIMER_BAR_HEIGHT: f32 = 0.12;
+
+#[derive(Clone)]
+pub struct Food;
+
+/// Game time a food has left before it disappears uneaten.
+#[derive(Clone)]
+pub struct FoodLifetime(pub Timer);
+
+impl Default for FoodLifetime {
+    fn default() -> Self {
+        Self(Timer::from_seconds(FOOD_LIFETIME, false))
+    }
+}
+
+impl FoodLifetime {
+    /// Share of the lifetime still to go, from 1.0 when spawned to 0.0.
+    pub fn left(&self) -> f32 {
+        1.0 - self.0.elapsed / self.0.duration
+    }
+}
+
+/// Shows how long the golden food it is a child of has left.
+#[derive(Clone)]
+pub struct FoodTimerBar;
+
+/// What a `Food` does when eaten.
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize)]
+pub enum FoodType {
+    Normal,
+    /// As common a sight as poison, grows the snake by two segments and
+    /// scores double.
+    Double,
+    /// Rare and short-lived, grows the snake by several segments and scores
+    /// big.
+    Golden,
+    /// Shrinks the snake and scores nothing.
+    Poison,
+}
+
+impl FoodType {
+    /// Segments gained when eaten; negative for segments lost.
+    pub fn growth(self) -> i32 {
+        match self {
+            Self::Normal => 1,
+            Self::Double => DOUBLE_FOOD_GROWTH,
+            Self::Golden => GOLDEN_FOOD_GROWTH,
+            Self::Poison => -POISON_FOOD_SHRINK,
+        }
+    }
+
+    /// Points scored when eaten, before the combo multiplier.
+    pub fn points(self) -> u32 {
+        match self {
+            Self::Normal => FOOD_POINTS,
+            Self::Double => DOUBLE_FOOD_POINTS,
+            Self::Golden => GOLDEN_FOOD_POINTS,
+            Self::Poison => 0,
+        }
+    }
+
+    /// Seconds it stays on the board uneaten.
+    pub fn lifetime(self) -> f32 {
+        match self {
+            Self::Golden => GOLDEN_FOOD_LIFETIME,
+            Self::Normal | Self::Double | Self::Poison => FOOD_LIFETIME,
+        }
+    }
+
+    pub fn material(self, materials: &Materials) -> Handle<ColorMaterial> {
+        match self {
+            Self::Normal => materials.food_material.clone(),
+            Self::Double => materials.double_food_material.clone(),
+            Self::Golden => materials.golden_food_material.clone(),
+            Self::Poison => materials.poison_food_material.clone(),
+        }
+    }
+}
+
+/// Relative spawn weights of each `FoodType`.
+pub struct FoodTable(pub Vec<(FoodType, u32)>);
+
+impl FoodTable {
+    /// Picks a food type with probability proportional to its weight, falling
+    /// back to `FoodType::Normal` when every weight is zero.
+    pub fn pick(&self, rng: &mut impl Rng) -> FoodType {
+        let total: u32 = self.0.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return FoodType::Normal;
+        }
+        let mut roll = rng.gen_range(0, total);
+        for (food, weight) in &self.0 {
+            if roll < *weight {
+                return *food;
+            }
+            roll -= weight;
+        }
+        unreachable!("roll is below the total weight")
+    }
+}
+
+impl Default for FoodTable {
+    fn default() -> Self {
+        Self(vec![
+            (FoodType::Normal, 75),
+            (FoodType::Double, 10),
+            (FoodType::Golden, 5),
+            (FoodType::Poison, 10),
+        ])
+    }
+}
+
+/// Marks food that wanders to a neighbouring cell every few move ticks.
+#[derive(Clone)]
+pub struct Mobile;
+
+/// Proportion of spawned food that is `Mobile`, between 0.0 and 1.0.
+pub struct MobileFoodChance(pub f32);
+
+impl Default for MobileFoodChance {
+    fn default() -> Self {
+        Self(MOBILE_FOOD_CHANCE)
+    }
+}
+
+#[derive(Clone)]
+pub struct FoodSpawnTimer(pub Timer);
+
+impl Default for FoodSpawnTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(
+            Difficulty::default().food_spawn_interval(),
+            true,
+        ))
+    }
+}
+
+pub fn spawn_food(
+    commands: &mut Commands,
+    materials: &Materials,
+    food: FoodType,
+    position: Position,
+    mobile: bool,
+) {
+    commands
+        .spawn(SpriteComponents {
+            material: food.material(materials),
+            ..Default::default()
+        })
+        .with(Food)
+        .with(FoodLifetime(Timer::from_seconds(food.lifetime(), false)))
+        .with(food)
+        .with(position)
+        .with(Size::square(FOOD_SIZE));
+    if food == FoodType::Golden {
+        commands.with_children(|parent| {
+            parent
+                .spawn(SpriteComponents {
+                    material: materials.golden_food_material.clone(),
+                    ..Default::default()
+                })
+                .with(FoodTimerBar)
+                .with(Size {
+                    width: FOOD_SIZE,
+                    height: FOOD_TIMER_BAR_HEIGHT,
+                });
+        });
+    }
+    if mobile {
+        commands.with(Mobile);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn food_spawner(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    (mobile_chance, food_table): (Res<MobileFoodChance>, Res<FoodTable>),
+    arena: Res<Arena>,
+    growth_events: Res<Events<GrowthEvent>>,
+    mut growth_reader: Local<EventReader<GrowthEvent>>,
+    (clock, countdown, state): (Res<GameClock>, Res<Countdown>, Res<AppState>),
+    (mut timer, mut rng, difficulty): (ResMut<FoodSpawnTimer>, ResMut<GameRng>, Res<Difficulty>),
+    positions: Query<Without<GhostSnake, &Position>>,
+) {
+    if *state != AppState::Playing {
+        return;
+    }
+    let spawn_due = if countdown.active() {
+        false
+    } else {
+        timer.0.tick(clock.delta_seconds);
+        timer.0.finished
+    };
+    // Every food eaten is replaced, even several in one tick.
+    let eaten = growth_reader.iter(&growth_events).count();
+    for _ in 0..eaten {
+        timer.0.duration =
+            (timer.0.duration - difficulty.food_spawn_shrink()).max(MIN_FOOD_SPAWN_INTERVAL);
+    }
+    let spawns = eaten + spawn_due as usize;
+    let mut occupied: HashSet<Position> = if spawns > 0 {
+        positions.iter().copied().collect()
+    } else {
+        HashSet::new()
+    };
+    let rng = &mut rng.0;
+    let mut next_free_cell = |rng: &mut StdRng| {
+        let cell = arena.random_free_cell(&occupied, rng);
+        occupied.extend(cell);
+        cell
+    };
+    for _ in 0..spawns {
+        if let Some(position) = next_free_cell(rng) {
+            let food = food_table.pick(rng);
+            let mobile = rng.gen::<f32>() < mobile_chance.0;
+            spawn_food(&mut commands, &materials, food, position, mobile);
+        }
+        for power_up in &PowerUp::ALL {
+            if rng.gen::<f32>() < power_up.spawn_chance() {
+                if let Some(position) = next_free_cell(rng) {
+                    spawn_power_up(&mut commands, &materials, *power_up, position);
+                }
+            }
+        }
+    }
+}
+
+/// Shuffles every `Mobile` food one cell in a random direction every
+/// `FOOD_WANDER_TICKS` move ticks. Runs after `snake_eating`, and never steps
+/// onto an occupied cell (head included), so a wandering food can only ever
+/// be eaten by the head moving onto it.
+pub fn food_wandering(
+    arena: Res<Arena>,
+    run_tick: Res<RunTick>,
+    mut rng: ResMut<GameRng>,
+    mobile_food: Query<With<Mobile, Entity>>,
+    mut positions: Query<Without<GhostSnake, &mut Position>>,
+) {
+    if !run_tick.0.is_multiple_of(FOOD_WANDER_TICKS) {
+        return;
+    }
+    let mut occupied: HashSet<Position> = positions.iter_mut().map(|p| *p).collect();
+    for ent in mobile_food.iter() {
+        let mut pos = positions.get_mut(ent).unwrap();
+        let free: Vec<Position> = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .iter()
+            .map(|(dx, dy)| Position {
+                x: pos.x + dx,
+                y: pos.y + dy,
+            })
+            .filter(|p| arena.contains(p) && !occupied.contains(p))
+            .collect();
+        let next = match free.choose(&mut rng.0) {
+            Some(next) => *next,
+            None => continue,
+        };
+        occupied.remove(&pos);
+        occupied.insert(next);
+        *pos = next;
+    }
+}
+
+/// While the food magnet runs, steps every food within `FOOD_MAGNET_RANGE`
+/// moves of the head one cell towards it each move tick. Like wandering food
+/// it never steps onto an occupied cell, so the head still has to reach it.
+pub fn food_attraction(
+    (bounds, effects): (Res<SafeBounds>, Res<ActiveEffects>),
+    food: Query<With<Food, Entity>>,
+    heads: Query<With<SnakeHead, Entity>>,
+    mut positions: Query<Without<GhostSnake, &mut Position>>,
+) {
+    if !effects.active(PowerUp::FoodMagnet) {
+        return;
+    }
+    let head = match heads.iter().next() {
+        Some(head) => *positions.get_mut(head).unwrap(),
+        None => return,
+    };
+    let mut occupied: HashSet<Position> = positions.iter_mut().map(|p| *p).collect();
+    for ent in food.iter() {
+        let mut pos = positions.get_mut(ent).unwrap();
+        if (pos.x - head.x).abs() + (pos.y - head.y).abs() > FOOD_MAGNET_RANGE {
+            continue;
+        }
+        let next = hunter_step(*pos, head, &bounds, &occupied);
+        occupied.remove(&pos);
+        occupied.insert(next);
+        *pos = next;
+    }
+}
+
+/// Ages uneaten food on the game clock, blinking it through its last
+/// `FOOD_BLINK_DURATION` seconds and removing it once its lifetime is up.
+pub fn food_lifetime(
+    mut commands: Commands,
+    (clock, countdown): (Res<GameClock>, Res<Countdown>),
+    mut food: Query<(Entity, &mut FoodLifetime, &mut Draw)>,
+) {
+    if countdown.active() {
+        return;
+    }
+    for (ent, mut lifetime, mut draw) in food.iter_mut() {
+        lifetime.0.tick(clock.delta_seconds);
+        if lifetime.0.finished {
+            commands.despawn_recursive(ent);
+            continue;
+        }
+        let blinking_for = lifetime.0.elapsed - (lifetime.0.duration - FOOD_BLINK_DURATION);
+        let visible = blinking_for < 0.0 || (blinking_for / (2.0 * FOOD_BLINK)).fract() >= 0.5;
+        if draw.is_visible != visible {
+            draw.is_visible = visible;
+        }
+    }
+}
+
+/// Spawns food and pickups, lets mobile food wander and removes food left
+/// uneaten too long.
+pub struct FoodPlugin;
+
+impl Plugin for FoodPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MobileFoodChance>()
+            .init_resource::<FoodTable>()
+            .init_resource::<FoodSpawnTimer>()
+            .add_step_system(snake_stage::GROWTH, food_wandering.system())
+            .add_step_system(snake_stage::GROWTH, food_attraction.system())
+            .add_system(food_spawner.system())
+            .add_system(food_lifetime.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expired_timer;
+    use rand::SeedableRng;
+
+    #[test]
+    fn food_types_are_picked_by_weight() {
+        let table = FoodTable(vec![
+            (FoodType::Normal, 6),
+            (FoodType::Double, 1),
+            (FoodType::Golden, 0),
+            (FoodType::Poison, 2),
+        ]);
+        let mut rng = StdRng::seed_from_u64(7);
+        let picks: Vec<FoodType> = (0..8000).map(|_| table.pick(&mut rng)).collect();
+        let count = |food| picks.iter().filter(|&&pick| pick == food).count();
+        assert_eq!(count(FoodType::Golden), 0);
+        assert!((5000..5600).contains(&count(FoodType::Normal)));
+        assert!((700..1100).contains(&count(FoodType::Double)));
+        assert!((1500..2050).contains(&count(FoodType::Poison)));
+        assert_eq!(FoodTable(vec![]).pick(&mut rng), FoodType::Normal);
+    }
+
+    #[test]
+    fn the_magnet_pulls_nearby_food_towards_the_head() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<SafeBounds>()
+            .init_resource::<ActiveEffects>()
+            .add_system(food_attraction.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.world.spawn((
+            SnakeHead {
+                direction: crate::Direction::Up,
+                try_direction: crate::Direction::Up,
+                queued_turns: Default::default(),
+            },
+            Position { x: 5, y: 5 },
+        ));
+        let food: Vec<Entity> = [(5, 8), (7, 6), (5, 6), (5, 9), (9, 5)]
+            .iter()
+            .map(|&(x, y)| app.world.spawn((Food, Position { x, y })))
+            .collect();
+        app.executor.initialize(&mut app.resources);
+        let cells = |app: &App| -> Vec<(i32, i32)> {
+            food.iter()
+                .map(|&ent| {
+                    let pos = app.world.get::<Position>(ent).unwrap();
+                    (pos.x, pos.y)
+                })
+                .collect()
+        };
+
+        app.update();
+        assert_eq!(cells(&app), [(5, 8), (7, 6), (5, 6), (5, 9), (9, 5)]);
+
+        app.resources
+            .get_mut::<ActiveEffects>()
+            .unwrap()
+            .grant(PowerUp::FoodMagnet);
+        app.update();
+        // Food right next to the head stays put rather than step onto it.
+        assert_eq!(cells(&app), [(5, 7), (6, 6), (5, 6), (5, 9), (9, 5)]);
+    }
+
+    #[test]
+    fn uneaten_food_blinks_then_disappears() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<GameClock>()
+            .add_resource(Countdown(expired_timer(0.0)))
+            .add_system(food_lifetime.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let food = app.world.spawn((FoodLifetime::default(), Draw::default()));
+        app.executor.initialize(&mut app.resources);
+        let advance = |app: &mut App, seconds| {
+            app.resources.get_mut::<GameClock>().unwrap().delta_seconds = seconds;
+            app.update();
+            app.world.get::<Draw>(food).map(|draw| draw.is_visible).ok()
+        };
+
+        assert_eq!(
+            advance(&mut app, FOOD_LIFETIME - FOOD_BLINK_DURATION - 1.0),
+            Some(true)
+        );
+        assert_eq!(advance(&mut app, 1.0 + FOOD_BLINK * 0.5), Some(false));
+        assert_eq!(advance(&mut app, FOOD_BLINK), Some(true));
+        assert_eq!(advance(&mut app, FOOD_BLINK_DURATION), None);
+    }
+
+    #[test]
+    fn golden_food_carries_a_timer_bar() {
+        fn spawn(mut commands: Commands, materials: Res<Materials>) {
+            for (food, x) in &[(FoodType::Normal, 1), (FoodType::Golden, 2)] {
+                spawn_food(
+                    &mut commands,
+                    &materials,
+                    *food,
+                    Position { x: *x, y: 0 },
+                    false,
+                );
+            }
+        }
+        let mut builder = App::build();
+        builder
+            .init_resource::<Materials>()
+            .add_startup_system(spawn.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.initialize();
+        let bars: Vec<Entity> = app
+            .world
+            .query::<With<FoodTimerBar, &Parent>>()
+            .map(|parent| parent.0)
+            .collect();
+        assert_eq!(bars.len(), 1);
+        assert_eq!(
+            *app.world.get::<FoodType>(bars[0]).unwrap(),
+            FoodType::Golden
+        );
+        let lifetime = app.world.get::<FoodLifetime>(bars[0]).unwrap();
+        assert_eq!(lifetime.0.duration, GOLDEN_FOOD_LIFETIME);
+        assert_eq!(lifetime.left(), 1.0);
+    }
+}
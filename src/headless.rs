@@ -0,0 +1,630 @@
+//! The game without a window, sprites or input devices, for CI and for
+//! training snake-playing programs. A `Simulation` is built on
+//! `MinimalPlugins` and steps one move at a time on a game clock it drives
+//! itself, so a seeded run plays out the same on every machine and as fast
+//! as the CPU allows.
+//!
+//! Levels are loaded through the asset server, which is not running here,
+//! so `--level` is ignored and the built-in layout is played.
+//!
+//! A `Simulation` can also be saved to a `Snapshot` and put back to it later,
+//! which is what rolling back a networked game takes.
+
+use crate::bot::{Autopilot, AutopilotEngaged, Bot, BotPlugin};
+use crate::daily::DailyChallenge;
+use crate::food::{Food, FoodLifetime, FoodPlugin, FoodSpawnTimer, FoodTimerBar, FoodType, Mobile};
+use crate::options::Options;
+use crate::powerup::{ActiveEffects, ActiveMultiplier, PowerUp, PowerUpEvent};
+use crate::*;
+use bevy::ecs::EntityBuilder;
+use bevy::render::pipeline::RenderPipelines;
+use bevy::render::render_graph::base::MainPass;
+use bevy::sprite::SpriteResizeMode;
+use std::collections::HashMap;
+
+/// Frames a single `Simulation::step` may take before giving up on the
+/// snake moving, e.g. when no snake is left to move.
+const MAX_FRAMES_PER_STEP: usize = 64;
+
+/// Moves after which `run` calls a game off, in case the autopilot circles
+/// forever without dying.
+const MAX_TICKS_PER_GAME: u32 = 100_000;
+
+/// Registers the gameplay systems and the resources they need, and none of
+/// the menus, text or rendering. Add it next to `MinimalPlugins`, after an
+/// `Options` resource if the command line should configure it.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let options = app
+            .resources()
+            .get::<Options>()
+            .map(|options| (*options).clone())
+            .unwrap_or_default();
+        let arena = options.arena.unwrap_or_default();
+        let layout = ArenaLayout::from_options(&options);
+        let (start, portals, obstacles) = layout.fit(&arena);
+        app.add_resource(ReplayMode::Record(None))
+            .add_resource(AppState::Playing)
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<BestRun>()
+            .add_resource(NextDifficulty::from_options(
+                &options,
+                Difficulty::default(),
+            ))
+            .init_resource::<Difficulty>()
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(
+                Difficulty::default().move_interval(),
+                true,
+            )))
+            .init_resource::<BaseMoveInterval>()
+            .add_resource(portals)
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .init_resource::<Boost>()
+            .init_resource::<Score>()
+            .init_resource::<RivalScores>()
+            .add_resource(VersusPlayers::from_options(&options))
+            .init_resource::<Combo>()
+            .init_resource::<Lives>()
+            .init_resource::<Invulnerable>()
+            .add_resource(arena)
+            .add_resource(SafeBounds::full(&arena))
+            .init_resource::<ShrinkTimer>()
+            .init_resource::<RunStats>()
+            .init_resource::<Countdown>()
+            .init_resource::<GameClock>()
+            .init_resource::<NameEntry>()
+            .init_resource::<Materials>()
+            .add_resource(GameMode::from_options(&options))
+            .add_resource(GameRules::from_options(&options))
+            .add_resource(obstacles)
+            .add_resource(SpeedUp::from_options(&options))
+            .add_resource(RunOverrides::from_options(&options))
+            .add_resource(start)
+            .add_resource(layout)
+            .init_resource::<RoundTimer>()
+            .add_resource(GameRng::from_options(&options))
+            .init_resource::<RunTick>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<Deaths>()
+            .init_resource::<DailyChallenge>()
+            .init_resource::<Profiles>()
+            .add_event::<GrowthEvent>()
+            .add_event::<ScoreEvent>()
+            .add_event::<GameOverEvent>()
+            .add_event::<VictoryEvent>()
+            .add_event::<ShrinkEvent>()
+            .add_event::<PowerUpEvent>()
+            .add_event::<EndRunEvent>()
+            .add_event::<RunStartEvent>()
+            .add_startup_system(game_setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, skip_countdown.system())
+            .add_snake_step()
+            .add_plugin(BotPlugin)
+            .add_plugin(FoodPlugin)
+            .add_system(victory.system())
+            .add_system(scoring.system())
+            .add_system(invulnerability.system())
+            .add_system(round_timer.system())
+            .add_system(run_clock.system())
+            .add_system(count_deaths.system())
+            .add_system(game_over.system())
+            .add_system(start_run.system())
+            .add_system(shrink_arena.system())
+            .add_system(hunter_spawner.system());
+    }
+}
+
+/// Lives Player 1 has lost in the current run.
+#[derive(Default, Clone)]
+struct Deaths(u32);
+
+fn count_deaths(
+    (mut reader, game_over_events): (
+        Local<EventReader<GameOverEvent>>,
+        Res<Events<GameOverEvent>>,
+    ),
+    mut deaths: ResMut<Deaths>,
+) {
+    for death in reader.iter(&game_over_events) {
+        if death.player == Player::One {
+            deaths.0 += 1;
+        }
+    }
+}
+
+/// Nobody is watching the 3, 2, 1, so every countdown ends as it starts.
+fn skip_countdown(mut countdown: ResMut<Countdown>) {
+    if countdown.active() {
+        *countdown = Countdown(expired_timer(COUNTDOWN_DURATION));
+    }
+}
+
+/// A game running without a window, advanced a move at a time.
+pub struct Simulation {
+    app: Box<App>,
+}
+
+impl Simulation {
+    /// Builds the game `options` describe and starts a run seeded with
+    /// `seed`.
+    pub fn new(options: &Options, seed: u64) -> Self {
+        let mut builder = App::build();
+        builder
+            .add_resource(options.clone())
+            .add_plugins(MinimalPlugins)
+            .add_plugin(HeadlessPlugin);
+        // Initialized systems hold on to the world's entity allocator by
+        // address, so the app is boxed before they are, never to move again.
+        let mut app = Box::new(std::mem::take(&mut builder.app));
+        app.initialize();
+        let mut simulation = Self { app };
+        simulation.reset(seed);
+        simulation
+    }
+
+    /// Throws the current run away and starts a fresh one seeded with
+    /// `seed`.
+    pub fn reset(&mut self, seed: u64) {
+        self.app.resources.get_mut::<RunOverrides>().unwrap().seed = Some(seed);
+        *self.app.resources.get_mut::<Deaths>().unwrap() = Deaths::default();
+        self.app
+            .resources
+            .get_mut::<Events<EndRunEvent>>()
+            .unwrap()
+            .send(EndRunEvent::Restart);
+        // One frame restarts the run, the next seeds it and lays out the food.
+        for _ in 0..2 {
+            self.frame(0.0);
+        }
+    }
+
+    /// Plays until the snakes' next move, or the run ends. Returns whether
+    /// the run is still going.
+    pub fn step(&mut self) -> bool {
+        let tick = self.app.resources.get::<RunTick>().unwrap().0;
+        for _ in 0..MAX_FRAMES_PER_STEP {
+            if self.game_over() {
+                break;
+            }
+            // Exactly what is left of the move interval, so effects, combos and
+            // spawn timers see the same time pass as in a windowed game.
+            let remaining = {
+                let timer = &self.app.resources.get::<SnakeMoveTimer>().unwrap().0;
+                (timer.duration - timer.elapsed).max(f32::EPSILON)
+            };
+            self.frame(remaining);
+            if self.app.resources.get::<RunTick>().unwrap().0 != tick {
+                break;
+            }
+        }
+        !self.game_over()
+    }
+
+    /// Turns Player 1's snake towards `direction` on its next move, unless
+    /// that would reverse it into its neck.
+    pub fn steer(&mut self, direction: Direction) {
+        self.steer_player(Player::One, direction);
+    }
+
+    /// Turns `player`'s snake towards `direction` on its next move, unless
+    /// that would reverse it into its neck.
+    pub fn steer_player(&mut self, player: Player, direction: Direction) {
+        for (steered, mut head) in self.app.world.query_mut::<(&Player, &mut SnakeHead)>() {
+            if *steered == player {
+                head.try_direction = direction;
+                head.queued_turns.clear();
+            }
+        }
+    }
+
+    /// Hands Player 1's snake to the autopilot, or takes it back.
+    pub fn autopilot(&mut self, engaged: bool) {
+        self.app.resources.get_mut::<AutopilotEngaged>().unwrap().0 = engaged;
+    }
+
+    /// Whether the current run has ended, lost or won.
+    pub fn game_over(&self) -> bool {
+        *self.app.resources.get::<AppState>().unwrap() == AppState::GameOver
+    }
+
+    pub fn score(&self) -> u32 {
+        self.app.resources.get::<Score>().unwrap().0
+    }
+
+    /// Player 2's score in versus.
+    pub fn rival_score(&self) -> u32 {
+        self.player_score(Player::Two)
+    }
+
+    /// `player`'s score; outside versus every snake scores for Player 1.
+    pub fn player_score(&self, player: Player) -> u32 {
+        let resources = &self.app.resources;
+        versus_score(
+            player,
+            &resources.get::<Score>().unwrap(),
+            &resources.get::<RivalScores>().unwrap(),
+        )
+    }
+
+    /// Seconds between the snakes' moves right now, for playing the run in
+    /// real time.
+    pub fn move_interval(&self) -> f32 {
+        self.app
+            .resources
+            .get::<SnakeMoveTimer>()
+            .unwrap()
+            .0
+            .duration
+    }
+
+    /// Player 1's length in cells, head included.
+    pub fn length(&self) -> usize {
+        player_one_length(self.app.world.query::<(&Player, &SnakeSegments)>())
+    }
+
+    /// Lives Player 1 has lost since the current run started, the one that
+    /// ended it included.
+    pub fn deaths(&self) -> u32 {
+        self.app.resources.get::<Deaths>().unwrap().0
+    }
+
+    /// Moves made since the current run started.
+    pub fn ticks(&self) -> u32 {
+        self.app.resources.get::<RunTick>().unwrap().0
+    }
+
+    /// The world the run is played in, for reading what is where.
+    pub fn world(&self) -> &World {
+        &self.app.world
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.app.resources
+    }
+
+    /// Saves everything the run's next moves depend on.
+    pub fn snapshot(&self) -> Snapshot {
+        let world = &self.app.world;
+        Snapshot {
+            entities: saved_entities(world)
+                .into_iter()
+                .map(|entity| {
+                    let components = SAVED_COMPONENTS
+                        .iter()
+                        .filter_map(|save| save(world, entity))
+                        .collect();
+                    (entity, components)
+                })
+                .collect(),
+            resources: SAVED_RESOURCES
+                .iter()
+                .map(|save| save(&self.app.resources))
+                .collect(),
+        }
+    }
+
+    /// Puts the run back the way it was when `snapshot` was taken. The
+    /// entities are spawned afresh, in the order they were saved in so that
+    /// queries visit them in the same order as before.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let world = &mut self.app.world;
+        for entity in saved_entities(world) {
+            world.despawn(entity).unwrap();
+        }
+        let respawned: HashMap<Entity, Entity> = snapshot
+            .entities
+            .iter()
+            .map(|(saved, _)| (*saved, world.reserve_entity()))
+            .collect();
+        let mut builder = EntityBuilder::new();
+        for (saved, components) in &snapshot.entities {
+            for add in components {
+                add(&mut builder);
+            }
+            world.insert(respawned[saved], builder.build()).unwrap();
+        }
+        let remap = |entity: &mut Entity| {
+            if let Some(respawned) = respawned.get(entity) {
+                *entity = *respawned;
+            }
+        };
+        for mut segments in world.query_mut::<&mut SnakeSegments>() {
+            segments.entities.iter_mut().for_each(remap);
+        }
+        for mut parent in world.query_mut::<&mut Parent>() {
+            remap(&mut parent.0);
+        }
+        for mut parent in world.query_mut::<&mut PreviousParent>() {
+            remap(&mut parent.0);
+        }
+        for mut children in world.query_mut::<&mut Children>() {
+            children.0.iter_mut().for_each(remap);
+        }
+        for restore in &snapshot.resources {
+            restore(&mut self.app.resources);
+        }
+    }
+
+    fn frame(&mut self, delta_seconds: f32) {
+        self.app
+            .resources
+            .get_mut::<GameClock>()
+            .unwrap()
+            .delta_seconds = delta_seconds;
+        self.app.update();
+    }
+}
+
+/// A component copied out of the world, which adds a copy of itself to an
+/// entity being respawned.
+type SavedComponent = Box<dyn Fn(&mut EntityBuilder)>;
+
+/// A resource copied out of the app, which puts a copy of itself back.
+type SavedResource = Box<dyn Fn(&mut Resources)>;
+
+/// The state of a `Simulation` at one moment, for `Simulation::restore`.
+pub struct Snapshot {
+    entities: Vec<(Entity, Vec<SavedComponent>)>,
+    resources: Vec<SavedResource>,
+}
+
+/// The entities of the run: everything on the grid and whatever hangs off
+/// it, such as a golden food's timer bar.
+fn saved_entities(world: &World) -> Vec<Entity> {
+    let mut entities: Vec<Entity> = world
+        .query::<(Entity, &Position)>()
+        .map(|(entity, _)| entity)
+        .collect();
+    let children: Vec<Entity> = world
+        .query::<(Entity, &Parent)>()
+        .filter(|(_, parent)| entities.contains(&parent.0))
+        .map(|(entity, _)| entity)
+        .collect();
+    entities.extend(children);
+    entities
+}
+
+fn save<T: Component + Clone>(world: &World, entity: Entity) -> Option<SavedComponent> {
+    let component = (*world.get::<T>(entity).ok()?).clone();
+    Some(Box::new(move |builder| {
+        builder.add(component.clone());
+    }))
+}
+
+/// `Sprite` is not `Clone`, but its two fields are all there is to it.
+fn save_sprite(world: &World, entity: Entity) -> Option<SavedComponent> {
+    let sprite = world.get::<Sprite>(entity).ok()?;
+    let (size, manual) = (
+        sprite.size,
+        matches!(sprite.resize_mode, SpriteResizeMode::Manual),
+    );
+    Some(Box::new(move |builder| {
+        builder.add(Sprite {
+            size,
+            resize_mode: if manual {
+                SpriteResizeMode::Manual
+            } else {
+                SpriteResizeMode::Automatic
+            },
+        });
+    }))
+}
+
+/// Only whether an entity is drawn is kept; the draw commands are rebuilt
+/// every frame anyway.
+fn save_draw(world: &World, entity: Entity) -> Option<SavedComponent> {
+    let is_visible = world.get::<Draw>(entity).ok()?.is_visible;
+    Some(Box::new(move |builder| {
+        builder.add(Draw {
+            is_visible,
+            ..Default::default()
+        });
+    }))
+}
+
+fn save_main_pass(world: &World, entity: Entity) -> Option<SavedComponent> {
+    world.get::<MainPass>(entity).ok()?;
+    Some(Box::new(|builder| {
+        builder.add(MainPass);
+    }))
+}
+
+/// Every component the run's entities are made of. One missing here is
+/// silently dropped on restore.
+const SAVED_COMPONENTS: &[fn(&World, Entity) -> Option<SavedComponent>] = &[
+    save::<Position>,
+    save::<Size>,
+    save::<Player>,
+    save::<SnakeHead>,
+    save::<SnakeSegments>,
+    save::<SnakeSegment>,
+    save::<Hunger>,
+    save::<Bot>,
+    save::<Autopilot>,
+    save::<Food>,
+    save::<FoodType>,
+    save::<FoodLifetime>,
+    save::<FoodTimerBar>,
+    save::<Mobile>,
+    save::<PowerUp>,
+    save::<Hunter>,
+    save::<Wall>,
+    save::<Obstacle>,
+    save::<Portal>,
+    save::<GhostSnake>,
+    save::<Parent>,
+    save::<PreviousParent>,
+    save::<Children>,
+    save_sprite,
+    save::<Handle<Mesh>>,
+    save::<Handle<ColorMaterial>>,
+    save_main_pass,
+    save_draw,
+    save::<RenderPipelines>,
+    save::<Transform>,
+    save::<GlobalTransform>,
+];
+
+fn save_resource<T: Resource + Clone>(resources: &Resources) -> SavedResource {
+    let resource = (*resources.get::<T>().unwrap()).clone();
+    Box::new(move |resources| *resources.get_mut::<T>().unwrap() = resource.clone())
+}
+
+/// Every resource the gameplay systems change as a run goes on.
+const SAVED_RESOURCES: &[fn(&Resources) -> SavedResource] = &[
+    save_resource::<AppState>,
+    save_resource::<Difficulty>,
+    save_resource::<SnakeMoveTimer>,
+    save_resource::<BaseMoveInterval>,
+    save_resource::<ActiveEffects>,
+    save_resource::<ActiveMultiplier>,
+    save_resource::<Boost>,
+    save_resource::<Score>,
+    save_resource::<RivalScores>,
+    save_resource::<Combo>,
+    save_resource::<Lives>,
+    save_resource::<Invulnerable>,
+    save_resource::<SafeBounds>,
+    save_resource::<ShrinkTimer>,
+    save_resource::<RunStats>,
+    save_resource::<Countdown>,
+    save_resource::<Obstacles>,
+    save_resource::<RoundTimer>,
+    save_resource::<GameRng>,
+    save_resource::<RunTick>,
+    save_resource::<ReplayRecorder>,
+    save_resource::<FoodSpawnTimer>,
+    save_resource::<Deaths>,
+];
+
+/// Plays `games` runs of the game `options` describe on the autopilot, as
+/// fast as they go, printing each result and a summary.
+pub fn run(options: &Options, games: u32) {
+    let seed = options.seed.unwrap_or_else(rand::random);
+    let started = std::time::Instant::now();
+    let mut simulation = Simulation::new(options, seed);
+    simulation.autopilot(true);
+    let mut total_score = 0;
+    for game in 0..games {
+        if game > 0 {
+            simulation.reset(seed.wrapping_add(game as u64));
+        }
+        while simulation.step() && simulation.ticks() < MAX_TICKS_PER_GAME {}
+        total_score += simulation.score();
+        println!(
+            "game {}: score {}, {} moves",
+            game + 1,
+            simulation.score(),
+            simulation.ticks()
+        );
+    }
+    let seconds = started.elapsed().as_secs_f32();
+    println!(
+        "{} games in {:.2}s ({:.0} games/s), average score {:.1}",
+        games,
+        seconds,
+        games as f32 / seconds.max(f32::EPSILON),
+        total_score as f32 / games.max(1) as f32
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_runs_play_out_the_same() {
+        let options = Options {
+            arena: Some(Arena {
+                width: 12,
+                height: 12,
+            }),
+            ..Default::default()
+        };
+        let play = |seed| {
+            let mut simulation = Simulation::new(&options, seed);
+            simulation.autopilot(true);
+            let mut moves = 0;
+            while simulation.step() && moves < 300 {
+                moves += 1;
+            }
+            (
+                simulation.score(),
+                simulation.ticks(),
+                simulation.game_over(),
+            )
+        };
+
+        let (score, ticks, _) = play(7);
+        assert!(score > 0);
+        assert!(ticks > 0);
+        assert_eq!(play(7), play(7));
+    }
+
+    #[test]
+    fn every_step_is_one_move() {
+        let mut simulation = Simulation::new(&Options::default(), 1);
+        assert_eq!(simulation.ticks(), 0);
+        let length = simulation.length();
+        assert!(length > 1);
+        simulation.steer(Direction::Right);
+        for tick in 1..=3 {
+            assert!(simulation.step());
+            assert_eq!(simulation.ticks(), tick);
+        }
+        // Straight back is ignored rather than fatal.
+        simulation.steer(Direction::Left);
+        assert!(simulation.step());
+        assert!(!simulation.game_over());
+        assert_eq!(simulation.length(), length);
+    }
+
+    /// What is where, and the score.
+    fn state(simulation: &Simulation) -> (u32, u32, Vec<(Position, bool)>) {
+        let mut cells: Vec<(Position, bool)> = simulation
+            .world()
+            .query::<(&Position, Option<&Food>)>()
+            .map(|(position, food)| (*position, food.is_some()))
+            .collect();
+        cells.sort_by_key(|(position, food)| (position.x, position.y, *food));
+        (simulation.score(), simulation.ticks(), cells)
+    }
+
+    #[test]
+    fn restoring_a_snapshot_replays_the_same_moves() {
+        let options = Options {
+            arena: Some(Arena {
+                width: 12,
+                height: 12,
+            }),
+            ..Default::default()
+        };
+        let mut simulation = Simulation::new(&options, 3);
+        simulation.autopilot(true);
+        for _ in 0..20 {
+            simulation.step();
+        }
+        let snapshot = simulation.snapshot();
+        let play = |simulation: &mut Simulation| {
+            (0..60)
+                .map(|_| {
+                    simulation.step();
+                    state(simulation)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = play(&mut simulation);
+        assert!(first.last().unwrap().0 > first[0].0);
+        simulation.restore(&snapshot);
+        assert_eq!(simulation.ticks(), 20);
+        assert_eq!(play(&mut simulation), first);
+        // A snapshot can be gone back to more than once.
+        simulation.restore(&snapshot);
+        assert_eq!(play(&mut simulation), first);
+    }
+}
@@ -0,0 +1,204 @@
+//! Steering the snake from the keyboard or a replay.
+
+use crate::bindings::{Action, KeyBindings};
+use crate::bot::{Autopilot, Bot};
+use crate::{
+    snake_stage, AddSnakeStep, AppState, Direction, GameMode, NameEntry, Player, ReplayMode,
+    RunTick, SnakeHead,
+};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// How far an analog stick must be pushed before it steers.
+pub const STICK_DEADZONE: f32 = 0.5;
+
+/// Controllers currently plugged in.
+#[derive(Default)]
+pub struct Gamepads(HashSet<Gamepad>);
+
+/// Keeps `Gamepads` up to date as controllers are plugged in and out.
+pub fn connect_gamepads(
+    mut reader: Local<EventReader<GamepadEvent>>,
+    gamepad_events: Res<Events<GamepadEvent>>,
+    mut gamepads: ResMut<Gamepads>,
+) {
+    for GamepadEvent(gamepad, event) in reader.iter(&gamepad_events) {
+        match event {
+            GamepadEventType::Connected => {
+                gamepads.0.insert(*gamepad);
+            }
+            GamepadEventType::Disconnected => {
+                gamepads.0.remove(gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The direction a stick pushed to (`x`, `y`) points in, along whichever axis
+/// it is pushed further, or `None` inside the deadzone.
+pub fn stick_direction(x: f32, y: f32) -> Option<Direction> {
+    if x.abs().max(y.abs()) < STICK_DEADZONE {
+        None
+    } else if x.abs() > y.abs() {
+        Some(if x < 0.0 {
+            Direction::Left
+        } else {
+            Direction::Right
+        })
+    } else {
+        Some(if y < 0.0 {
+            Direction::Down
+        } else {
+            Direction::Up
+        })
+    }
+}
+
+/// The key that turns `player` towards `direction` in two-player modes, where
+/// the keyboard is split: WASD for Player 1 and the arrow keys for Player 2.
+/// Players 3 and 4 only play on the dedicated server, and have no keys.
+pub fn split_keyboard_key(player: Player, direction: Direction) -> Option<KeyCode> {
+    let key = match (player, direction) {
+        (Player::One, Direction::Up) => KeyCode::W,
+        (Player::One, Direction::Left) => KeyCode::A,
+        (Player::One, Direction::Down) => KeyCode::S,
+        (Player::One, Direction::Right) => KeyCode::D,
+        (Player::Two, Direction::Up) => KeyCode::Up,
+        (Player::Two, Direction::Left) => KeyCode::Left,
+        (Player::Two, Direction::Down) => KeyCode::Down,
+        (Player::Two, Direction::Right) => KeyCode::Right,
+        (Player::Three, _) | (Player::Four, _) => return None,
+    };
+    Some(key)
+}
+
+/// Steers the snake from the turn keys, the arrow keys and WASD by default, or
+/// the d-pad or left stick of any connected controller. In two-player modes
+/// each player has their half of the keyboard from `split_keyboard_key`
+/// instead, and controllers steer Player 1. Heads the computer steers, bots
+/// and the autopilot, are left alone. Presses that come faster than the snake moves are queued on
+/// its head and made one move at a time.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn handle_movement(
+    (keyboard_input, bindings): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    (gamepads, buttons, axes): (
+        Res<Gamepads>,
+        Res<Input<GamepadButton>>,
+        Res<Axis<GamepadAxis>>,
+    ),
+    (replay_mode, state): (Res<ReplayMode>, Res<AppState>),
+    name_entry: Res<NameEntry>,
+    mode: Res<GameMode>,
+    mut heads: Query<Without<Bot, Without<Autopilot, (&Player, &mut SnakeHead)>>>,
+) {
+    if let ReplayMode::Playback { .. } = *replay_mode {
+        return;
+    }
+    if *state != AppState::Playing || name_entry.active() {
+        return;
+    }
+    let controls = [
+        (
+            Direction::Left,
+            Action::TurnLeft,
+            GamepadButtonType::DPadLeft,
+        ),
+        (
+            Direction::Down,
+            Action::TurnDown,
+            GamepadButtonType::DPadDown,
+        ),
+        (Direction::Up, Action::TurnUp, GamepadButtonType::DPadUp),
+        (
+            Direction::Right,
+            Action::TurnRight,
+            GamepadButtonType::DPadRight,
+        ),
+    ];
+    let axis = |gamepad, axis_type| axes.get(GamepadAxis(gamepad, axis_type)).unwrap_or(0.0);
+    // Some controllers report their d-pad as a pair of axes instead of buttons.
+    let sticks: Vec<Direction> = gamepads
+        .0
+        .iter()
+        .flat_map(|gamepad| {
+            vec![
+                stick_direction(
+                    axis(*gamepad, GamepadAxisType::LeftStickX),
+                    axis(*gamepad, GamepadAxisType::LeftStickY),
+                ),
+                stick_direction(
+                    axis(*gamepad, GamepadAxisType::DPadX),
+                    axis(*gamepad, GamepadAxisType::DPadY),
+                ),
+            ]
+        })
+        .flatten()
+        .collect();
+    for (player, mut head) in heads.iter_mut() {
+        for (direction, action, button) in &controls {
+            let keys = if mode.two_player() {
+                split_keyboard_key(*player, *direction)
+                    .is_some_and(|key| keyboard_input.pressed(key))
+            } else {
+                bindings.pressed(&keyboard_input, *action)
+            };
+            let held = keys
+                || *player == Player::One
+                    && (gamepads
+                        .0
+                        .iter()
+                        .any(|gamepad| buttons.pressed(GamepadButton(*gamepad, *button)))
+                        || sticks.contains(direction));
+            if held && head.steer(*direction) {
+                break;
+            }
+        }
+    }
+}
+
+/// Steers Player 1 from the replay being played back, in place of the
+/// keyboard.
+pub fn replay_input(
+    replay_mode: Res<ReplayMode>,
+    run_tick: Res<RunTick>,
+    mut heads: Query<(&Player, &mut SnakeHead)>,
+) {
+    if let ReplayMode::Playback { replay, .. } = &*replay_mode {
+        let input = replay.inputs.iter().find(|(tick, _)| *tick == run_tick.0);
+        if let Some((_, direction)) = input {
+            for (player, mut head) in heads.iter_mut() {
+                if *player == Player::One {
+                    head.try_direction = *direction;
+                }
+            }
+        }
+    }
+}
+
+/// Turns the snake's head, once per frame before it moves, and tracks which
+/// controllers are connected.
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Gamepads>()
+            .add_system_to_stage(stage::PRE_UPDATE, connect_gamepads.system())
+            .add_system_to_stage(snake_stage::TICK, handle_movement.system())
+            .add_step_system(snake_stage::STEER, replay_input.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sticks_steer_along_their_stronger_axis() {
+        assert_eq!(stick_direction(0.2, -0.4), None);
+        assert_eq!(stick_direction(-0.9, 0.3), Some(Direction::Left));
+        assert_eq!(stick_direction(0.6, -0.8), Some(Direction::Down));
+        assert_eq!(stick_direction(1.0, 0.0), Some(Direction::Right));
+        assert_eq!(stick_direction(0.0, STICK_DEADZONE), Some(Direction::Up));
+    }
+}
@@ -0,0 +1,225 @@
+//! Top scores kept on disk, per difficulty.
+
+use crate::Difficulty;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries kept per difficulty.
+pub const LEADERBOARD_SIZE: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub initials: String,
+    pub score: u32,
+    /// Length of the snake when the run ended; 0 for entries saved before
+    /// lengths were kept.
+    #[serde(default)]
+    pub length: usize,
+    pub difficulty: Difficulty,
+    /// Seconds since the Unix epoch when the entry was made.
+    pub timestamp: u64,
+    /// Profile that made the entry; empty for entries made before profiles
+    /// existed.
+    #[serde(default)]
+    pub player: String,
+}
+
+impl Entry {
+    pub fn new(initials: String, score: u32, length: usize, difficulty: Difficulty) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        Self {
+            initials,
+            score,
+            length,
+            difficulty,
+            timestamp,
+            player: String::new(),
+        }
+    }
+
+    /// The entry's date as `YYYY-MM-DD` (UTC).
+    pub fn date(&self) -> String {
+        // Howard Hinnant's days-to-civil algorithm.
+        let days = (self.timestamp / 86_400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// Entries of every difficulty, each difficulty's best first.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Leaderboard {
+    entries: Vec<Entry>,
+}
+
+impl Leaderboard {
+    /// `leaderboard.json` in the platform data directory, e.g.
+    /// `~/.local/share/bevy-snake` on Linux.
+    pub fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("bevy-snake").join("leaderboard.json"))
+    }
+
+    /// Reads the leaderboard at `path`; a missing or corrupt file gives an
+    /// empty one.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// The table for `difficulty`, best first.
+    pub fn top(&self, difficulty: Difficulty) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.difficulty == difficulty)
+    }
+
+    /// Whether `score` would make the table; ties do not push out an
+    /// existing entry.
+    pub fn qualifies(&self, score: u32, difficulty: Difficulty) -> bool {
+        let table: Vec<&Entry> = self.top(difficulty).collect();
+        table.len() < LEADERBOARD_SIZE || table.last().is_some_and(|last| score > last.score)
+    }
+
+    /// Adds `entry` below every entry of its difficulty with an equal or
+    /// higher score, so older ties stay ahead, and drops whatever falls off the
+    /// bottom. Returns the entry's 1-based rank if it made the table.
+    pub fn insert(&mut self, entry: Entry) -> Option<usize> {
+        let difficulty = entry.difficulty;
+        let rank = self
+            .top(difficulty)
+            .filter(|e| e.score >= entry.score)
+            .count()
+            + 1;
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.difficulty == difficulty && e.score < entry.score)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, entry);
+        let mut kept = 0;
+        self.entries.retain(|e| {
+            if e.difficulty != difficulty {
+                return true;
+            }
+            kept += 1;
+            kept <= LEADERBOARD_SIZE
+        });
+        Some(rank).filter(|rank| *rank <= LEADERBOARD_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(initials: &str, score: u32) -> Entry {
+        Entry {
+            initials: initials.to_string(),
+            score,
+            length: 0,
+            difficulty: Difficulty::Normal,
+            timestamp: 0,
+            player: String::new(),
+        }
+    }
+
+    #[test]
+    fn ties_keep_the_older_entry_higher() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.insert(entry("AAA", 50));
+        leaderboard.insert(entry("BBB", 70));
+        assert_eq!(leaderboard.insert(entry("CCC", 50)), Some(3));
+        let initials: Vec<&str> = leaderboard
+            .top(Difficulty::Normal)
+            .map(|e| e.initials.as_str())
+            .collect();
+        assert_eq!(initials, ["BBB", "AAA", "CCC"]);
+    }
+
+    #[test]
+    fn keeps_ten_entries_per_difficulty() {
+        let mut leaderboard = Leaderboard::default();
+        for score in 1..=LEADERBOARD_SIZE as u32 {
+            leaderboard.insert(entry("AAA", score * 10));
+        }
+        leaderboard.insert(Entry {
+            difficulty: Difficulty::Hard,
+            ..entry("HRD", 5)
+        });
+        assert!(!leaderboard.qualifies(10, Difficulty::Normal));
+        assert!(leaderboard.qualifies(11, Difficulty::Normal));
+        assert!(leaderboard.qualifies(0, Difficulty::Hard));
+        assert_eq!(leaderboard.insert(entry("ZZZ", 10)), None);
+        assert_eq!(leaderboard.insert(entry("NEW", 55)), Some(6));
+        assert_eq!(
+            leaderboard.top(Difficulty::Normal).count(),
+            LEADERBOARD_SIZE
+        );
+        assert_eq!(
+            leaderboard.top(Difficulty::Normal).last().unwrap().score,
+            20
+        );
+        assert_eq!(leaderboard.top(Difficulty::Hard).count(), 1);
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut leaderboard = Leaderboard::default();
+        leaderboard.insert(entry("ABC", 120));
+        let text = serde_json::to_string(&leaderboard).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Leaderboard>(&text).unwrap(),
+            leaderboard
+        );
+    }
+
+    #[test]
+    fn entries_without_a_length_still_load() {
+        let text =
+            r#"{"entries":[{"initials":"OLD","score":30,"difficulty":"Easy","timestamp":0}]}"#;
+        let leaderboard: Leaderboard = serde_json::from_str(text).unwrap();
+        let entry = leaderboard.top(Difficulty::Easy).next().unwrap();
+        assert_eq!((entry.score, entry.length), (30, 0));
+    }
+
+    #[test]
+    fn corrupt_files_load_empty() {
+        let path = std::env::temp_dir().join("bevy-snake-corrupt-leaderboard.json");
+        fs::write(&path, "{ not json").unwrap();
+        assert_eq!(Leaderboard::load(&path), Leaderboard::default());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Leaderboard::load(&path), Leaderboard::default());
+    }
+
+    #[test]
+    fn formats_dates() {
+        let entry = Entry {
+            timestamp: 1_602_720_000,
+            ..entry("ABC", 0)
+        };
+        assert_eq!(entry.date(), "2020-10-15");
+    }
+}
@@ -0,0 +1,272 @@
+//! Levels loaded from RON files in `assets/levels`, replacing the built-in
+//! arena, walls, portals and food table.
+
+use crate::food::{FoodTable, FoodType, MobileFoodChance};
+use crate::options::Options;
+use crate::{
+    spawn_layout, AppState, Arena, ArenaBackground, ArenaLayout, Materials, Obstacle, Obstacles,
+    Portal, Portals, Position, SafeBounds, Size, SnakeStart,
+};
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::type_registry::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+
+/// An arena layout, e.g.
+///
+/// ```ron
+/// (
+///     width: 24,
+///     height: 24,
+///     start: (x: 3, y: 3),
+///     walls: [((x: 11, y: 6), (x: 12, y: 17))],
+///     portals: [((x: 2, y: 21), (x: 21, y: 2))],
+///     food: Some([(Normal, 80), (Golden, 20)]),
+/// )
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "5d0c3a6e-8f21-4b7e-a5c9-2e41d7b09f63"]
+pub struct Level {
+    pub width: u32,
+    pub height: u32,
+    /// Cell the head starts each life on; the neck goes just below it.
+    pub start: Position,
+    /// Inclusive corners of each wall: a single cell, a line or a block.
+    #[serde(default)]
+    pub walls: Vec<(Position, Position)>,
+    #[serde(default)]
+    pub portals: Vec<(Position, Position)>,
+    /// Spawn weights of each food type; the usual table when left out.
+    #[serde(default)]
+    pub food: Option<Vec<(FoodType, u32)>>,
+    #[serde(default)]
+    pub mobile_food_chance: Option<f32>,
+}
+
+impl Level {
+    pub fn arena(&self) -> Arena {
+        Arena {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Every cell covered by a wall.
+    pub fn wall_cells(&self) -> Vec<Position> {
+        self.walls
+            .iter()
+            .flat_map(|(a, b)| {
+                let (ys_from, ys_to) = (a.y.min(b.y), a.y.max(b.y));
+                (a.x.min(b.x)..=a.x.max(b.x))
+                    .flat_map(move |x| (ys_from..=ys_to).map(move |y| Position { x, y }))
+            })
+            .collect()
+    }
+
+    /// Why the level cannot be played, if it cannot: everything must lie
+    /// inside the arena, and the snake must start on open cells.
+    pub fn check(&self) -> Result<(), String> {
+        let arena = self.arena();
+        if self.width < 2 || self.height < 2 {
+            return Err(format!("{}x{} arena is too small", self.width, self.height));
+        }
+        let walls = self.wall_cells();
+        let portals: Vec<Position> = self
+            .portals
+            .iter()
+            .flat_map(|(a, b)| vec![*a, *b])
+            .collect();
+        if let Some(cell) = walls
+            .iter()
+            .chain(&portals)
+            .find(|cell| !arena.contains(cell))
+        {
+            return Err(format!("({}, {}) is outside the arena", cell.x, cell.y));
+        }
+        let neck = Position {
+            x: self.start.x,
+            y: self.start.y - 1,
+        };
+        for cell in &[self.start, neck] {
+            if !arena.contains(cell) || walls.contains(cell) || portals.contains(cell) {
+                return Err(format!(
+                    "the snake cannot start at ({}, {})",
+                    cell.x, cell.y
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let level: Level = ron::de::from_bytes(bytes)?;
+            level.check().map_err(anyhow::Error::msg)?;
+            load_context.set_default_asset(LoadedAsset::new(level));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// The level given with `--level`, relative to `assets/`, e.g.
+/// `--level levels/cross.ron`. Without one the built-in arena is played.
+#[derive(Default)]
+pub struct ChosenLevel {
+    path: Option<String>,
+    handle: Option<Handle<Level>>,
+    applied: bool,
+}
+
+impl ChosenLevel {
+    fn from_options(options: &Options) -> Self {
+        Self {
+            path: options.level.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+pub fn load_level(asset_server: Res<AssetServer>, mut chosen: ResMut<ChosenLevel>) {
+    if let Some(path) = &chosen.path {
+        chosen.handle = Some(asset_server.load(path.as_str()));
+    }
+}
+
+/// Builds the arena from the chosen level once it has loaded, replacing the
+/// built-in layout `game_setup` spawned. Waits on the menu so no run is
+/// under way while the arena changes size.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_level(
+    mut commands: Commands,
+    (asset_server, levels): (Res<AssetServer>, Res<Assets<Level>>),
+    (state, materials): (Res<AppState>, Res<Materials>),
+    mut chosen: ResMut<ChosenLevel>,
+    (mut arena, mut bounds, mut start): (ResMut<Arena>, ResMut<SafeBounds>, ResMut<SnakeStart>),
+    (mut layout, mut portals, mut obstacles): (
+        ResMut<ArenaLayout>,
+        ResMut<Portals>,
+        ResMut<Obstacles>,
+    ),
+    (mut food_table, mut mobile_chance): (ResMut<FoodTable>, ResMut<MobileFoodChance>),
+    portal_entities: Query<With<Portal, Entity>>,
+    obstacle_entities: Query<With<Obstacle, Entity>>,
+    mut backgrounds: Query<With<ArenaBackground, &mut Size>>,
+) {
+    if chosen.applied || *state != AppState::Menu {
+        return;
+    }
+    let handle = match &chosen.handle {
+        Some(handle) => handle.clone(),
+        None => return,
+    };
+    let level = match levels.get(&handle) {
+        Some(level) => level,
+        None => {
+            if asset_server.get_load_state(handle.id) == LoadState::Failed {
+                eprintln!(
+                    "could not load level {}",
+                    chosen.path.as_deref().unwrap_or("")
+                );
+                chosen.applied = true;
+            }
+            return;
+        }
+    };
+    *arena = level.arena();
+    *bounds = SafeBounds::full(&arena);
+    *layout = ArenaLayout {
+        start: level.start,
+        portals: level.portals.clone(),
+        walls: level.wall_cells(),
+    };
+    let (level_start, level_portals, level_obstacles) = layout.fit(&arena);
+    *start = level_start;
+    *portals = level_portals;
+    *obstacles = level_obstacles;
+    if let Some(food) = &level.food {
+        food_table.0 = food.clone();
+    }
+    if let Some(chance) = level.mobile_food_chance {
+        mobile_chance.0 = chance;
+    }
+    for ent in portal_entities.iter().chain(obstacle_entities.iter()) {
+        commands.despawn(ent);
+    }
+    spawn_layout(&mut commands, &materials, &portals, &obstacles);
+    for mut size in backgrounds.iter_mut() {
+        *size = Size {
+            width: arena.width as f32,
+            height: arena.height as f32,
+        };
+    }
+    chosen.applied = true;
+}
+
+/// Loads the level chosen on the command line and builds the arena from it.
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let chosen = app
+            .resources()
+            .get::<Options>()
+            .map(|options| ChosenLevel::from_options(&options))
+            .unwrap_or_default();
+        app.add_asset::<Level>()
+            .init_asset_loader::<LevelLoader>()
+            .add_resource(chosen)
+            .add_startup_system(load_level.system())
+            .add_system(apply_level.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bundled_level_is_playable() {
+        let level: Level = ron::de::from_str(include_str!("../assets/levels/cross.ron")).unwrap();
+        assert_eq!(level.check(), Ok(()));
+        assert!(!level.wall_cells().is_empty());
+    }
+
+    #[test]
+    fn walls_cover_their_whole_rectangle() {
+        let level = Level {
+            width: 10,
+            height: 10,
+            start: Position { x: 1, y: 1 },
+            walls: vec![(Position { x: 5, y: 4 }, Position { x: 4, y: 5 })],
+            portals: Vec::new(),
+            food: None,
+            mobile_food_chance: None,
+        };
+        assert_eq!(level.wall_cells().len(), 4);
+        assert_eq!(level.check(), Ok(()));
+        let blocked = Level {
+            start: Position { x: 4, y: 6 },
+            ..level.clone()
+        };
+        assert!(blocked.check().is_err());
+        let outside = Level {
+            start: Position { x: 4, y: 0 },
+            ..level
+        };
+        assert!(outside.check().is_err());
+    }
+}
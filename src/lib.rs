@@ -0,0 +1,6355 @@
+//! A snake game for Bevy, packaged as a plugin.
+
+#![warn(clippy::complexity)]
+use achievements::{Achievement, Achievements, Progress};
+use bevy::app::AppExit;
+use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use bevy::render::pass::ClearColor;
+use bindings::{Action, KeyBindings};
+use daily::{DailyBest, DailyChallenge};
+use food::{spawn_food, Food, FoodSpawnTimer, FoodTable, FoodType, MobileFoodChance};
+use leaderboard::{Entry, Leaderboard, LEADERBOARD_SIZE};
+use options::Options;
+use powerup::{
+    effect_bars, grant_power_ups, multiplier_badge, tick_effects, ActiveEffects, ActiveMultiplier,
+    EffectBar, MultiplierBadge, PowerUp, PowerUpEvent, EFFECT_BAR_WIDTH,
+};
+use profile::{ProfileEntry, ProfilePlugin, Profiles};
+use rand::prelude::{thread_rng, Rng, SeedableRng, SliceRandom, StdRng};
+use render::{DeathAnimation, Patterns, ShakeIntensity, SpriteAtlas};
+use replay::Replay;
+use serde::{Deserialize, Serialize};
+use settings::Settings;
+use sound::{Music, Sounds, Volume};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use theme::Themes;
+
+pub mod achievements;
+pub mod api;
+pub mod bindings;
+pub mod bot;
+pub mod client;
+pub mod daily;
+pub mod env;
+pub mod food;
+pub mod headless;
+pub mod input;
+pub mod leaderboard;
+pub mod level;
+pub mod maze;
+pub mod online;
+pub mod options;
+pub mod path;
+pub mod powerup;
+pub mod profile;
+pub mod render;
+pub mod replay;
+pub mod rollback;
+pub mod server;
+pub mod settings;
+pub mod sound;
+pub mod theme;
+
+pub use api::BotApiPlugin;
+pub use bot::BotPlugin;
+pub use daily::DailyPlugin;
+pub use food::FoodPlugin;
+pub use input::InputPlugin;
+pub use level::LevelPlugin;
+pub use online::OnlinePlugin;
+pub use render::RenderPlugin;
+pub use sound::SoundPlugin;
+pub use theme::{Theme, ThemePlugin};
+
+pub const ARENA_HEIGHT: u32 = 20;
+pub const ARENA_WIDTH: u32 = 20;
+/// Sides of the square arenas the settings screen steps through.
+pub const ARENA_SIZES: [u32; 5] = [10, 15, 20, 25, 30];
+/// Move intervals in seconds the settings screen's speed line steps through.
+pub const MOVE_INTERVALS: [f32; 5] = [0.2, 0.15, 0.1, 0.075, 0.05];
+/// Smallest arena `--arena` accepts, which still fits the snake's start and
+/// a fully shrunk arena.
+pub const MIN_ARENA_SIZE: u32 = MIN_SAFE_SIZE as u32;
+pub const FREE_CELL_ATTEMPTS: usize = 8;
+
+pub const MIN_MOVE_INTERVAL: f32 = 0.05;
+pub const SPEED_UP_EVERY: u32 = 5;
+pub const SPEED_UP_STEP: f32 = 0.01;
+pub const SEGMENT_GRADIENT_STEPS: usize = 8;
+pub const SEGMENT_SIZE: f32 = 0.65;
+pub const GHOST_MODE_DURATION: f32 = 5.0;
+pub const SLOW_MOTION_DURATION: f32 = 5.0;
+pub const SLOW_MOTION_FACTOR: f32 = 2.0;
+pub const SPEED_BOOST_DURATION: f32 = 5.0;
+pub const SPEED_BOOST_FACTOR: f32 = 0.6;
+pub const SCORE_MULTIPLIER_DURATION: f32 = 10.0;
+pub const SCORE_MULTIPLIER_MAX: u32 = 3;
+pub const FOOD_MAGNET_DURATION: f32 = 8.0;
+pub const BOOST_FACTOR: f32 = 0.5;
+pub const BOOST_DRAIN: f32 = 0.4;
+pub const BOOST_REFILL: f32 = 0.1;
+pub const STAMINA_BAR_WIDTH: f32 = 120.0;
+pub const COMBO_WINDOW: f32 = 3.0;
+pub const COMBO_MAX_MULTIPLIER: u32 = 8;
+pub const COMBO_FLASH_DURATION: f32 = 0.4;
+pub const STARTING_LIVES: u32 = 3;
+pub const STARVATION_TICKS: u32 = 40;
+pub const POISON_FLASH_DURATION: f32 = 0.3;
+pub const POISON_FLASH_ALPHA: f32 = 0.5;
+pub const INVULNERABILITY_DURATION: f32 = 1.5;
+pub const INVULNERABILITY_BLINK: f32 = 0.1;
+pub const TIME_ATTACK_DURATION: f32 = 120.0;
+pub const TIME_ATTACK_DEATH_PENALTY: f32 = 5.0;
+pub const BANNER_DURATION: f32 = 3.0;
+pub const COUNTDOWN_DURATION: f32 = 3.0;
+pub const COUNTDOWN_GO_DURATION: f32 = 0.6;
+pub const STALL_PAUSE: f32 = 0.5;
+pub const SCORE_POPUP_DURATION: f32 = 0.7;
+pub const SCORE_POPUP_DRIFT: f32 = 40.0;
+pub const SCORE_POPUP_FONT_SIZE: f32 = 20.0;
+pub const HUNTER_SPAWN_LENGTH: usize = 8;
+pub const HUNTER_MOVE_TICKS: u32 = 2;
+pub const HUNTER_SPAWN_DISTANCE: i32 = 6;
+pub const SHRINK_INTERVAL: f32 = 20.0;
+pub const MIN_SAFE_SIZE: i32 = 6;
+pub const TOAST_DURATION: f32 = 3.0;
+pub const TOAST_SLIDE: f32 = 0.3;
+pub const TOAST_HEIGHT: f32 = 34.0;
+pub const LAST_RUN_REPLAY: &str = "last_run.replay";
+pub const SNAKE_START: Position = Position { x: 3, y: 3 };
+
+/// Cell the head starts each life on, heading up with its neck just below.
+pub struct SnakeStart(Position);
+impl Default for SnakeStart {
+    fn default() -> Self {
+        Self(SNAKE_START)
+    }
+}
+impl SnakeStart {
+    /// Where each of `players`' snakes starts a life, and its heading. Player
+    /// two starts opposite player one through the centre of `arena`, heading
+    /// down, and players three and four start in the other two corners
+    /// player one's start mirrors to, heading up and down.
+    fn placements(&self, players: &[Player], arena: &Arena) -> Vec<(Player, Position, Direction)> {
+        let (x, y) = (self.0.x, self.0.y);
+        let (mirror_x, mirror_y) = (arena.width as i32 - 1 - x, arena.height as i32 - 1 - y);
+        players
+            .iter()
+            .map(|player| match player {
+                Player::One => (*player, self.0, Direction::Up),
+                Player::Two => (
+                    *player,
+                    Position {
+                        x: mirror_x,
+                        y: mirror_y,
+                    },
+                    Direction::Down,
+                ),
+                Player::Three => (*player, Position { x: mirror_x, y }, Direction::Up),
+                Player::Four => (*player, Position { x, y: mirror_y }, Direction::Down),
+            })
+            .collect()
+    }
+
+    /// The cells of every head and neck placed for `players`, all taken by
+    /// the snakes when a life begins.
+    fn cells(&self, players: &[Player], arena: &Arena) -> Vec<Position> {
+        self.placements(players, arena)
+            .into_iter()
+            .flat_map(|(_, start, direction)| vec![start, neck_behind(start, direction)])
+            .collect()
+    }
+}
+
+/// Cell of the first segment of a snake whose head starts at `start` heading
+/// in `direction`.
+fn neck_behind(start: Position, direction: Direction) -> Position {
+    direction.opposite().step(start)
+}
+
+/// Stages of the snake step. `TICK` runs every frame; the rest are stages of
+/// the `FixedSchedule` and run once per move.
+pub mod snake_stage {
+    pub const TICK: &str = "snake_tick";
+    pub const FIXED_UPDATE: &str = "snake_fixed_update";
+    pub const STEER: &str = "snake_steer";
+    pub const MOVEMENT: &str = "snake_movement";
+    pub const EATING: &str = "snake_eating";
+    pub const GROWTH: &str = "snake_growth";
+    pub const SHRINK: &str = "snake_shrink";
+}
+
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Position {
+    x: i32,
+    y: i32,
+}
+
+/// Playfield size in cells.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Arena {
+    width: u32,
+    height: u32,
+}
+impl Arena {
+    fn cells(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        pos.x >= 0 && pos.y >= 0 && (pos.x as u32) < self.width && (pos.y as u32) < self.height
+    }
+
+    /// Picks a uniformly random cell of the arena.
+    fn random_cell(&self, rng: &mut impl Rng) -> Position {
+        Position {
+            x: rng.gen_range(0, self.width as i32),
+            y: rng.gen_range(0, self.height as i32),
+        }
+    }
+
+    /// Picks a random cell that is not in `occupied`, or `None` if every cell is taken.
+    /// Tries a few random cells first and only lists the free ones when the
+    /// arena is crowded.
+    fn random_free_cell(
+        &self,
+        occupied: &HashSet<Position>,
+        rng: &mut impl Rng,
+    ) -> Option<Position> {
+        for _ in 0..FREE_CELL_ATTEMPTS {
+            let cell = self.random_cell(rng);
+            if !occupied.contains(&cell) {
+                return Some(cell);
+            }
+        }
+        let height = self.height as i32;
+        let free: Vec<Position> = (0..self.width as i32)
+            .flat_map(|x| (0..height).map(move |y| Position { x, y }))
+            .filter(|p| !occupied.contains(p))
+            .collect();
+        free.choose(rng).copied()
+    }
+
+    /// Side of one square cell in pixels: the largest that fits the whole
+    /// arena, leaving letterbox bars on the window's longer axis.
+    fn cell_size(&self, window: &Window) -> f32 {
+        (window.width() as f32 / self.width as f32).min(window.height() as f32 / self.height as f32)
+    }
+
+    /// Center of the cell at `pos` relative to the center of the arena, for
+    /// cells `cell` pixels wide.
+    fn cell_center(&self, pos: &Position, cell: f32) -> Vec2 {
+        Vec2::new(
+            (pos.x as f32 - self.width as f32 / 2. + 0.5) * cell,
+            (pos.y as f32 - self.height as f32 / 2. + 0.5) * cell,
+        )
+    }
+}
+impl Default for Arena {
+    fn default() -> Self {
+        Self {
+            width: ARENA_WIDTH,
+            height: ARENA_HEIGHT,
+        }
+    }
+}
+impl std::str::FromStr for Arena {
+    type Err = String;
+
+    /// Parses `WIDTHxHEIGHT`, e.g. `30x20`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let size = |side: &str| {
+            side.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|side| *side >= MIN_ARENA_SIZE)
+        };
+        match s.split_once('x').map(|(w, h)| (size(w), size(h))) {
+            Some((Some(width), Some(height))) => Ok(Self { width, height }),
+            _ => Err(format!(
+                "expected an arena of at least {0}x{0} cells, such as 30x20, not {1:?}",
+                MIN_ARENA_SIZE, s
+            )),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Size {
+    width: f32,
+    height: f32,
+}
+impl Size {
+    pub fn square(x: f32) -> Self {
+        Self {
+            width: x,
+            height: x,
+        }
+    }
+}
+
+/// Turns that can wait behind the one pending for the next move, so quick
+/// presses within one tick are made on the ticks after it instead of lost.
+pub const TURN_BUFFER: usize = 2;
+
+/// Who steers a snake. Only the modes with two snakes have a player two,
+/// and only versus on the dedicated server has players three and four.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Player {
+    One,
+    Two,
+    Three,
+    Four,
+}
+impl Player {
+    pub const ALL: [Player; 4] = [Self::One, Self::Two, Self::Three, Self::Four];
+
+    /// The player's number, from 1.
+    pub fn number(self) -> u8 {
+        match self {
+            Self::One => 1,
+            Self::Two => 2,
+            Self::Three => 3,
+            Self::Four => 4,
+        }
+    }
+}
+impl std::fmt::Display for Player {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Player {}", self.number())
+    }
+}
+
+/// Length of player one's snake, head included. Lengths, scores and
+/// achievements outside versus are all player one's.
+fn player_one_length<'a>(heads: impl Iterator<Item = (&'a Player, &'a SnakeSegments)>) -> usize {
+    heads
+        .filter(|(player, _)| **player == Player::One)
+        .map(|(_, segments)| segments.len())
+        .next()
+        .unwrap_or(0)
+        + 1
+}
+
+#[derive(Clone)]
+pub struct SnakeHead {
+    direction: Direction,
+    try_direction: Direction,
+    /// Turns to make after `try_direction`, one per move.
+    queued_turns: VecDeque<Direction>,
+}
+impl SnakeHead {
+    /// The direction the snake will head in once every pending turn is made.
+    fn planned(&self) -> Direction {
+        self.queued_turns
+            .back()
+            .copied()
+            .unwrap_or(self.try_direction)
+    }
+
+    /// Turns towards `dir` on the next move, or after the turns already
+    /// pending. The turn must neither repeat nor reverse the last one
+    /// planned, so no run of key presses can steer into the neck. Returns
+    /// whether the turn was taken.
+    fn steer(&mut self, dir: Direction) -> bool {
+        let planned = self.planned();
+        if dir == planned || dir == planned.opposite() {
+            false
+        } else if self.try_direction == self.direction && self.queued_turns.is_empty() {
+            self.try_direction = dir;
+            true
+        } else if self.queued_turns.len() < TURN_BUFFER {
+            self.queued_turns.push_back(dir);
+            true
+        } else {
+            false
+        }
+    }
+}
+#[derive(Default)]
+pub struct Materials {
+    arena_material: Handle<ColorMaterial>,
+    head_material: Handle<ColorMaterial>,
+    rival_head_material: Handle<ColorMaterial>,
+    shielded_head_material: Handle<ColorMaterial>,
+    /// Body shades from just behind the head to the tail.
+    segment_gradient: Vec<Handle<ColorMaterial>>,
+    /// The same shades for segments where the body turns a corner.
+    corner_gradient: Vec<Handle<ColorMaterial>>,
+    /// The last segment, in the last shade, drawn as the tail once the sprite
+    /// atlas has loaded.
+    tail_material: Handle<ColorMaterial>,
+    food_material: Handle<ColorMaterial>,
+    double_food_material: Handle<ColorMaterial>,
+    golden_food_material: Handle<ColorMaterial>,
+    poison_food_material: Handle<ColorMaterial>,
+    portal_material: Handle<ColorMaterial>,
+    ghost_pickup_material: Handle<ColorMaterial>,
+    ghost_segment_material: Handle<ColorMaterial>,
+    slow_motion_pickup_material: Handle<ColorMaterial>,
+    speed_boost_pickup_material: Handle<ColorMaterial>,
+    shield_pickup_material: Handle<ColorMaterial>,
+    shrink_potion_material: Handle<ColorMaterial>,
+    score_multiplier_material: Handle<ColorMaterial>,
+    food_magnet_material: Handle<ColorMaterial>,
+    ghost_snake_material: Handle<ColorMaterial>,
+    wall_material: Handle<ColorMaterial>,
+    hunter_material: Handle<ColorMaterial>,
+}
+impl Materials {
+    fn head_material(&self, player: Player) -> Handle<ColorMaterial> {
+        match player {
+            Player::One => self.head_material.clone(),
+            Player::Two | Player::Three | Player::Four => self.rival_head_material.clone(),
+        }
+    }
+
+    /// Gradient shade for segment `index` of a body `len` segments long.
+    fn segment_material(&self, index: usize, len: usize) -> Handle<ColorMaterial> {
+        Self::shade(&self.segment_gradient, index, len)
+    }
+
+    /// Gradient shade for a corner at segment `index` of a body `len`
+    /// segments long.
+    fn corner_material(&self, index: usize, len: usize) -> Handle<ColorMaterial> {
+        Self::shade(&self.corner_gradient, index, len)
+    }
+
+    fn shade(
+        gradient: &[Handle<ColorMaterial>],
+        index: usize,
+        len: usize,
+    ) -> Handle<ColorMaterial> {
+        let steps = gradient.len();
+        if steps == 0 {
+            return Handle::default();
+        }
+        let step = index * (steps - 1) / len.saturating_sub(1).max(1);
+        gradient[step.min(steps - 1)].clone()
+    }
+}
+
+/// Time towards the snakes' next move. Unlike a repeating `Timer` it keeps
+/// all the time it has been given, so a long frame owes several moves rather
+/// than dropping all but one; `finished` tells whether any were played this
+/// frame.
+#[derive(Clone)]
+pub struct SnakeMoveTimer(Timer);
+
+/// Most moves played in one frame. The moves owed past these after a long
+/// stall are dropped rather than raced through.
+pub const MAX_MOVES_PER_FRAME: u32 = 4;
+
+impl SnakeMoveTimer {
+    /// Takes the moves that are due off the timer, keeping what is left over
+    /// towards the next.
+    fn take_due(&mut self) -> u32 {
+        let timer = &mut self.0;
+        let mut due = 0;
+        while timer.elapsed >= timer.duration && due < MAX_MOVES_PER_FRAME {
+            timer.elapsed -= timer.duration;
+            due += 1;
+        }
+        if timer.elapsed >= timer.duration {
+            timer.elapsed %= timer.duration;
+        }
+        timer.finished = due > 0;
+        due
+    }
+}
+
+/// The systems that play one move of the game, stage by stage: steering,
+/// movement, eating, growth and shrinking. `fixed_update` runs them once for
+/// every move the `SnakeMoveTimer` has due, however many frames that takes.
+/// Add to it with `add_step_system`.
+pub struct FixedSchedule {
+    stages: Vec<(&'static str, Vec<Box<dyn System>>)>,
+    initialized: bool,
+}
+
+impl Default for FixedSchedule {
+    fn default() -> Self {
+        let stages = [
+            snake_stage::STEER,
+            snake_stage::MOVEMENT,
+            snake_stage::EATING,
+            snake_stage::GROWTH,
+            snake_stage::SHRINK,
+        ];
+        Self {
+            stages: stages.iter().map(|stage| (*stage, Vec::new())).collect(),
+            initialized: false,
+        }
+    }
+}
+
+impl FixedSchedule {
+    fn add_system(&mut self, stage: &str, system: Box<dyn System>) {
+        let (_, systems) = self
+            .stages
+            .iter_mut()
+            .find(|(name, _)| *name == stage)
+            .unwrap_or_else(|| panic!("no fixed stage {}", stage));
+        systems.push(system);
+    }
+
+    /// Plays one move: every stage's systems in the order they were added,
+    /// their commands applied before the next stage. Unlike a `Schedule` it
+    /// leaves change tracking alone, so the frame's other systems still see
+    /// what the move changed.
+    fn run(&mut self, world: &mut World, resources: &mut Resources) {
+        if !self.initialized {
+            for (_, systems) in &mut self.stages {
+                for system in systems.iter_mut() {
+                    system.initialize(world, resources);
+                }
+            }
+            self.initialized = true;
+        }
+        for (_, systems) in &mut self.stages {
+            for system in systems.iter_mut() {
+                system.update(world);
+                system.run(world, resources);
+            }
+            for system in systems.iter_mut() {
+                system.run_thread_local(world, resources);
+            }
+        }
+    }
+}
+
+/// Plays every move due this frame, so the snakes keep the same pace at any
+/// frame rate and each move sees the timer, movement and eating in order.
+pub fn fixed_update(world: &mut World, resources: &mut Resources) {
+    let due = resources.get_mut::<SnakeMoveTimer>().unwrap().take_due();
+    if due == 0 {
+        return;
+    }
+    let mut schedule = std::mem::take(&mut *resources.get_mut::<FixedSchedule>().unwrap());
+    for _ in 0..due {
+        schedule.run(world, resources);
+    }
+    *resources.get_mut::<FixedSchedule>().unwrap() = schedule;
+}
+
+/// Move interval in seconds before temporary effects such as slow motion are
+/// applied. `SnakeMoveTimer` is derived from this every frame.
+#[derive(Clone)]
+pub struct BaseMoveInterval(f32);
+impl Default for BaseMoveInterval {
+    fn default() -> Self {
+        Self(Difficulty::default().move_interval())
+    }
+}
+
+#[derive(Clone)]
+pub struct Portal;
+
+/// Paired portal cells; stepping onto either end places the head on the other.
+pub struct Portals(Vec<(Position, Position)>);
+impl Portals {
+    fn exit(&self, entry: &Position) -> Option<Position> {
+        self.0.iter().find_map(|(a, b)| {
+            if a == entry {
+                Some(*b)
+            } else if b == entry {
+                Some(*a)
+            } else {
+                None
+            }
+        })
+    }
+}
+impl Default for Portals {
+    fn default() -> Self {
+        Self(vec![
+            (Position { x: 4, y: 15 }, Position { x: 15, y: 4 }),
+            (Position { x: 15, y: 15 }, Position { x: 10, y: 8 }),
+        ])
+    }
+}
+
+/// Interior wall cells of the level, fatal to the head like the arena's edge.
+/// The arena is open unless `--obstacles` is given.
+#[derive(Default, Clone)]
+pub struct Obstacles(Vec<Position>);
+impl Obstacles {
+    fn from_options(options: &Options) -> Self {
+        if options.obstacles {
+            Self::level()
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Three bars that keep clear of the portals and the snake's start.
+    fn level() -> Self {
+        let horizontal = |y, xs: std::ops::RangeInclusive<i32>| xs.map(move |x| Position { x, y });
+        Self(
+            horizontal(6, 6..=9)
+                .chain(horizontal(13, 10..=13))
+                .chain((9..=12).map(|y| Position { x: 5, y }))
+                .collect(),
+        )
+    }
+}
+
+/// The start, portals and walls of the built-in arena or the chosen level,
+/// laid out for its own size. `SnakeStart`, `Portals` and `Obstacles` hold
+/// what of it fits the arena being played, so going back to a larger arena
+/// brings back whatever a smaller one left out.
+#[derive(Clone)]
+pub struct ArenaLayout {
+    pub start: Position,
+    pub portals: Vec<(Position, Position)>,
+    pub walls: Vec<Position>,
+}
+impl ArenaLayout {
+    pub fn from_options(options: &Options) -> Self {
+        Self {
+            start: SNAKE_START,
+            portals: Portals::default().0,
+            walls: Obstacles::from_options(options).0,
+        }
+    }
+
+    /// The start, portals and walls that fit `arena`; the snake starts in
+    /// the corner if its own start does not.
+    pub fn fit(&self, arena: &Arena) -> (SnakeStart, Portals, Obstacles) {
+        let start = if arena.contains(&self.start) {
+            SnakeStart(self.start)
+        } else {
+            SnakeStart::default()
+        };
+        let portals = self
+            .portals
+            .iter()
+            .filter(|(a, b)| arena.contains(a) && arena.contains(b))
+            .copied()
+            .collect();
+        let walls = self
+            .walls
+            .iter()
+            .filter(|cell| arena.contains(cell))
+            .copied()
+            .collect();
+        (start, Portals(portals), Obstacles(walls))
+    }
+}
+impl Default for ArenaLayout {
+    fn default() -> Self {
+        Self::from_options(&Options::default())
+    }
+}
+
+/// Sent when a snake dies, with whose it was, what killed it and the head's
+/// cell at the time. For wall deaths that is the last cell inside the arena.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GameOverEvent {
+    player: Player,
+    reason: GameOverReason,
+    position: Position,
+}
+impl std::fmt::Display for GameOverEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (x, y) = (self.position.x, self.position.y);
+        match self.reason {
+            GameOverReason::HitWall => write!(f, "You ran into the wall at ({}, {})", x, y),
+            GameOverReason::HitSelf => write!(f, "You bit yourself at ({}, {})", x, y),
+            GameOverReason::CaughtByHunter => write!(f, "The hunter caught you at ({}, {})", x, y),
+            GameOverReason::Starved => write!(f, "You starved at ({}, {})", x, y),
+            GameOverReason::Poisoned => write!(f, "You were poisoned at ({}, {})", x, y),
+            GameOverReason::HitRival => {
+                write!(f, "You ran into the other snake at ({}, {})", x, y)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GameOverReason {
+    /// Left the arena or ran into a wall of the shrinking arena.
+    HitWall,
+    HitSelf,
+    CaughtByHunter,
+    /// Went hungry with no segment left to lose.
+    Starved,
+    /// Ate poison with too few segments left to lose.
+    Poisoned,
+    /// Ran into the other snake in versus.
+    HitRival,
+}
+/// Sent for every food eaten, with its type, the segments it adds (negative
+/// for segments lost), the cell it was eaten at and the head that ate it.
+pub struct GrowthEvent {
+    food: FoodType,
+    amount: i32,
+    position: Position,
+    snake: Entity,
+}
+
+/// Points one eaten food scored, and where it was eaten.
+pub struct ScoreEvent {
+    points: u32,
+    position: Position,
+}
+pub struct VictoryEvent;
+/// Sent when the snake with this head has gone `GameRules::starvation` ticks
+/// without eating.
+pub struct ShrinkEvent(Entity);
+
+/// Sent whenever a fresh run begins: once at startup and after every run ends.
+pub struct RunStartEvent;
+
+/// Ends the current run outright, regardless of remaining lives, or starts
+/// the next one.
+pub enum EndRunEvent {
+    TimeUp,
+    Restart,
+}
+
+/// Asks for the arena to take this size, sent from the settings screen.
+pub struct ResizeArenaEvent(Arena);
+
+#[derive(Clone)]
+pub struct SnakeSegment;
+
+/// A snake's body from the segment behind the head to the tail, kept on the
+/// head. Each segment's cell is kept alongside its entity, so a move rotates
+/// the tail segment round to the front instead of shifting every segment
+/// along. How many segments sit on each cell is kept up to date as well, so
+/// telling whether a cell is taken does not walk the body.
+#[derive(Default, Clone)]
+pub struct SnakeSegments {
+    entities: VecDeque<Entity>,
+    positions: VecDeque<Position>,
+    occupied: HashMap<Position, u32>,
+    /// Cell the tail left on the last move; growth puts new segments there.
+    vacated: Option<Position>,
+}
+impl SnakeSegments {
+    fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &Entity> {
+        self.entities.iter()
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        self.occupied.contains_key(pos)
+    }
+
+    /// Whether a head moving onto `pos` would run into the body once the
+    /// body has followed it, i.e. into any segment but the tail.
+    fn blocks(&self, pos: &Position) -> bool {
+        let here = self.occupied.get(pos).copied().unwrap_or(0);
+        let tail = (self.positions.back() == Some(pos)) as u32;
+        here > tail
+    }
+
+    fn occupy(&mut self, pos: Position) {
+        *self.occupied.entry(pos).or_insert(0) += 1;
+    }
+
+    fn vacate(&mut self, pos: &Position) {
+        if let Some(count) = self.occupied.get_mut(pos) {
+            *count -= 1;
+            if *count == 0 {
+                self.occupied.remove(pos);
+            }
+        }
+    }
+
+    /// Adds a segment behind the tail.
+    fn push(&mut self, entity: Entity, position: Position) {
+        self.entities.push_back(entity);
+        self.positions.push_back(position);
+        self.occupy(position);
+    }
+
+    /// Cuts the body down to `len` segments and returns the ones cut off.
+    /// The cell of the first one cut off becomes `vacated`, so growth keeps
+    /// adding segments right behind the new tail.
+    fn truncate(&mut self, len: usize) -> impl Iterator<Item = Entity> + '_ {
+        if let Some(behind_tail) = self.positions.get(len) {
+            self.vacated = Some(*behind_tail);
+        }
+        for cut in self.positions.split_off(len.min(self.positions.len())) {
+            self.vacate(&cut);
+        }
+        self.entities.drain(len.min(self.entities.len())..)
+    }
+
+    /// Moves the body one step, the segment behind the head taking `neck`.
+    /// Only the tail segment changes cell, to become the new neck; it is
+    /// returned so its `Position` can be updated.
+    fn advance(&mut self, neck: Position) -> Option<Entity> {
+        let tail = self.entities.pop_back()?;
+        self.vacated = self.positions.pop_back();
+        if let Some(vacated) = self.vacated {
+            self.vacate(&vacated);
+        }
+        self.entities.push_front(tail);
+        self.positions.push_front(neck);
+        self.occupy(neck);
+        Some(tail)
+    }
+}
+
+/// A one-shot timer that starts out already expired.
+pub fn expired_timer(seconds: f32) -> Timer {
+    let mut timer = Timer::from_seconds(seconds, false);
+    timer.elapsed = timer.duration;
+    timer.finished = true;
+    timer
+}
+
+/// Turbo while Left Shift is held: `active` moves twice as fast, draining
+/// `stamina` (0.0 to 1.0), which refills slowly while Shift is up.
+#[derive(Clone)]
+pub struct Boost {
+    stamina: f32,
+    active: bool,
+}
+impl Default for Boost {
+    fn default() -> Self {
+        Self {
+            stamina: 1.0,
+            active: false,
+        }
+    }
+}
+
+pub struct StaminaBar;
+
+/// Player one's score: the one recorded, ranked and shown outside versus.
+#[derive(Default, Clone)]
+pub struct Score(u32);
+
+/// The other players' scores in versus, player two's first.
+#[derive(Default, Clone)]
+pub struct RivalScores(Vec<u32>);
+impl RivalScores {
+    fn add(&mut self, player: Player, points: u32) {
+        let index = player as usize - 1;
+        if self.0.len() <= index {
+            self.0.resize(index + 1, 0);
+        }
+        self.0[index] += points;
+    }
+}
+
+/// `player`'s score in versus: player one's is the `Score`, and the others'
+/// are among the `RivalScores`.
+pub fn versus_score(player: Player, score: &Score, rival_scores: &RivalScores) -> u32 {
+    match player {
+        Player::One => score.0,
+        _ => rival_scores
+            .0
+            .get(player as usize - 1)
+            .copied()
+            .unwrap_or(0),
+    }
+}
+
+/// Snakes in a versus round, one per player from player one. The windowed
+/// game always has two, one per half of the keyboard; the dedicated server
+/// seats as many as `--players` says.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct VersusPlayers(usize);
+impl Default for VersusPlayers {
+    fn default() -> Self {
+        Self(2)
+    }
+}
+impl VersusPlayers {
+    pub fn from_options(options: &Options) -> Self {
+        options
+            .players
+            .map_or_else(Self::default, |players| Self(players as usize))
+    }
+}
+
+/// Eating again before `window` runs out raises the score multiplier.
+#[derive(Clone)]
+pub struct Combo {
+    multiplier: u32,
+    window: Timer,
+}
+impl Combo {
+    /// Registers an eaten food and returns the multiplier it scores with.
+    fn eat(&mut self) -> u32 {
+        self.multiplier = if self.window.finished {
+            1
+        } else {
+            (self.multiplier + 1).min(COMBO_MAX_MULTIPLIER)
+        };
+        self.window.reset();
+        self.multiplier
+    }
+}
+impl Default for Combo {
+    fn default() -> Self {
+        Self {
+            multiplier: 1,
+            window: expired_timer(COMBO_WINDOW),
+        }
+    }
+}
+
+pub struct ComboText {
+    flash: Timer,
+}
+
+#[derive(Clone)]
+pub struct Lives(u32);
+impl Default for Lives {
+    fn default() -> Self {
+        Self(STARTING_LIVES)
+    }
+}
+
+pub struct LivesText;
+
+/// The score and the snake's length, in the top right corner.
+pub struct ScoreText;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GameMode {
+    Classic,
+    TimeAttack,
+    /// The arena closes in one ring at a time, and a single crash ends the run.
+    ShrinkingArena,
+    /// Classic rules in a fresh random maze every run.
+    Maze,
+    /// Two snakes on one keyboard, WASD against the arrow keys. The first
+    /// to crash, into anything including the other snake, loses.
+    Versus,
+    /// Two snakes on one keyboard playing together for one score. A crash,
+    /// into the other snake too, costs a life from a shared pool and
+    /// restarts both.
+    Coop,
+    /// Versus against a snake the computer steers towards the nearest food.
+    VersusBot,
+}
+impl GameMode {
+    fn from_options(options: &Options) -> Self {
+        if options.time_attack {
+            Self::TimeAttack
+        } else if options.shrinking_arena {
+            Self::ShrinkingArena
+        } else if options.maze {
+            Self::Maze
+        } else if options.versus {
+            Self::Versus
+        } else if options.coop {
+            Self::Coop
+        } else if options.versus_bot {
+            Self::VersusBot
+        } else {
+            Self::Classic
+        }
+    }
+
+    /// The mode after this one on the settings screen.
+    fn next(self) -> Self {
+        match self {
+            Self::Classic => Self::TimeAttack,
+            Self::TimeAttack => Self::ShrinkingArena,
+            Self::ShrinkingArena => Self::Maze,
+            Self::Maze => Self::Versus,
+            Self::Versus => Self::Coop,
+            Self::Coop => Self::VersusBot,
+            Self::VersusBot => Self::Classic,
+        }
+    }
+
+    /// The players with a snake in the arena.
+    pub fn players(self, versus: VersusPlayers) -> &'static [Player] {
+        let snakes = match self {
+            Self::Versus => versus.0.clamp(2, Player::ALL.len()),
+            _ if self.two_snakes() => 2,
+            _ => 1,
+        };
+        &Player::ALL[..snakes]
+    }
+
+    /// Whether a second snake shares the arena.
+    pub fn two_snakes(self) -> bool {
+        self.two_player() || self == Self::VersusBot
+    }
+
+    /// Whether the two snakes play against each other, each for their own
+    /// score, until the first crash.
+    pub fn rivals(self) -> bool {
+        matches!(self, Self::Versus | Self::VersusBot)
+    }
+
+    /// Whether two snakes share the arena, steered from either half of the
+    /// keyboard.
+    pub fn two_player(self) -> bool {
+        matches!(self, Self::Versus | Self::Coop)
+    }
+}
+
+/// How the arena treats the snake, independent of the `GameMode`.
+#[derive(Default, Copy, Clone, PartialEq, Debug)]
+pub struct GameRules {
+    /// Leaving the safe bounds brings the head back in on the opposite side
+    /// instead of crashing it.
+    pub wrap_around: bool,
+    /// Move ticks the snake may go without eating before it loses its tail
+    /// segment; it never starves when `None`.
+    pub starvation: Option<u32>,
+}
+impl GameRules {
+    fn from_options(options: &Options) -> Self {
+        Self {
+            wrap_around: options.wrap_around,
+            starvation: options.starvation,
+        }
+    }
+}
+
+/// Move ticks a snake has gone since it last ate or lost a segment to
+/// hunger, kept on its head.
+#[derive(Default, Clone)]
+pub struct Hunger(u32);
+
+/// How the move interval shortens as the snake eats, on difficulties that
+/// speed up: `step` seconds off for every `every` foods, never going below
+/// `floor`. Set with `--speed-up-every`, `--speed-up-step` and
+/// `--speed-up-floor`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SpeedUp {
+    pub every: u32,
+    pub step: f32,
+    pub floor: f32,
+}
+impl Default for SpeedUp {
+    fn default() -> Self {
+        Self {
+            every: SPEED_UP_EVERY,
+            step: SPEED_UP_STEP,
+            floor: MIN_MOVE_INTERVAL,
+        }
+    }
+}
+impl SpeedUp {
+    fn from_options(options: &Options) -> Self {
+        let default = Self::default();
+        Self {
+            every: options.speed_up_every.unwrap_or(default.every),
+            step: options.speed_up_step.unwrap_or(default.step),
+            floor: options.speed_up_floor.unwrap_or(default.floor),
+        }
+    }
+
+    /// `base` shortened by the steps earned with `food_eaten` foods. A base
+    /// already below the floor is left alone.
+    fn interval(&self, base: f32, food_eaten: u32) -> f32 {
+        let steps = food_eaten.checked_div(self.every).unwrap_or(0);
+        (base - steps as f32 * self.step).max(self.floor.min(base))
+    }
+}
+
+/// Inclusive corners of the cells the snake may occupy. Covers the whole arena
+/// except in `GameMode::ShrinkingArena`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SafeBounds {
+    min: Position,
+    max: Position,
+}
+impl SafeBounds {
+    fn full(arena: &Arena) -> Self {
+        Self {
+            min: Position { x: 0, y: 0 },
+            max: Position {
+                x: arena.width as i32 - 1,
+                y: arena.height as i32 - 1,
+            },
+        }
+    }
+
+    fn contains(&self, pos: &Position) -> bool {
+        (self.min.x..=self.max.x).contains(&pos.x) && (self.min.y..=self.max.y).contains(&pos.y)
+    }
+
+    /// `pos` moved back inside, as if the opposite edges were joined.
+    fn wrap(&self, pos: &Position) -> Position {
+        let wrap = |value: i32, min: i32, max: i32| min + (value - min).rem_euclid(max - min + 1);
+        Position {
+            x: wrap(pos.x, self.min.x, self.max.x),
+            y: wrap(pos.y, self.min.y, self.max.y),
+        }
+    }
+
+    /// The bounds one ring further in, unless that would leave less than
+    /// `MIN_SAFE_SIZE` cells on either side.
+    fn shrunk(&self) -> Option<Self> {
+        let shrunk = Self {
+            min: Position {
+                x: self.min.x + 1,
+                y: self.min.y + 1,
+            },
+            max: Position {
+                x: self.max.x - 1,
+                y: self.max.y - 1,
+            },
+        };
+        if shrunk.max.x - shrunk.min.x + 1 < MIN_SAFE_SIZE
+            || shrunk.max.y - shrunk.min.y + 1 < MIN_SAFE_SIZE
+        {
+            None
+        } else {
+            Some(shrunk)
+        }
+    }
+}
+impl Default for SafeBounds {
+    fn default() -> Self {
+        Self::full(&Arena::default())
+    }
+}
+
+#[derive(Clone)]
+pub struct ShrinkTimer(Timer);
+impl Default for ShrinkTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(SHRINK_INTERVAL, true))
+    }
+}
+
+/// The sprite behind the grid, sized to the `Arena`.
+pub struct ArenaBackground;
+
+/// A lethal cell outside the `SafeBounds`, or one of the level's `Obstacles`.
+#[derive(Clone)]
+pub struct Wall;
+
+/// Marks the walls that belong to the level rather than to a shrunk arena, so
+/// they outlast the run.
+#[derive(Clone)]
+pub struct Obstacle;
+
+/// An enemy that chases the snake's head. It kills the head on contact but
+/// is harmless to the body.
+#[derive(Clone)]
+pub struct Hunter;
+
+/// Remaining time of a time attack round; only ticked in `GameMode::TimeAttack`.
+#[derive(Clone)]
+pub struct RoundTimer(Timer);
+impl Default for RoundTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(TIME_ATTACK_DURATION, false))
+    }
+}
+
+pub struct RoundTimerText;
+
+/// Runs whenever the snake is (re)spawned; the snake stays put until it ends.
+#[derive(Clone)]
+pub struct Countdown(Timer);
+impl Countdown {
+    fn active(&self) -> bool {
+        !self.0.finished
+    }
+}
+impl Default for Countdown {
+    fn default() -> Self {
+        Self(Timer::from_seconds(COUNTDOWN_DURATION, false))
+    }
+}
+
+pub struct CountdownText {
+    go: Timer,
+}
+
+/// Seconds of gameplay that passed this frame; zero unless a run is being
+/// played.
+/// Gameplay timers tick with this instead of `Time` so pausing freezes them all.
+#[derive(Default)]
+pub struct GameClock {
+    delta_seconds: f32,
+}
+
+/// Which screen the game is on. Bevy 0.3 has no state machine of its own, so
+/// gameplay systems check this resource and do nothing off the `Playing`
+/// screen.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AppState {
+    /// The main menu, shown before the first run.
+    #[default]
+    Menu,
+    /// Reached from the menu, or the pause screen with O; Escape goes back.
+    Settings,
+    /// Asks for a new player's name: on first launch, and from the settings
+    /// screen. Escape goes back to the settings once a profile exists.
+    Profile,
+    Playing,
+    /// P, Escape or Space resumes.
+    Paused,
+    /// The run is over, lost or won; Enter or Space starts the next one.
+    GameOver,
+}
+
+impl AppState {
+    /// What the prompt text reads on this screen.
+    fn prompt(self) -> &'static str {
+        match self {
+            Self::Menu => "Snake!",
+            Self::Settings => "Settings",
+            Self::Profile => "Your name? Enter to confirm",
+            Self::Playing => "",
+            Self::Paused => "Paused (P to resume, O for settings)",
+            Self::GameOver => "Enter to play again",
+        }
+    }
+
+    /// Whether keys are being typed into a prompt, rather than being
+    /// shortcuts.
+    fn typing(self) -> bool {
+        self == Self::Profile
+    }
+}
+
+/// Tells the player how to leave the current screen.
+pub struct PromptText;
+
+/// Dims the whole window while the game is paused or over.
+pub struct PauseOverlay;
+
+/// Marks the full-window red veil that flashes when poison is eaten, fading
+/// out over the timer.
+pub struct PoisonFlash(Timer);
+impl Default for PoisonFlash {
+    fn default() -> Self {
+        Self(expired_timer(POISON_FLASH_DURATION))
+    }
+}
+
+/// Lines of the main menu, top to bottom.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MenuItem {
+    Play,
+    Settings,
+    /// Today's daily challenge.
+    Daily,
+    Quit,
+}
+
+impl MenuItem {
+    pub const ALL: [MenuItem; 4] = [Self::Play, Self::Settings, Self::Daily, Self::Quit];
+}
+
+/// Lines of the settings screen, top to bottom.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SettingsItem {
+    Difficulty,
+    /// Steps through the `MOVE_INTERVALS`, for this run and the next.
+    Speed,
+    Mode,
+    /// Steps through the `ARENA_SIZES`.
+    Arena,
+    /// Turns `GameRules::wrap_around` on and off.
+    Wrap,
+    Theme,
+    /// Switches to the next local profile.
+    Player,
+    /// Opens the name prompt to make another profile.
+    NewPlayer,
+    /// Steps the master volume down a quarter at a time.
+    Volume,
+    Music,
+    Effects,
+    /// Steps the screen shake down a quarter at a time, to off.
+    Shake,
+    /// Turns the patterns on the head and food on and off.
+    Patterns,
+    Back,
+}
+
+impl SettingsItem {
+    pub const ALL: [SettingsItem; 14] = [
+        Self::Difficulty,
+        Self::Speed,
+        Self::Mode,
+        Self::Arena,
+        Self::Wrap,
+        Self::Theme,
+        Self::Player,
+        Self::NewPlayer,
+        Self::Volume,
+        Self::Music,
+        Self::Effects,
+        Self::Shake,
+        Self::Patterns,
+        Self::Back,
+    ];
+
+    fn line(self) -> usize {
+        Self::ALL.iter().position(|item| *item == self).unwrap()
+    }
+
+    /// Whether picking the line waits for the menu, as the mode and arena
+    /// must not change under a run that was paused to get here.
+    fn between_runs(self) -> bool {
+        matches!(self, Self::Mode | Self::Arena)
+    }
+}
+
+/// The screen the settings were opened from, the menu or the pause screen,
+/// which leaving them goes back to.
+#[derive(Default)]
+pub struct SettingsOrigin(AppState);
+
+/// Highlighted line of the menu or settings screen.
+#[derive(Default)]
+pub struct MenuCursor(usize);
+
+/// Line of the menu or settings screen.
+pub struct MenuRow(usize);
+
+#[derive(Default)]
+pub struct DebugOverlay(bool);
+
+pub struct DebugText;
+
+pub struct GameCamera;
+
+pub struct UiFont(Handle<Font>);
+
+/// Initials being entered for a run whose score made the leaderboard. The
+/// game clock stands still while `pending` holds the run's score, final length
+/// and difficulty.
+#[derive(Default)]
+pub struct NameEntry {
+    pending: Option<(u32, usize, Difficulty)>,
+    letters: [u8; 3],
+    slot: usize,
+}
+impl NameEntry {
+    fn active(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+pub struct NameEntryText;
+
+/// Whether the leaderboard table is shown; L toggles it.
+#[derive(Default)]
+pub struct LeaderboardView(bool);
+
+/// Line of the leaderboard table; 0 is the heading.
+pub struct LeaderboardRow(usize);
+
+/// Notification that slides in from the top edge into `slot`, then vanishes.
+pub struct Toast {
+    timer: Timer,
+    slot: usize,
+}
+
+/// Whether the achievements list is shown; F4 toggles it.
+#[derive(Default)]
+pub struct AchievementsView(bool);
+
+/// Line of the achievements list; 0 is the heading.
+pub struct AchievementRow(usize);
+
+/// Floating "+N" text that drifts up from `top` and fades out. It is a UI
+/// node placed in window pixels, not a grid entity with a `Position`.
+pub struct ScorePopup {
+    timer: Timer,
+    top: f32,
+}
+
+/// Centered message shown for a while after a run ends.
+pub struct Banner {
+    timer: Timer,
+}
+
+/// Second banner line listing the `RunStats` of the run that just ended.
+pub struct StatsText;
+
+/// Statistics of the current run, kept across lost lives and reset with the run.
+#[derive(Default, Clone)]
+pub struct RunStats {
+    food_eaten: u32,
+    time_survived: f32,
+    fastest_interval: Option<f32>,
+}
+impl RunStats {
+    fn summary(&self, length: usize) -> String {
+        format!(
+            "Food: {}  Length: {}  Time: {:.0}s  Fastest tick: {:.0} ms",
+            self.food_eaten,
+            length,
+            self.time_survived,
+            self.fastest_interval.unwrap_or_default() * 1000.0
+        )
+    }
+}
+
+/// Grace period after losing a life during which the snake cannot collide
+/// with itself.
+#[derive(Clone)]
+pub struct Invulnerable(Timer);
+impl Invulnerable {
+    fn active(&self) -> bool {
+        !self.0.finished
+    }
+}
+impl Default for Invulnerable {
+    fn default() -> Self {
+        Self(expired_timer(INVULNERABILITY_DURATION))
+    }
+}
+
+/// Source of all gameplay randomness, reseeded at the start of every run so a
+/// run can be replayed from its seed.
+#[derive(Clone)]
+pub struct GameRng(StdRng);
+impl Default for GameRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+impl GameRng {
+    /// Seeded from `--seed` when it is given, so whatever is drawn before the
+    /// first run reseeds it comes out the same too.
+    fn from_options(options: &Options) -> Self {
+        options
+            .seed
+            .map_or_else(Self::default, |seed| Self(StdRng::seed_from_u64(seed)))
+    }
+}
+
+/// What `--seed` and `--speed` pin down for every run, in place of a random
+/// seed and the difficulty's move interval. Replays keep their own seed.
+#[derive(Default)]
+pub struct RunOverrides {
+    seed: Option<u64>,
+    move_interval: Option<f32>,
+}
+impl RunOverrides {
+    fn from_options(options: &Options) -> Self {
+        Self {
+            seed: options.seed,
+            move_interval: options.speed.map(|ms| ms as f32 / 1000.0),
+        }
+    }
+}
+
+/// Move ticks since the current run started; recorded inputs are keyed by it.
+#[derive(Default, Clone)]
+pub struct RunTick(u32);
+
+/// Whether runs are being recorded or a recorded run is being played back.
+pub enum ReplayMode {
+    /// Records every run, saving it to the path (if any) once it ends.
+    Record(Option<PathBuf>),
+    /// Plays `replay` back in a loop; `frame` is the next clock delta to feed.
+    Playback { replay: Replay, frame: usize },
+}
+impl ReplayMode {
+    fn from_options(options: &Options) -> Self {
+        let path = options.replay.as_ref();
+        match path.map(|path| (path, Replay::load(path))) {
+            Some((_, Ok(replay))) => Self::Playback { replay, frame: 0 },
+            Some((path, Err(err))) => {
+                eprintln!("could not load replay {}: {}", path.display(), err);
+                Self::default()
+            }
+            None => Self::default(),
+        }
+    }
+}
+impl Default for ReplayMode {
+    fn default() -> Self {
+        Self::Record(Some(PathBuf::from(LAST_RUN_REPLAY)))
+    }
+}
+
+/// The run recorded so far.
+#[derive(Default, Clone)]
+pub struct ReplayRecorder(Replay);
+
+/// Highest scoring run recorded so far at the current difficulty, re-enacted
+/// by the ghost snake.
+#[derive(Default)]
+pub struct BestRun(Option<Replay>);
+
+/// Bundles the tunables that make a run easier or harder. The active one is
+/// fixed for the whole run; see `NextDifficulty` for choosing another.
+#[derive(Default, Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+impl Difficulty {
+    fn next(self) -> Self {
+        match self {
+            Self::Easy => Self::Normal,
+            Self::Normal => Self::Hard,
+            Self::Hard => Self::Easy,
+        }
+    }
+
+    fn move_interval(self) -> f32 {
+        match self {
+            Self::Easy => 0.2,
+            Self::Normal => 0.15,
+            Self::Hard => 0.1,
+        }
+    }
+
+    /// Whether the move interval shortens as the snake eats.
+    fn speeds_up(self) -> bool {
+        self != Self::Easy
+    }
+
+    fn food_spawn_interval(self) -> f32 {
+        match self {
+            Self::Easy => 12.0,
+            Self::Normal => 10.0,
+            Self::Hard => 8.0,
+        }
+    }
+
+    /// Seconds taken off the food spawn interval per food eaten.
+    fn food_spawn_shrink(self) -> f32 {
+        match self {
+            Self::Hard => 0.5,
+            _ => 0.0,
+        }
+    }
+
+    /// File the best run at this difficulty is kept in, next to the last run.
+    fn best_run_file(self) -> String {
+        format!("best_run_{:?}.replay", self).to_lowercase()
+    }
+}
+impl std::str::FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            _ => Err(format!("unknown difficulty {:?}", s)),
+        }
+    }
+}
+
+/// Difficulty the next run starts with, chosen with `--difficulty` or 1/2/3.
+#[derive(Default)]
+pub struct NextDifficulty(Difficulty);
+impl NextDifficulty {
+    /// `--difficulty` if given, otherwise `saved`.
+    fn from_options(options: &Options, saved: Difficulty) -> Self {
+        Self(options.difficulty.unwrap_or(saved))
+    }
+}
+
+/// Part of the ghost snake; 0 is the head, then the segments in order. Ghost
+/// entities carry a `Position` for rendering but nothing gameplay queries for.
+#[derive(Clone)]
+pub struct GhostSnake(usize);
+
+pub struct GhostVisible(bool);
+impl Default for GhostVisible {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Direction {
+    Left,
+    Up,
+    Right,
+    Down,
+}
+
+impl Direction {
+    const ALL: [Self; 4] = [Self::Left, Self::Up, Self::Right, Self::Down];
+
+    /// The cell next to `from` this way.
+    fn step(self, from: Position) -> Position {
+        match self {
+            Self::Left => Position {
+                x: from.x - 1,
+                ..from
+            },
+            Self::Right => Position {
+                x: from.x + 1,
+                ..from
+            },
+            Self::Up => Position {
+                y: from.y + 1,
+                ..from
+            },
+            Self::Down => Position {
+                y: from.y - 1,
+                ..from
+            },
+        }
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+        }
+    }
+}
+
+pub fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    (theme, arena): (Res<Theme>, Res<Arena>),
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let font = asset_server.load("fonts/DejaVuSans-Bold.ttf");
+    let slow_motion_pickup_material = materials.add(Color::rgb(1.0, 0.8, 0.0).into());
+    let speed_boost_pickup_material = materials.add(Color::rgb(0.2, 1.0, 0.4).into());
+    let food_magnet_material = materials.add(Color::rgb(0.75, 0.75, 0.8).into());
+    let arena_material = materials.add(theme.background().into());
+    commands
+        .spawn(Camera2dComponents::default())
+        .with(GameCamera)
+        .spawn(SpriteComponents {
+            material: arena_material.clone(),
+            ..Default::default()
+        })
+        .with(ArenaBackground)
+        .with(Size {
+            width: arena.width as f32,
+            height: arena.height as f32,
+        })
+        .spawn(UiCameraComponents::default())
+        .spawn(NodeComponents {
+            style: Style {
+                size: bevy::math::Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.6).into()),
+            draw: Draw {
+                is_visible: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(PauseOverlay)
+        .spawn(NodeComponents {
+            style: Style {
+                size: bevy::math::Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(1.0, 0.0, 0.0, 0.0).into()),
+            draw: Draw {
+                is_visible: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(PoisonFlash::default())
+        .spawn(NodeComponents {
+            style: Style {
+                size: bevy::math::Size::new(Val::Px(STAMINA_BAR_WIDTH), Val::Px(6.0)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: materials.add(Color::rgb(0.2, 0.8, 1.0).into()),
+            ..Default::default()
+        })
+        .with(StaminaBar)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: "x1".to_string(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 24.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(ComboText {
+            flash: expired_timer(COMBO_FLASH_DURATION),
+        })
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    top: Val::Px(40.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: format!("Lives: {}", STARTING_LIVES),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 24.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(LivesText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(40.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 24.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(ScoreText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(360.0),
+                    top: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 40.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(RoundTimerText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(200.0),
+                    top: Val::Px(370.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 40.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(Banner {
+            timer: expired_timer(BANNER_DURATION),
+        })
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(140.0),
+                    top: Val::Px(420.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(Banner {
+            timer: expired_timer(BANNER_DURATION),
+        })
+        .with(StatsText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 18.0,
+                    color: Color::rgb(0.8, 0.8, 0.8),
+                },
+            },
+            ..Default::default()
+        })
+        .with(DebugText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(350.0),
+                    top: Val::Px(300.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 120.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(CountdownText {
+            go: expired_timer(COUNTDOWN_GO_DURATION),
+        })
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(180.0),
+                    top: Val::Px(300.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 40.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(PromptText)
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(160.0),
+                    top: Val::Px(250.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 32.0,
+                    color: theme.text(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(NameEntryText);
+    let timed_effects = [
+        (PowerUp::SlowMotion, &slow_motion_pickup_material),
+        (PowerUp::SpeedBoost, &speed_boost_pickup_material),
+        (PowerUp::FoodMagnet, &food_magnet_material),
+    ];
+    for (row, (power_up, material)) in timed_effects.iter().enumerate() {
+        commands
+            .spawn(NodeComponents {
+                style: Style {
+                    size: bevy::math::Size::new(Val::Px(EFFECT_BAR_WIDTH), Val::Px(6.0)),
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        right: Val::Px(10.0),
+                        top: Val::Px(44.0 + row as f32 * 10.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                material: (*material).clone(),
+                draw: Draw {
+                    is_visible: false,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with(EffectBar(*power_up));
+    }
+    commands
+        .spawn(TextComponents {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    right: Val::Px(10.0),
+                    top: Val::Px(64.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 24.0,
+                    color: theme.text(),
+                },
+            },
+            draw: Draw {
+                is_visible: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(MultiplierBadge);
+    for row in 0..=LEADERBOARD_SIZE {
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(190.0),
+                        top: Val::Px(120.0 + row as f32 * 28.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: String::new(),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: 24.0,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(LeaderboardRow(row));
+    }
+    for row in 0..=Achievement::ALL.len() {
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(140.0),
+                        top: Val::Px(160.0 + row as f32 * 28.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: String::new(),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: 22.0,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(AchievementRow(row));
+    }
+    for row in 0..MenuItem::ALL.len().max(SettingsItem::ALL.len()) {
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(250.0),
+                        top: Val::Px(370.0 + row as f32 * 44.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: String::new(),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: 32.0,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(MenuRow(row));
+    }
+    commands.insert_resource(UiFont(font));
+    commands.insert_resource(Sounds::load(&asset_server));
+    commands.insert_resource(Music::load(&asset_server));
+    commands.insert_resource(SpriteAtlas::load(&asset_server));
+    commands.insert_resource(Materials {
+        arena_material,
+        head_material: materials.add(theme.head().into()),
+        rival_head_material: materials.add(Color::rgb(1.0, 0.55, 0.1).into()),
+        shielded_head_material: materials.add(Color::rgb(0.3, 0.8, 1.0).into()),
+        segment_gradient: (0..SEGMENT_GRADIENT_STEPS)
+            .map(|step| materials.add(theme.segment_shade(step).into()))
+            .collect(),
+        corner_gradient: (0..SEGMENT_GRADIENT_STEPS)
+            .map(|step| materials.add(theme.segment_shade(step).into()))
+            .collect(),
+        tail_material: materials.add(theme.segment_shade(SEGMENT_GRADIENT_STEPS - 1).into()),
+        food_material: materials.add(theme.food().into()),
+        double_food_material: materials.add(Color::rgb(0.2, 0.85, 0.85).into()),
+        golden_food_material: materials.add(Color::rgb(1.0, 0.75, 0.0).into()),
+        poison_food_material: materials.add(Color::rgb(0.4, 0.55, 0.05).into()),
+        portal_material: materials.add(Color::rgb(0.2, 0.4, 1.0).into()),
+        ghost_pickup_material: materials.add(Color::rgb(0.9, 0.9, 1.0).into()),
+        ghost_segment_material: materials.add(theme.ghost_segment().into()),
+        slow_motion_pickup_material,
+        speed_boost_pickup_material,
+        shield_pickup_material: materials.add(Color::rgb(0.3, 0.8, 1.0).into()),
+        shrink_potion_material: materials.add(Color::rgb(0.7, 0.3, 0.9).into()),
+        score_multiplier_material: materials.add(Color::rgb(1.0, 0.45, 0.75).into()),
+        food_magnet_material,
+        ghost_snake_material: materials.add(Color::rgba(0.8, 0.8, 1.0, 0.3).into()),
+        wall_material: materials.add(Color::rgb(0.55, 0.15, 0.1).into()),
+        hunter_material: materials.add(Color::rgb(1.0, 0.1, 0.1).into()),
+    });
+}
+
+pub fn game_setup(
+    mut commands: Commands,
+    materials: Res<Materials>,
+    (portals, obstacles): (Res<Portals>, Res<Obstacles>),
+) {
+    spawn_layout(&mut commands, &materials, &portals, &obstacles);
+}
+
+/// Spawns the portals and interior walls of the level.
+pub fn spawn_layout(
+    commands: &mut Commands,
+    materials: &Materials,
+    portals: &Portals,
+    obstacles: &Obstacles,
+) {
+    for position in portals.0.iter().flat_map(|(a, b)| vec![*a, *b]) {
+        commands
+            .spawn(SpriteComponents {
+                material: materials.portal_material.clone(),
+                ..Default::default()
+            })
+            .with(Portal)
+            .with(position)
+            .with(Size::square(0.9));
+    }
+    spawn_walls(commands, materials, &obstacles.0);
+}
+
+/// Spawns an interior wall on each of `cells`.
+pub fn spawn_walls(commands: &mut Commands, materials: &Materials, cells: &[Position]) {
+    for position in cells {
+        commands
+            .spawn(SpriteComponents {
+                material: materials.wall_material.clone(),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(Obstacle)
+            .with(*position)
+            .with(Size::square(1.0));
+    }
+}
+
+/// Begins a run: saves the recording of the previous one, reseeds the RNG
+/// (from the replay when playing one back), resets what the run's timing
+/// depends on, builds the maze in `GameMode::Maze` and spawns the first food.
+#[allow(clippy::too_many_arguments)]
+pub fn start_run(
+    mut commands: Commands,
+    (mut reader, run_start_events): (
+        Local<EventReader<RunStartEvent>>,
+        Res<Events<RunStartEvent>>,
+    ),
+    (materials, mode, versus, daily): (
+        Res<Materials>,
+        Res<GameMode>,
+        Res<VersusPlayers>,
+        Res<DailyChallenge>,
+    ),
+    (arena, layout, portals, mut obstacles): (
+        Res<Arena>,
+        Res<ArenaLayout>,
+        Res<Portals>,
+        ResMut<Obstacles>,
+    ),
+    (mobile_chance, food_table, start): (Res<MobileFoodChance>, Res<FoodTable>, Res<SnakeStart>),
+    (mut replay_mode, mut recorder, mut best_run, mut rng): (
+        ResMut<ReplayMode>,
+        ResMut<ReplayRecorder>,
+        ResMut<BestRun>,
+        ResMut<GameRng>,
+    ),
+    (mut run_tick, mut snake_timer, profiles): (
+        ResMut<RunTick>,
+        ResMut<SnakeMoveTimer>,
+        Res<Profiles>,
+    ),
+    (mut food_spawn_timer, mut countdown): (ResMut<FoodSpawnTimer>, ResMut<Countdown>),
+    (mut difficulty, next_difficulty, mut base_interval, overrides): (
+        ResMut<Difficulty>,
+        Res<NextDifficulty>,
+        ResMut<BaseMoveInterval>,
+        Res<RunOverrides>,
+    ),
+    mut in_maze: Local<bool>,
+    obstacle_entities: Query<With<Obstacle, Entity>>,
+) {
+    if reader.iter(&run_start_events).next().is_none() {
+        return;
+    }
+    let (seed, next) = match &mut *replay_mode {
+        ReplayMode::Record(path) => {
+            let finished = std::mem::take(&mut recorder.0);
+            if !finished.frames.is_empty() {
+                let best = best_run
+                    .0
+                    .as_ref()
+                    .is_none_or(|best| finished.score > best.score);
+                if let Some(path) = path {
+                    let mut saves = vec![path.clone()];
+                    if best {
+                        saves.push(path.with_file_name(finished.difficulty.best_run_file()));
+                    }
+                    for path in saves {
+                        if let Err(err) = finished.save(&path) {
+                            eprintln!("could not save replay {}: {}", path.display(), err);
+                        }
+                    }
+                }
+                if best {
+                    best_run.0 = Some(finished);
+                }
+            }
+            if next_difficulty.0 != *difficulty || best_run.0.is_none() {
+                best_run.0 = path.as_ref().and_then(|path| {
+                    Replay::load(&path.with_file_name(next_difficulty.0.best_run_file())).ok()
+                });
+            }
+            (
+                daily
+                    .0
+                    .map(daily::daily_seed)
+                    .or(overrides.seed)
+                    .unwrap_or_else(|| thread_rng().gen()),
+                next_difficulty.0,
+            )
+        }
+        ReplayMode::Playback { replay, frame } => {
+            *frame = 0;
+            (replay.seed, replay.difficulty)
+        }
+    };
+    *difficulty = next;
+    base_interval.0 = overrides
+        .move_interval
+        .unwrap_or_else(|| difficulty.move_interval());
+    rng.0 = StdRng::seed_from_u64(seed);
+    recorder.0 = Replay {
+        seed,
+        difficulty: *difficulty,
+        player: profiles.player(),
+        ..Default::default()
+    };
+    run_tick.0 = 0;
+    snake_timer.0.reset();
+    *food_spawn_timer = FoodSpawnTimer(Timer::from_seconds(difficulty.food_spawn_interval(), true));
+    countdown.0.reset();
+    // A maze run swaps the level's walls for a fresh maze; the next run in
+    // any other mode puts them back.
+    let walls = match (*mode == GameMode::Maze, *in_maze) {
+        (true, _) => Some(maze::generate(&arena, &portals, start.0, &mut rng.0)),
+        (false, true) => Some(layout.fit(&arena).2 .0),
+        (false, false) => None,
+    };
+    *in_maze = *mode == GameMode::Maze;
+    if let Some(walls) = walls {
+        for ent in obstacle_entities.iter() {
+            commands.despawn(ent);
+        }
+        spawn_walls(&mut commands, &materials, &walls);
+        obstacles.0 = walls;
+    }
+    let mut occupied: HashSet<Position> =
+        portals.0.iter().flat_map(|(a, b)| vec![*a, *b]).collect();
+    occupied.extend(&obstacles.0);
+    occupied.extend(start.cells(mode.players(*versus), &arena));
+    if let Some(position) = arena.random_free_cell(&occupied, &mut rng.0) {
+        let food = food_table.pick(&mut rng.0);
+        let mobile = rng.0.gen::<f32>() < mobile_chance.0;
+        spawn_food(&mut commands, &materials, food, position, mobile);
+    }
+}
+
+/// Spawns a snake for every placement, each from `SnakeStart::placements`.
+fn spawn_snakes(
+    commands: &mut Commands,
+    materials: &Materials,
+    placements: Vec<(Player, Position, Direction)>,
+) {
+    for (player, start, direction) in placements {
+        spawn_snake(commands, materials, player, start, direction);
+    }
+}
+
+/// Spawns `player`'s snake with its head on `start`, heading in `direction`,
+/// and a single segment behind it.
+pub fn spawn_snake(
+    commands: &mut Commands,
+    materials: &Materials,
+    player: Player,
+    start: Position,
+    direction: Direction,
+) {
+    let neck = neck_behind(start, direction);
+    let mut segments = SnakeSegments::default();
+    let first_segment = spawn_segment(commands, &materials.segment_material(0, 1), neck);
+    segments.push(first_segment, neck);
+    commands
+        .spawn(SpriteComponents {
+            material: materials.head_material(player),
+            sprite: Sprite::new(Vec2::new(10.0, 10.0)),
+            ..Default::default()
+        })
+        .with(SnakeHead {
+            direction,
+            try_direction: direction,
+            queued_turns: VecDeque::new(),
+        })
+        .with(player)
+        .with(segments)
+        .with(Hunger::default())
+        .with(start)
+        .with(Size::square(0.8));
+}
+
+pub fn spawn_segment(
+    commands: &mut Commands,
+    material: &Handle<ColorMaterial>,
+    position: Position,
+) -> Entity {
+    commands
+        .spawn(SpriteComponents {
+            material: material.clone(),
+            ..SpriteComponents::default()
+        })
+        .with(SnakeSegment)
+        .with(position)
+        .with(Size::square(SEGMENT_SIZE));
+    commands.current_entity().unwrap()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn snake_movement(
+    (snake_timer, state): (ResMut<SnakeMoveTimer>, Res<AppState>),
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    (bounds, rules, obstacles): (Res<SafeBounds>, Res<GameRules>, Res<Obstacles>),
+    portals: Res<Portals>,
+    mut effects: ResMut<ActiveEffects>,
+    invulnerable: Res<Invulnerable>,
+    mut run_stats: ResMut<RunStats>,
+    (mut recorder, mut run_tick): (ResMut<ReplayRecorder>, ResMut<RunTick>),
+    mut heads: Query<(Entity, &Player, &mut SnakeHead, &mut SnakeSegments)>,
+    mut positions: Query<&mut Position>,
+) {
+    if *state != AppState::Playing {
+        return;
+    }
+    let interval = snake_timer.0.duration;
+    if run_stats
+        .fastest_interval
+        .is_none_or(|fastest| interval < fastest)
+    {
+        run_stats.fastest_interval = Some(interval);
+    }
+    let intangible = effects.active(PowerUp::Ghost) || invulnerable.active();
+    let mut moved = Vec::new();
+    for (head_entity, player, mut head, mut segments) in heads.iter_mut() {
+        let mut head_pos = positions.get_mut(head_entity).unwrap();
+        // Only player one's run is recorded; the rival in versus has none.
+        let recorded = *player == Player::One;
+        // Replays and other systems set `try_direction` directly, so reversals
+        // are refused here as well as in `handle_movement`.
+        let dir = head.try_direction;
+        if dir != head.direction && dir != head.direction.opposite() {
+            if recorded {
+                recorder.0.inputs.push((run_tick.0, dir));
+            }
+            head.direction = dir;
+        }
+        if let Some(next) = head.queued_turns.pop_front() {
+            head.try_direction = next;
+        }
+        let last_head_pos = *head_pos;
+        match &head.direction {
+            Direction::Left => {
+                head_pos.x -= 1;
+            }
+            Direction::Right => {
+                head_pos.x += 1;
+            }
+            Direction::Up => {
+                head_pos.y += 1;
+            }
+            Direction::Down => {
+                head_pos.y -= 1;
+            }
+        };
+        if let Some(exit) = portals.exit(&head_pos) {
+            *head_pos = exit;
+        }
+        if rules.wrap_around {
+            *head_pos = bounds.wrap(&head_pos);
+        }
+        let hit_wall = !bounds.contains(&head_pos) || obstacles.0.contains(&head_pos);
+        // The head is tested against where every segment ends up: the cell
+        // the tail leaves is free to move into, unless growth stacked another
+        // segment on it.
+        let hit_self = !intangible && segments.blocks(&head_pos);
+        if (hit_wall || hit_self) && effects.consume(PowerUp::Shield) {
+            // The shield takes the hit and the snake sits this move out.
+            *head_pos = last_head_pos;
+            if recorded {
+                recorder.0.steps.push((last_head_pos, segments.len() + 1));
+            }
+            moved.push((head_entity, *player, last_head_pos));
+            continue;
+        }
+        if hit_wall {
+            game_over_events.send(GameOverEvent {
+                player: *player,
+                reason: GameOverReason::HitWall,
+                position: last_head_pos,
+            });
+        }
+        let new_head_pos = *head_pos;
+        if let Some(neck) = segments.advance(last_head_pos) {
+            *positions.get_mut(neck).unwrap() = last_head_pos;
+        }
+        if hit_self {
+            game_over_events.send(GameOverEvent {
+                player: *player,
+                reason: GameOverReason::HitSelf,
+                position: new_head_pos,
+            });
+        }
+        if recorded {
+            recorder.0.steps.push((new_head_pos, segments.len() + 1));
+        }
+        moved.push((head_entity, *player, new_head_pos));
+    }
+    // Snakes are tested against each other once all of them have moved, so
+    // none sees another's body from before this move. Meeting head on kills
+    // both.
+    if !intangible {
+        for (snake, player, position) in &moved {
+            let hit_rival = moved
+                .iter()
+                .any(|(other, _, other_pos)| other != snake && other_pos == position)
+                || heads
+                    .iter_mut()
+                    .any(|(other, _, _, segments)| other != *snake && segments.contains(position));
+            if hit_rival {
+                game_over_events.send(GameOverEvent {
+                    player: *player,
+                    reason: GameOverReason::HitRival,
+                    position: *position,
+                });
+            }
+        }
+    }
+    run_tick.0 += 1;
+}
+
+/// Respawns the snake after a death that leaves lives to spare and shows the
+/// game over screen once the run is over. A restart clears the arena and
+/// begins the next run.
+#[allow(clippy::too_many_arguments)]
+pub fn game_over(
+    mut commands: Commands,
+    (mut reader, game_over_events): (
+        Local<EventReader<GameOverEvent>>,
+        Res<Events<GameOverEvent>>,
+    ),
+    (mut end_run_reader, end_run_events): (
+        Local<EventReader<EndRunEvent>>,
+        Res<Events<EndRunEvent>>,
+    ),
+    (materials, start, arena, mut run_start_events): (
+        Res<Materials>,
+        Res<SnakeStart>,
+        Res<Arena>,
+        ResMut<Events<RunStartEvent>>,
+    ),
+    (mode, versus, mut round_timer, mut state): (
+        Res<GameMode>,
+        Res<VersusPlayers>,
+        ResMut<RoundTimer>,
+        ResMut<AppState>,
+    ),
+    (mut effects, mut boost): (ResMut<ActiveEffects>, ResMut<Boost>),
+    (mut score, mut rival_scores, mut combo): (ResMut<Score>, ResMut<RivalScores>, ResMut<Combo>),
+    (mut lives, mut invulnerable): (ResMut<Lives>, ResMut<Invulnerable>),
+    (mut run_stats, mut countdown): (ResMut<RunStats>, ResMut<Countdown>),
+    segments: Query<(Entity, &SnakeSegment)>,
+    food: Query<(Entity, &Food)>,
+    pickups: Query<(Entity, &PowerUp)>,
+    heads: Query<(Entity, &Player, &SnakeSegments)>,
+    hunters: Query<With<Hunter, Entity>>,
+    mut banners: Query<(&mut Text, &mut Banner, Option<&StatsText>)>,
+) {
+    let deaths: Vec<GameOverEvent> = reader.iter(&game_over_events).copied().collect();
+    let death = deaths.first().copied();
+    let end_run = end_run_reader.iter(&end_run_events).next();
+    if death.is_none() && end_run.is_none() {
+        return;
+    }
+    for (ent, _) in segments.iter() {
+        commands.despawn(ent);
+    }
+    for ent in hunters.iter() {
+        commands.despawn(ent);
+    }
+    for (ent, _, _) in heads.iter() {
+        commands.despawn(ent);
+    }
+    if let Some(EndRunEvent::Restart) = end_run {
+        for (mut text, _, _) in banners.iter_mut() {
+            text.value.clear();
+        }
+        for (ent, _) in food.iter() {
+            commands.despawn_recursive(ent);
+        }
+        for (ent, _) in pickups.iter() {
+            commands.despawn(ent);
+        }
+        *effects = ActiveEffects::default();
+        *boost = Boost::default();
+        *score = Score::default();
+        *rival_scores = RivalScores::default();
+        *combo = Combo::default();
+        *lives = Lives::default();
+        *invulnerable = Invulnerable::default();
+        *round_timer = RoundTimer::default();
+        *run_stats = RunStats::default();
+        *state = AppState::Playing;
+        run_start_events.send(RunStartEvent);
+        countdown.0.reset();
+        spawn_snakes(
+            &mut commands,
+            &materials,
+            start.placements(mode.players(*versus), &arena),
+        );
+        return;
+    }
+    let run_over = end_run.is_some()
+        || match *mode {
+            GameMode::TimeAttack => {
+                round_timer.0.elapsed += TIME_ATTACK_DEATH_PENALTY;
+                false
+            }
+            GameMode::Classic | GameMode::Maze | GameMode::Coop => {
+                lives.0 = lives.0.saturating_sub(1);
+                lives.0 == 0
+            }
+            GameMode::ShrinkingArena | GameMode::Versus | GameMode::VersusBot => true,
+        };
+    if run_over {
+        let length =
+            player_one_length(heads.iter().map(|(_, player, segments)| (player, segments)));
+        for (mut text, mut banner, stats_text) in banners.iter_mut() {
+            text.value = match (end_run, death) {
+                _ if stats_text.is_some() => run_stats.summary(length),
+                (Some(_), _) => format!("Time's up! Score: {}", score.0),
+                (None, Some(_)) if mode.rivals() => {
+                    let players = mode.players(*versus);
+                    let scores: Vec<u32> = players
+                        .iter()
+                        .map(|player| versus_score(*player, &score, &rival_scores))
+                        .collect();
+                    versus_result(*mode, &deaths, players, &scores)
+                }
+                (None, Some(death)) => format!("{}! Score: {}", death, score.0),
+                (None, None) => String::new(),
+            };
+            banner.timer.reset();
+        }
+        *state = AppState::GameOver;
+    } else {
+        invulnerable.0.reset();
+        countdown.0.reset();
+        spawn_snakes(
+            &mut commands,
+            &materials,
+            start.placements(mode.players(*versus), &arena),
+        );
+    }
+}
+
+/// Who won a versus round in `mode` between `players`, given every death
+/// that ended it and each player's score, in the same order. Whoever did not
+/// crash wins; when several did not, the one of them with the best score.
+fn versus_result(
+    mode: GameMode,
+    deaths: &[GameOverEvent],
+    players: &[Player],
+    scores: &[u32],
+) -> String {
+    let survivors: Vec<(Player, u32)> = players
+        .iter()
+        .copied()
+        .zip(scores.iter().copied())
+        .filter(|(player, _)| deaths.iter().all(|death| death.player != *player))
+        .collect();
+    let best = survivors.iter().map(|(_, score)| *score).max();
+    let leaders: Vec<Player> = survivors
+        .iter()
+        .filter(|(_, score)| Some(*score) == best)
+        .map(|(player, _)| *player)
+        .collect();
+    let against_bot = mode == GameMode::VersusBot;
+    let winner = match leaders[..] {
+        [] if players.len() == 2 => "Both crashed! It's a draw".to_string(),
+        [] => "Everyone crashed! It's a draw".to_string(),
+        [Player::One] if against_bot => "You win".to_string(),
+        [_] if against_bot => "The computer wins".to_string(),
+        [leader] => format!("{} wins", leader),
+        _ => "It's a draw".to_string(),
+    };
+    let scores: Vec<String> = scores.iter().map(|score| score.to_string()).collect();
+    format!("{}! {}", winner, scores.join(" to "))
+}
+
+pub fn countdown(
+    time: Res<Time>,
+    clock: Res<GameClock>,
+    mut countdown: ResMut<Countdown>,
+    mut texts: Query<(&mut Text, &mut CountdownText)>,
+) {
+    countdown.0.tick(clock.delta_seconds);
+    for (mut text, mut countdown_text) in texts.iter_mut() {
+        if countdown.0.just_finished {
+            countdown_text.go.reset();
+        }
+        countdown_text.go.tick(time.delta_seconds);
+        let value = if countdown.active() {
+            ((countdown.0.duration - countdown.0.elapsed).ceil() as u32).to_string()
+        } else if !countdown_text.go.finished {
+            "GO".to_string()
+        } else {
+            String::new()
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// Counts down the post-respawn grace period, blinking the head while it lasts.
+pub fn invulnerability(
+    clock: Res<GameClock>,
+    countdown: Res<Countdown>,
+    mut invulnerable: ResMut<Invulnerable>,
+    mut heads: Query<With<SnakeHead, &mut Draw>>,
+) {
+    if !countdown.active() {
+        invulnerable.0.tick(clock.delta_seconds);
+    }
+    let visible = !invulnerable.active()
+        || (invulnerable.0.elapsed / (2.0 * INVULNERABILITY_BLINK)).fract() < 0.5;
+    for mut draw in heads.iter_mut() {
+        draw.is_visible = visible;
+    }
+}
+
+pub fn round_timer(
+    clock: Res<GameClock>,
+    mode: Res<GameMode>,
+    countdown: Res<Countdown>,
+    mut round_timer: ResMut<RoundTimer>,
+    mut end_run_events: ResMut<Events<EndRunEvent>>,
+    mut texts: Query<With<RoundTimerText, &mut Text>>,
+) {
+    if *mode != GameMode::TimeAttack {
+        return;
+    }
+    if !countdown.active() {
+        round_timer.0.tick(clock.delta_seconds);
+    }
+    if round_timer.0.just_finished {
+        end_run_events.send(EndRunEvent::TimeUp);
+    }
+    let remaining = (round_timer.0.duration - round_timer.0.elapsed)
+        .max(0.0)
+        .ceil() as u32;
+    for mut text in texts.iter_mut() {
+        let value = format!("{:02}:{:02}", remaining / 60, remaining % 60);
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub fn run_clock(
+    clock: Res<GameClock>,
+    state: Res<AppState>,
+    countdown: Res<Countdown>,
+    mut run_stats: ResMut<RunStats>,
+) {
+    if *state == AppState::Playing && !countdown.active() {
+        run_stats.time_survived += clock.delta_seconds;
+    }
+}
+
+/// Shows the banners once the `DeathAnimation` has played, and clears them
+/// when their time is up.
+pub fn banner(
+    time: Res<Time>,
+    death_animation: Res<DeathAnimation>,
+    mut banners: Query<(&mut Text, &mut Banner, &mut Draw)>,
+) {
+    for (mut text, mut banner, mut draw) in banners.iter_mut() {
+        draw.is_visible = !death_animation.playing();
+        if death_animation.playing() {
+            continue;
+        }
+        banner.timer.tick(time.delta_seconds);
+        if banner.timer.just_finished {
+            text.value.clear();
+        }
+    }
+}
+
+/// Advances the game clock, recording every delta or, when playing a replay
+/// back, feeding the recorded ones instead of real time. A paused replay holds
+/// its place.
+pub fn game_clock(
+    time: Res<Time>,
+    state: Res<AppState>,
+    name_entry: Res<NameEntry>,
+    mut replay_mode: ResMut<ReplayMode>,
+    mut recorder: ResMut<ReplayRecorder>,
+    mut clock: ResMut<GameClock>,
+) {
+    clock.delta_seconds = match &mut *replay_mode {
+        ReplayMode::Record(_) => {
+            let delta = if *state != AppState::Playing || name_entry.active() {
+                0.0
+            } else {
+                time.delta_seconds
+            };
+            recorder.0.frames.push(delta);
+            delta
+        }
+        ReplayMode::Playback { .. } if *state != AppState::Playing => 0.0,
+        ReplayMode::Playback { replay, frame } => {
+            *frame += 1;
+            replay.frames.get(*frame - 1).copied().unwrap_or_default()
+        }
+    };
+}
+
+/// Moves between screens on key presses: the pause keys (Escape or P by
+/// default) pause and resume, and the restart keys (Enter or Space) resume
+/// too and leave the game over screen, which asks `game_over` for a fresh
+/// run. The arena is dimmed while paused and after the run ends, once the
+/// `DeathAnimation` has played, which restarting waits for too. The settings
+/// keys (O) open the settings from the pause screen, which stays dimmed behind
+/// them. A replay being played back skips the menu and the game over screen,
+/// starting its run straight away.
+///
+/// The stall pause stops the game when a frame comes more than `STALL_PAUSE`
+/// seconds after the last, e.g. after the window was minimized or dragged. It is
+/// not a focus pause: Bevy 0.3's winit runner drops focus events, and a
+/// window that loses focus but keeps drawing plays on. Resuming always takes
+/// an explicit key press so the player has time to get ready.
+pub fn app_state(
+    time: Res<Time>,
+    (keyboard_input, bindings): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    (replay_mode, name_entry, death_animation): (
+        Res<ReplayMode>,
+        Res<NameEntry>,
+        Res<DeathAnimation>,
+    ),
+    (mut state, mut cursor, mut origin): (
+        ResMut<AppState>,
+        ResMut<MenuCursor>,
+        ResMut<SettingsOrigin>,
+    ),
+    mut end_run_events: ResMut<Events<EndRunEvent>>,
+    mut texts: Query<With<PromptText, &mut Text>>,
+    mut overlays: Query<With<PauseOverlay, &mut Draw>>,
+) {
+    let restart = bindings.just_pressed(&keyboard_input, Action::Restart);
+    let pause = bindings.just_pressed(&keyboard_input, Action::Pause);
+    let settings = bindings.just_pressed(&keyboard_input, Action::Settings);
+    let playback = matches!(*replay_mode, ReplayMode::Playback { .. });
+    match *state {
+        AppState::Menu | AppState::GameOver if playback => {
+            end_run_events.send(EndRunEvent::Restart)
+        }
+        AppState::Playing if pause || time.delta_seconds > STALL_PAUSE => *state = AppState::Paused,
+        AppState::Paused if restart || pause => *state = AppState::Playing,
+        AppState::Paused if settings => {
+            *state = AppState::Settings;
+            origin.0 = AppState::Paused;
+            cursor.0 = 0;
+        }
+        AppState::GameOver if restart && !name_entry.active() && !death_animation.playing() => {
+            end_run_events.send(EndRunEvent::Restart)
+        }
+        _ => {}
+    }
+    let dying = *state == AppState::GameOver && death_animation.playing();
+    for mut text in texts.iter_mut() {
+        let value = if dying { "" } else { state.prompt() };
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+    for mut draw in overlays.iter_mut() {
+        draw.is_visible = match *state {
+            AppState::Paused => true,
+            AppState::Settings => origin.0 == AppState::Paused,
+            AppState::GameOver => !dying,
+            _ => false,
+        };
+    }
+}
+
+/// Runs the main menu and the settings screen. Up and Down (or W and S) move
+/// the cursor and Enter or Space picks the highlighted line: Play asks
+/// `game_over` for the first run, Daily does too with today's challenge seed,
+/// and Quit closes the game. On the settings
+/// screen, picking a setting switches it to its next value straight away, and
+/// Back or Escape returns to the menu or the pause screen, whichever it was
+/// opened from. The mode and arena size only change when opened from the menu.
+///
+/// The name prompt takes letters, digits, Space and Minus, Backspace deletes
+/// the last one, and Enter makes the profile and moves on to the menu, or back
+/// to the settings when it was opened from there.
+#[allow(clippy::too_many_arguments)]
+pub fn menu(
+    keyboard_input: Res<Input<KeyCode>>,
+    (mut state, mut cursor, mut origin): (
+        ResMut<AppState>,
+        ResMut<MenuCursor>,
+        ResMut<SettingsOrigin>,
+    ),
+    (arena, mut rules, mut resize_events): (
+        Res<Arena>,
+        ResMut<GameRules>,
+        ResMut<Events<ResizeArenaEvent>>,
+    ),
+    (mut next_difficulty, mut mode, mut themes): (
+        ResMut<NextDifficulty>,
+        ResMut<GameMode>,
+        ResMut<Themes>,
+    ),
+    (mut base_interval, mut overrides): (ResMut<BaseMoveInterval>, ResMut<RunOverrides>),
+    (mut volume, mut shake, mut patterns): (
+        ResMut<Volume>,
+        ResMut<ShakeIntensity>,
+        ResMut<Patterns>,
+    ),
+    (mut daily, daily_best): (ResMut<DailyChallenge>, Res<DailyBest>),
+    (mut profiles, mut profile_entry): (ResMut<Profiles>, ResMut<ProfileEntry>),
+    (mut end_run_events, mut app_exit_events): (
+        ResMut<Events<EndRunEvent>>,
+        ResMut<Events<AppExit>>,
+    ),
+    mut rows: Query<(&MenuRow, &mut Text)>,
+) {
+    let back_to = origin.0;
+    let mid_run = back_to == AppState::Paused;
+    let leave_settings = |state: &mut AppState, cursor: &mut MenuCursor| {
+        *state = back_to;
+        cursor.0 = if back_to == AppState::Menu {
+            MenuItem::ALL
+                .iter()
+                .position(|item| *item == MenuItem::Settings)
+                .unwrap()
+        } else {
+            0
+        };
+    };
+    // Counted before the prompt can close, so the Enter that confirms a name
+    // does not also pick a line.
+    let lines = match *state {
+        AppState::Menu => MenuItem::ALL.len(),
+        AppState::Settings => SettingsItem::ALL.len(),
+        _ => 0,
+    };
+    if *state == AppState::Profile {
+        let pressed = |key| keyboard_input.just_pressed(key);
+        let shift =
+            keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        profile_entry.type_chars(
+            keyboard_input
+                .get_just_pressed()
+                .filter_map(|key| profile::key_char(*key, shift)),
+        );
+        if pressed(KeyCode::Back) {
+            profile_entry.0.pop();
+        }
+        if pressed(KeyCode::Return) {
+            if let Some(name) = profile_entry.name() {
+                let first = profiles.names.is_empty();
+                profiles.add(name);
+                profile_entry.0.clear();
+                *state = if first {
+                    AppState::Menu
+                } else {
+                    AppState::Settings
+                };
+                cursor.0 = if first {
+                    0
+                } else {
+                    SettingsItem::Player.line()
+                };
+            }
+        } else if pressed(KeyCode::Escape) && !profiles.names.is_empty() {
+            profile_entry.0.clear();
+            *state = AppState::Settings;
+            cursor.0 = SettingsItem::NewPlayer.line();
+        }
+    }
+    if lines > 0 {
+        let pressed = |keys: &[KeyCode]| keys.iter().any(|key| keyboard_input.just_pressed(*key));
+        if pressed(&[KeyCode::Up, KeyCode::W]) {
+            cursor.0 = (cursor.0 + lines - 1) % lines;
+        } else if pressed(&[KeyCode::Down, KeyCode::S]) {
+            cursor.0 = (cursor.0 + 1) % lines;
+        }
+        let pick = pressed(&[KeyCode::Return, KeyCode::Space]);
+        match *state {
+            AppState::Menu if pick => match MenuItem::ALL[cursor.0] {
+                MenuItem::Play => {
+                    daily.0 = None;
+                    end_run_events.send(EndRunEvent::Restart);
+                }
+                MenuItem::Daily => {
+                    daily.0 = Some(daily::today());
+                    end_run_events.send(EndRunEvent::Restart);
+                }
+                MenuItem::Settings => {
+                    *state = AppState::Settings;
+                    origin.0 = AppState::Menu;
+                    cursor.0 = 0;
+                }
+                MenuItem::Quit => app_exit_events.send(AppExit),
+            },
+            AppState::Settings if pick => match SettingsItem::ALL[cursor.0] {
+                item if item.between_runs() && mid_run => {}
+                SettingsItem::Difficulty => next_difficulty.0 = next_difficulty.0.next(),
+                // `slow_motion` derives the move timer from the base interval
+                // every frame, so the new speed holds from the next move; the
+                // override keeps it for the runs after this one.
+                SettingsItem::Speed => {
+                    base_interval.0 = next_move_interval(base_interval.0);
+                    overrides.move_interval = Some(base_interval.0);
+                }
+                SettingsItem::Mode => *mode = mode.next(),
+                SettingsItem::Arena => {
+                    resize_events.send(ResizeArenaEvent(next_arena_size(&arena)))
+                }
+                SettingsItem::Wrap => rules.wrap_around = !rules.wrap_around,
+                SettingsItem::Theme => themes.next(),
+                SettingsItem::Player => profiles.next(),
+                SettingsItem::NewPlayer => {
+                    *state = AppState::Profile;
+                    cursor.0 = 0;
+                }
+                SettingsItem::Volume => volume.master = sound::next_level(volume.master),
+                SettingsItem::Music => volume.music = sound::next_level(volume.music),
+                SettingsItem::Effects => volume.effects = sound::next_level(volume.effects),
+                SettingsItem::Shake => shake.0 = sound::next_level(shake.0),
+                SettingsItem::Patterns => patterns.0 = !patterns.0,
+                SettingsItem::Back => leave_settings(&mut state, &mut cursor),
+            },
+            AppState::Settings if pressed(&[KeyCode::Escape]) => {
+                leave_settings(&mut state, &mut cursor)
+            }
+            _ => {}
+        }
+    }
+    for (row, mut text) in rows.iter_mut() {
+        let label = match *state {
+            AppState::Menu => MenuItem::ALL.get(row.0).map(|item| match item {
+                MenuItem::Daily => match daily_best.on(daily::today()) {
+                    Some(best) => format!("Daily (best {})", best),
+                    None => "Daily".to_string(),
+                },
+                _ => format!("{:?}", item),
+            }),
+            AppState::Settings => SettingsItem::ALL
+                .get(row.0)
+                .map(|item| match item {
+                    SettingsItem::Difficulty => format!("Difficulty: {:?}", next_difficulty.0),
+                    SettingsItem::Speed => {
+                        format!("Speed: {:.0} ms a move", base_interval.0 * 1000.0)
+                    }
+                    SettingsItem::Mode => format!("Mode: {:?}", *mode),
+                    SettingsItem::Arena => format!("Arena: {}x{}", arena.width, arena.height),
+                    SettingsItem::Wrap if rules.wrap_around => "Wrap: On".to_string(),
+                    SettingsItem::Wrap => "Wrap: Off".to_string(),
+                    SettingsItem::Theme => format!("Theme: {}", themes.current().name),
+                    SettingsItem::Player => format!("Player: {}", profiles.player()),
+                    SettingsItem::NewPlayer => "New player".to_string(),
+                    SettingsItem::Volume => {
+                        let muted = if volume.muted { " (muted)" } else { "" };
+                        format!("Volume: {:.0}%{}", volume.master * 100.0, muted)
+                    }
+                    SettingsItem::Music => format!("Music: {:.0}%", volume.music * 100.0),
+                    SettingsItem::Effects => format!("Effects: {:.0}%", volume.effects * 100.0),
+                    SettingsItem::Shake if shake.0 <= 0.0 => "Screen shake: Off".to_string(),
+                    SettingsItem::Shake => format!("Screen shake: {:.0}%", shake.0 * 100.0),
+                    SettingsItem::Patterns if patterns.0 => "Patterns: On".to_string(),
+                    SettingsItem::Patterns => "Patterns: Off".to_string(),
+                    SettingsItem::Back => "Back".to_string(),
+                })
+                .map(|label| {
+                    let locked = mid_run && SettingsItem::ALL[row.0].between_runs();
+                    if locked {
+                        format!("{} (from the menu)", label)
+                    } else {
+                        label
+                    }
+                }),
+            AppState::Profile if row.0 == 0 => Some(format!("Name: {}_", profile_entry.0)),
+            _ => None,
+        };
+        let value = match label {
+            Some(label) if row.0 == cursor.0 => format!("> {}", label),
+            Some(label) => format!("  {}", label),
+            None => String::new(),
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// The move interval after `interval` in `MOVE_INTERVALS`: the next shorter
+/// one, or the longest after the shortest.
+fn next_move_interval(interval: f32) -> f32 {
+    MOVE_INTERVALS
+        .iter()
+        .copied()
+        .find(|next| *next < interval - f32::EPSILON)
+        .unwrap_or(MOVE_INTERVALS[0])
+}
+
+/// The square arena after `arena` in `ARENA_SIZES`: the next larger one, or
+/// the smallest after the largest.
+fn next_arena_size(arena: &Arena) -> Arena {
+    let side = ARENA_SIZES
+        .iter()
+        .copied()
+        .find(|side| *side > arena.width)
+        .unwrap_or(ARENA_SIZES[0]);
+    Arena {
+        width: side,
+        height: side,
+    }
+}
+
+/// Gives the arena the size picked on the settings screen: the safe bounds
+/// and background follow, and the start, walls and portals are those of the
+/// `ArenaLayout` that fit, so a larger size brings back what a smaller one
+/// left out. A maze comes back with the next maze run.
+#[allow(clippy::too_many_arguments)]
+pub fn resize_arena(
+    mut commands: Commands,
+    (mut reader, resize_events): (
+        Local<EventReader<ResizeArenaEvent>>,
+        Res<Events<ResizeArenaEvent>>,
+    ),
+    materials: Res<Materials>,
+    (mut arena, mut bounds, mut start): (ResMut<Arena>, ResMut<SafeBounds>, ResMut<SnakeStart>),
+    (layout, mut portals, mut obstacles): (Res<ArenaLayout>, ResMut<Portals>, ResMut<Obstacles>),
+    portal_entities: Query<With<Portal, Entity>>,
+    obstacle_entities: Query<With<Obstacle, Entity>>,
+    mut backgrounds: Query<With<ArenaBackground, &mut Size>>,
+) {
+    let resized = match reader.iter(&resize_events).last() {
+        Some(ResizeArenaEvent(resized)) => *resized,
+        None => return,
+    };
+    *arena = resized;
+    *bounds = SafeBounds::full(&arena);
+    let (fitted_start, fitted_portals, fitted_obstacles) = layout.fit(&arena);
+    *start = fitted_start;
+    *portals = fitted_portals;
+    *obstacles = fitted_obstacles;
+    for ent in portal_entities.iter().chain(obstacle_entities.iter()) {
+        commands.despawn(ent);
+    }
+    spawn_layout(&mut commands, &materials, &portals, &obstacles);
+    for mut size in backgrounds.iter_mut() {
+        *size = Size {
+            width: arena.width as f32,
+            height: arena.height as f32,
+        };
+    }
+}
+
+pub fn toggle_debug_overlay(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut overlay: ResMut<DebugOverlay>,
+    mut texts: Query<With<DebugText, &mut Text>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F3) {
+        overlay.0 = !overlay.0;
+        if !overlay.0 {
+            for mut text in texts.iter_mut() {
+                text.value.clear();
+            }
+        }
+    }
+}
+
+pub fn debug_overlay(
+    overlay: Res<DebugOverlay>,
+    diagnostics: Res<Diagnostics>,
+    snake_timer: Res<SnakeMoveTimer>,
+    food: Query<&Food>,
+    heads: Query<(&SnakeHead, &Player, &SnakeSegments, &Position)>,
+    mut texts: Query<With<DebugText, &mut Text>>,
+) {
+    if !overlay.0 {
+        return;
+    }
+    let fps = diagnostics
+        .get(FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.average())
+        .unwrap_or_default();
+    let head = heads
+        .iter()
+        .find(|(_, player, _, _)| **player == Player::One)
+        .map(|(head, _, _, pos)| format!("({}, {}) {:?}", pos.x, pos.y, head.direction))
+        .unwrap_or_default();
+    let length = player_one_length(
+        heads
+            .iter()
+            .map(|(_, player, segments, _)| (player, segments)),
+    );
+    let value = format!(
+        "FPS {:.0} | tick {:.0} ms | segments {} | food {} | head {}",
+        fps,
+        snake_timer.0.duration * 1000.0,
+        length - 1,
+        food.iter().count(),
+        head
+    );
+    for mut text in texts.iter_mut() {
+        text.value = value.clone();
+    }
+}
+
+pub fn debug_log_game_over(
+    overlay: Res<DebugOverlay>,
+    mut reader: Local<EventReader<GameOverEvent>>,
+    game_over_events: Res<Events<GameOverEvent>>,
+    heads: Query<(&SnakeHead, &Player)>,
+) {
+    for event in reader.iter(&game_over_events) {
+        if overlay.0 {
+            for (head, _) in heads.iter().filter(|(_, player)| **player == event.player) {
+                println!(
+                    "game over: {} {:?} at ({}, {}) heading {:?}",
+                    event.player, event.reason, event.position.x, event.position.y, head.direction
+                );
+            }
+        }
+    }
+}
+
+pub fn lives_text(
+    lives: Res<Lives>,
+    mode: Res<GameMode>,
+    mut texts: Query<With<LivesText, &mut Text>>,
+) {
+    for mut text in texts.iter_mut() {
+        let value = match *mode {
+            GameMode::Classic | GameMode::Maze | GameMode::Coop => format!("Lives: {}", lives.0),
+            GameMode::TimeAttack
+            | GameMode::ShrinkingArena
+            | GameMode::Versus
+            | GameMode::VersusBot => String::new(),
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub fn score_text(
+    (score, rival_scores, mode, versus): (
+        Res<Score>,
+        Res<RivalScores>,
+        Res<GameMode>,
+        Res<VersusPlayers>,
+    ),
+    heads: Query<(&Player, &SnakeSegments)>,
+    mut texts: Query<With<ScoreText, &mut Text>>,
+) {
+    for mut text in texts.iter_mut() {
+        let value = if *mode == GameMode::Versus {
+            let scores: Vec<String> = mode
+                .players(*versus)
+                .iter()
+                .map(|player| {
+                    let score = versus_score(*player, &score, &rival_scores);
+                    format!("P{}: {}", player.number(), score)
+                })
+                .collect();
+            scores.join("  ")
+        } else if *mode == GameMode::VersusBot {
+            let rival_score = versus_score(Player::Two, &score, &rival_scores);
+            format!("You: {}  CPU: {}", score.0, rival_score)
+        } else {
+            format!(
+                "Score: {}  Length: {}",
+                score.0,
+                player_one_length(heads.iter())
+            )
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub fn snake_eating(
+    mut commands: Commands,
+    mut growth_events: ResMut<Events<GrowthEvent>>,
+    mut power_up_events: ResMut<Events<PowerUpEvent>>,
+    food_positions: Query<With<Food, (Entity, &FoodType, &Position)>>,
+    pickup_positions: Query<(Entity, &PowerUp, &Position)>,
+    head_positions: Query<With<SnakeHead, (Entity, &Position)>>,
+) {
+    for (snake, head_pos) in head_positions.iter() {
+        for (ent, food, food_pos) in food_positions.iter() {
+            if food_pos == head_pos {
+                commands.despawn_recursive(ent);
+                growth_events.send(GrowthEvent {
+                    food: *food,
+                    amount: food.growth(),
+                    position: *food_pos,
+                    snake,
+                });
+            }
+        }
+        for (ent, power_up, pickup_pos) in pickup_positions.iter() {
+            if pickup_pos == head_pos {
+                commands.despawn(ent);
+                power_up_events.send(PowerUpEvent {
+                    power_up: *power_up,
+                    snake,
+                });
+            }
+        }
+    }
+}
+
+/// Makes the body translucent while ghost mode lasts; `segment_gradient`
+/// restores the normal shades afterwards.
+pub fn ghost_mode(
+    materials: Res<Materials>,
+    effects: Res<ActiveEffects>,
+    mut segments: Query<With<SnakeSegment, &mut Handle<ColorMaterial>>>,
+) {
+    if !effects.active(PowerUp::Ghost) {
+        return;
+    }
+    for mut handle in segments.iter_mut() {
+        if *handle != materials.ghost_segment_material {
+            *handle = materials.ghost_segment_material.clone();
+        }
+    }
+}
+
+/// Tints the head while a shield is up.
+pub fn shield_tint(
+    materials: Res<Materials>,
+    effects: Res<ActiveEffects>,
+    mut heads: Query<With<SnakeHead, (&Player, &mut Handle<ColorMaterial>)>>,
+) {
+    let shielded = effects.active(PowerUp::Shield);
+    for (player, mut handle) in heads.iter_mut() {
+        let material = if shielded {
+            materials.shielded_head_material.clone()
+        } else {
+            materials.head_material(*player)
+        };
+        if *handle != material {
+            *handle = material;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn snake_growth(
+    mut commands: Commands,
+    growth_events: Res<Events<GrowthEvent>>,
+    mut growth_reader: Local<EventReader<GrowthEvent>>,
+    mut victory_events: ResMut<Events<VictoryEvent>>,
+    mut run_stats: ResMut<RunStats>,
+    (arena, obstacles): (Res<Arena>, Res<Obstacles>),
+    materials: Res<Materials>,
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    mut heads: Query<(&Player, &mut SnakeSegments)>,
+) {
+    for growth in growth_reader.iter(&growth_events) {
+        run_stats.food_eaten += 1;
+        let (player, mut segments) = match heads.get_mut(growth.snake) {
+            Ok(head) => head,
+            Err(_) => continue,
+        };
+        if growth.amount > 0 {
+            let position = segments.vacated.unwrap();
+            for _ in 0..growth.amount {
+                let len = segments.len() + 1;
+                let segment = spawn_segment(
+                    &mut commands,
+                    &materials.segment_material(len - 1, len),
+                    position,
+                );
+                segments.push(segment, position);
+            }
+        } else if growth.amount < 0 && segments.len() <= -growth.amount as usize {
+            // Losing every segment behind the head is fatal.
+            game_over_events.send(GameOverEvent {
+                player: *player,
+                reason: GameOverReason::Poisoned,
+                position: growth.position,
+            });
+        } else {
+            let kept = segments.len() - (-growth.amount) as usize;
+            for segment in segments.truncate(kept) {
+                commands.despawn(segment);
+            }
+        }
+        let occupied: usize = heads
+            .iter_mut()
+            .map(|(_, segments)| segments.len() + 1)
+            .sum();
+        if occupied >= arena.cells() - obstacles.0.len() {
+            victory_events.send(VictoryEvent);
+        }
+    }
+}
+
+/// Counts the move ticks each snake goes without eating and, under the
+/// starvation rule, sends a `ShrinkEvent` every time one goes hungry too long.
+pub fn hunger(
+    (state, rules): (Res<AppState>, Res<GameRules>),
+    (mut growth_reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    mut shrink_events: ResMut<Events<ShrinkEvent>>,
+    mut heads: Query<(Entity, &mut Hunger)>,
+) {
+    let fed: Vec<Entity> = growth_reader
+        .iter(&growth_events)
+        .map(|growth| growth.snake)
+        .collect();
+    let limit = match rules.starvation {
+        Some(limit) if *state == AppState::Playing => limit,
+        _ => return,
+    };
+    for (snake, mut hunger) in heads.iter_mut() {
+        hunger.0 = if fed.contains(&snake) {
+            0
+        } else {
+            hunger.0 + 1
+        };
+        if hunger.0 >= limit {
+            hunger.0 = 0;
+            shrink_events.send(ShrinkEvent(snake));
+        }
+    }
+}
+
+/// Takes the tail segment off for every `ShrinkEvent`, the counterpart of
+/// `snake_growth`. A snake with only its neck left starves instead.
+pub fn snake_shrink(
+    mut commands: Commands,
+    (mut shrink_reader, shrink_events): (Local<EventReader<ShrinkEvent>>, Res<Events<ShrinkEvent>>),
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    mut heads: Query<(&Player, &Position, &mut SnakeSegments)>,
+) {
+    for ShrinkEvent(snake) in shrink_reader.iter(&shrink_events) {
+        let (player, head, mut segments) = match heads.get_mut(*snake) {
+            Ok(head) => head,
+            Err(_) => continue,
+        };
+        let len = segments.len();
+        if len <= 1 {
+            game_over_events.send(GameOverEvent {
+                player: *player,
+                reason: GameOverReason::Starved,
+                position: *head,
+            });
+            continue;
+        }
+        for segment in segments.truncate(len - 1) {
+            commands.despawn(segment);
+        }
+    }
+}
+
+/// Cuts the body of the snake that picked up a shrink potion in half,
+/// rounding the part kept up.
+pub fn shrink_potion(
+    mut commands: Commands,
+    (mut reader, power_up_events): (Local<EventReader<PowerUpEvent>>, Res<Events<PowerUpEvent>>),
+    mut heads: Query<&mut SnakeSegments>,
+) {
+    for PowerUpEvent { power_up, snake } in reader.iter(&power_up_events) {
+        if *power_up != PowerUp::ShrinkPotion {
+            continue;
+        }
+        let mut segments = match heads.get_mut(*snake) {
+            Ok(segments) => segments,
+            Err(_) => continue,
+        };
+        let kept = segments.len() - segments.len() / 2;
+        for segment in segments.truncate(kept) {
+            commands.despawn(segment);
+        }
+    }
+}
+
+/// Flashes the screen red whenever poison is eaten.
+pub fn poison_flash(
+    time: Res<Time>,
+    (mut reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut flashes: Query<(&mut PoisonFlash, &Handle<ColorMaterial>, &mut Draw)>,
+) {
+    let poisoned = reader
+        .iter(&growth_events)
+        .any(|growth| growth.food == FoodType::Poison);
+    for (mut flash, handle, mut draw) in flashes.iter_mut() {
+        if poisoned {
+            flash.0.reset();
+        } else if flash.0.finished && !draw.is_visible {
+            continue;
+        }
+        flash.0.tick(time.delta_seconds);
+        draw.is_visible = !flash.0.finished;
+        if let Some(material) = materials.get_mut(handle) {
+            material
+                .color
+                .set_a(POISON_FLASH_ALPHA * (1.0 - flash.0.elapsed / flash.0.duration));
+        }
+    }
+}
+
+/// Ends the run with a victory message once the snake fills the arena.
+pub fn victory(
+    mut reader: Local<EventReader<VictoryEvent>>,
+    victory_events: Res<Events<VictoryEvent>>,
+    mut state: ResMut<AppState>,
+    (score, run_stats): (Res<Score>, Res<RunStats>),
+    heads: Query<(&Player, &SnakeSegments)>,
+    mut banners: Query<(&mut Text, &Banner, Option<&StatsText>)>,
+) {
+    if reader.iter(&victory_events).next().is_some() {
+        *state = AppState::GameOver;
+        for (mut text, _, stats_text) in banners.iter_mut() {
+            text.value = if stats_text.is_some() {
+                run_stats.summary(player_one_length(heads.iter()))
+            } else {
+                format!("You win! Score: {}", score.0)
+            };
+        }
+    }
+}
+
+/// Scores each eaten food by its type, multiplied by the current combo and
+/// any score multiplier picked up. Food worth no points neither scores nor
+/// counts towards the combo.
+#[allow(clippy::too_many_arguments)]
+pub fn scoring(
+    clock: Res<GameClock>,
+    growth_events: Res<Events<GrowthEvent>>,
+    mut growth_reader: Local<EventReader<GrowthEvent>>,
+    (effects, multiplier): (Res<ActiveEffects>, Res<ActiveMultiplier>),
+    mut combo: ResMut<Combo>,
+    (mut score, mut rival_scores): (ResMut<Score>, ResMut<RivalScores>),
+    (mut recorder, mode): (ResMut<ReplayRecorder>, Res<GameMode>),
+    mut score_events: ResMut<Events<ScoreEvent>>,
+    players: Query<&Player>,
+) {
+    combo.window.tick(clock.delta_seconds);
+    if combo.window.just_finished {
+        combo.multiplier = 1;
+    }
+    for growth in growth_reader.iter(&growth_events) {
+        if growth.food.points() == 0 {
+            continue;
+        }
+        let points = growth.food.points() * combo.eat() * multiplier.factor(&effects);
+        let rival = players
+            .get(growth.snake)
+            .ok()
+            .copied()
+            .filter(|player| mode.rivals() && *player != Player::One);
+        if let Some(rival) = rival {
+            rival_scores.add(rival, points);
+        } else {
+            score.0 += points;
+            recorder.0.score = score.0;
+        }
+        score_events.send(ScoreEvent {
+            points,
+            position: growth.position,
+        });
+    }
+}
+
+/// Spawns a popup over the cell of every scored food and animates the live
+/// ones. A popup spawned while others are still showing starts one line
+/// higher per live popup, so quick successive eats don't overlap.
+#[allow(clippy::too_many_arguments)]
+pub fn score_popups(
+    mut commands: Commands,
+    time: Res<Time>,
+    (windows, arena): (Res<Windows>, Res<Arena>),
+    font: Res<UiFont>,
+    theme: Res<Theme>,
+    mut reader: Local<EventReader<ScoreEvent>>,
+    score_events: Res<Events<ScoreEvent>>,
+    mut popups: Query<(Entity, &mut ScorePopup, &mut Style, &mut Text)>,
+) {
+    let mut live = 0;
+    for (ent, mut popup, mut style, mut text) in popups.iter_mut() {
+        popup.timer.tick(time.delta_seconds);
+        if popup.timer.finished {
+            commands.despawn(ent);
+            continue;
+        }
+        live += 1;
+        let t = popup.timer.elapsed / popup.timer.duration;
+        style.position.top = Val::Px(popup.top - SCORE_POPUP_DRIFT * t);
+        text.style.color.set_a(1.0 - t);
+    }
+    let window = windows.get_primary().unwrap();
+    let cell = arena.cell_size(window);
+    for event in reader.iter(&score_events) {
+        let center = arena.cell_center(&event.position, cell);
+        let left = window.width() as f32 / 2. + center.x() - SCORE_POPUP_FONT_SIZE;
+        let top =
+            window.height() as f32 / 2. - center.y() - SCORE_POPUP_FONT_SIZE * (live as f32 + 1.0);
+        live += 1;
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(left),
+                        top: Val::Px(top),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: format!("+{}", event.points),
+                    font: font.0.clone(),
+                    style: TextStyle {
+                        font_size: SCORE_POPUP_FONT_SIZE,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(ScorePopup {
+                timer: Timer::from_seconds(SCORE_POPUP_DURATION, false),
+                top,
+            });
+    }
+}
+
+pub fn combo_text(
+    time: Res<Time>,
+    combo: Res<Combo>,
+    theme: Res<Theme>,
+    mut texts: Query<(&mut Text, &mut ComboText)>,
+) {
+    for (mut text, mut combo_text) in texts.iter_mut() {
+        let value = format!("x{}", combo.multiplier);
+        if text.value != value {
+            if combo.multiplier > 1 {
+                combo_text.flash.reset();
+            }
+            text.value = value;
+        }
+        combo_text.flash.tick(time.delta_seconds);
+        text.style.color = if combo_text.flash.finished {
+            theme.text()
+        } else {
+            Color::rgb(1.0, 0.8, 0.0)
+        };
+    }
+}
+
+/// Writes the settings file whenever one of the persisted settings changes.
+pub fn persist_settings(
+    themes: Res<Themes>,
+    next_difficulty: Res<NextDifficulty>,
+    volume: Res<Volume>,
+    (shake, patterns): (Res<ShakeIntensity>, Res<Patterns>),
+    mut saved: Local<Option<Settings>>,
+) {
+    let settings = Settings {
+        theme: themes.chosen().to_string(),
+        difficulty: next_difficulty.0,
+        shake: *shake,
+        patterns: *patterns,
+        audio: *volume,
+    };
+    if saved.is_none() {
+        *saved = Some(settings);
+    } else if saved.as_ref() != Some(&settings) {
+        if let Some(path) = Settings::path() {
+            if let Err(err) = settings.save(&path) {
+                eprintln!("could not save settings {}: {}", path.display(), err);
+            }
+        }
+        *saved = Some(settings);
+    }
+}
+
+/// Cycles the theme with T. Whenever the theme changes, here or on the
+/// settings screen, or its file loads or changes, recolors the shared
+/// materials in place so every spawned entity picks up the new palette
+/// without being respawned.
+#[allow(clippy::too_many_arguments)]
+pub fn cycle_theme(
+    (keyboard_input, state): (Res<Input<KeyCode>>, Res<AppState>),
+    mut themes: ResMut<Themes>,
+    mut theme: ResMut<Theme>,
+    mut applied: Local<Option<Theme>>,
+    mut clear_color: ResMut<ClearColor>,
+    materials: Res<Materials>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut texts: Query<Without<DebugText, &mut Text>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::T) && !state.typing() {
+        themes.next();
+    }
+    if *theme != *themes.current() {
+        *theme = themes.current().clone();
+    }
+    // `setup` built the materials in the starting theme.
+    if *applied.get_or_insert_with(|| theme.clone()) == *theme {
+        return;
+    }
+    *applied = Some(theme.clone());
+    clear_color.0 = theme.letterbox();
+    let shades = materials
+        .segment_gradient
+        .iter()
+        .enumerate()
+        .chain(materials.corner_gradient.iter().enumerate())
+        .map(|(step, handle)| (handle, theme.segment_shade(step)));
+    for (handle, color) in [
+        (&materials.arena_material, theme.background()),
+        (&materials.head_material, theme.head()),
+        (&materials.food_material, theme.food()),
+        (&materials.ghost_segment_material, theme.ghost_segment()),
+        (
+            &materials.tail_material,
+            theme.segment_shade(SEGMENT_GRADIENT_STEPS - 1),
+        ),
+    ]
+    .iter()
+    .copied()
+    .chain(shades)
+    {
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.color = color;
+        }
+    }
+    for mut text in texts.iter_mut() {
+        text.style.color = theme.text();
+    }
+}
+
+/// One greedy step from `from` towards `target`: the neighbouring cell inside
+/// `bounds` and not `blocked` that gets closest, trying the axis with the
+/// larger gap first. Stays put when no step gets closer, e.g. when cornered.
+pub fn hunter_step(
+    from: Position,
+    target: Position,
+    bounds: &SafeBounds,
+    blocked: &HashSet<Position>,
+) -> Position {
+    let distance = |p: &Position| (p.x - target.x).abs() + (p.y - target.y).abs();
+    let step_x = Position {
+        x: from.x + (target.x - from.x).signum(),
+        y: from.y,
+    };
+    let step_y = Position {
+        x: from.x,
+        y: from.y + (target.y - from.y).signum(),
+    };
+    let steps = if (target.x - from.x).abs() >= (target.y - from.y).abs() {
+        [step_x, step_y]
+    } else {
+        [step_y, step_x]
+    };
+    steps
+        .iter()
+        .copied()
+        .find(|p| distance(p) < distance(&from) && bounds.contains(p) && !blocked.contains(p))
+        .unwrap_or(from)
+}
+
+/// Kills a snake when its head and a hunter share a cell, whether the head
+/// ran into the hunter or the hunter caught up, and moves every hunter one
+/// step towards the nearest head each `HUNTER_MOVE_TICKS` move ticks. Hunters
+/// walk over bodies but around walls, food and pickups.
+#[allow(clippy::type_complexity)]
+pub fn hunter_chase(
+    (run_tick, bounds, invulnerable): (Res<RunTick>, Res<SafeBounds>, Res<Invulnerable>),
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    heads: Query<With<SnakeHead, (&Player, &Position)>>,
+    obstacles: Query<Without<Hunter, (&Position, Or<(&Wall, &Food, &PowerUp)>)>>,
+    mut hunters: Query<With<Hunter, &mut Position>>,
+) {
+    let heads: Vec<(Player, Position)> = heads
+        .iter()
+        .map(|(player, head)| (*player, *head))
+        .collect();
+    if heads.is_empty() {
+        return;
+    }
+    let moves = run_tick.0.is_multiple_of(HUNTER_MOVE_TICKS);
+    let blocked: HashSet<Position> = if moves {
+        obstacles.iter().map(|(pos, _)| *pos).collect()
+    } else {
+        HashSet::new()
+    };
+    let caught = |hunter: &Position| heads.iter().find(|(_, head)| head == hunter).copied();
+    for mut hunter in hunters.iter_mut() {
+        let mut prey = caught(&hunter);
+        if prey.is_none() && moves {
+            let nearest = heads
+                .iter()
+                .map(|(_, head)| *head)
+                .min_by_key(|head| (head.x - hunter.x).abs() + (head.y - hunter.y).abs())
+                .unwrap();
+            *hunter = hunter_step(*hunter, nearest, &bounds, &blocked);
+            prey = caught(&hunter);
+        }
+        if let Some((player, head)) = prey {
+            if !invulnerable.active() {
+                game_over_events.send(GameOverEvent {
+                    player,
+                    reason: GameOverReason::CaughtByHunter,
+                    position: head,
+                });
+            }
+        }
+    }
+}
+
+/// Spawns a hunter once a snake is `HUNTER_SPAWN_LENGTH` long, on a free
+/// cell at least `HUNTER_SPAWN_DISTANCE` from every head.
+#[allow(clippy::too_many_arguments)]
+pub fn hunter_spawner(
+    mut commands: Commands,
+    (arena, bounds, materials): (Res<Arena>, Res<SafeBounds>, Res<Materials>),
+    countdown: Res<Countdown>,
+    mut rng: ResMut<GameRng>,
+    hunters: Query<With<Hunter, Entity>>,
+    heads: Query<(&Position, &SnakeSegments)>,
+    positions: Query<Without<GhostSnake, &Position>>,
+) {
+    let longest = heads.iter().map(|(_, segments)| segments.len() + 1).max();
+    if countdown.active()
+        || longest.unwrap_or(0) < HUNTER_SPAWN_LENGTH
+        || hunters.iter().next().is_some()
+    {
+        return;
+    }
+    let head_cells: Vec<Position> = heads.iter().map(|(head, _)| *head).collect();
+    let near_head = head_cells.iter().flat_map(|head| {
+        (-HUNTER_SPAWN_DISTANCE..=HUNTER_SPAWN_DISTANCE).flat_map(move |dx| {
+            (-HUNTER_SPAWN_DISTANCE..=HUNTER_SPAWN_DISTANCE)
+                .filter(move |dy| dx.abs() + dy.abs() < HUNTER_SPAWN_DISTANCE)
+                .map(move |dy| Position {
+                    x: head.x + dx,
+                    y: head.y + dy,
+                })
+        })
+    });
+    let outside = (0..arena.width as i32)
+        .flat_map(|x| (0..arena.height as i32).map(move |y| Position { x, y }))
+        .filter(|pos| !bounds.contains(pos));
+    let occupied: HashSet<Position> = positions
+        .iter()
+        .copied()
+        .chain(near_head)
+        .chain(outside)
+        .collect();
+    if let Some(position) = arena.random_free_cell(&occupied, &mut rng.0) {
+        commands
+            .spawn(SpriteComponents {
+                material: materials.hunter_material.clone(),
+                ..Default::default()
+            })
+            .with(Hunter)
+            .with(position)
+            .with(Size::square(0.9));
+    }
+}
+
+pub fn snake_timer(
+    clock: Res<GameClock>,
+    state: Res<AppState>,
+    countdown: Res<Countdown>,
+    mut snake_timer: ResMut<SnakeMoveTimer>,
+) {
+    if *state == AppState::GameOver || countdown.active() {
+        snake_timer.0.reset();
+        return;
+    }
+    // Moves are taken off in `fixed_update`, however many are due.
+    snake_timer.0.elapsed += clock.delta_seconds;
+}
+
+/// Tracks whether the boost is held, from the keyboard or the replay being
+/// played back, and drains or refills stamina on the game clock. Key presses
+/// and releases are recorded by frame so replays boost at the same moments.
+pub fn boost(
+    clock: Res<GameClock>,
+    (keyboard_input, bindings): (Res<Input<KeyCode>>, Res<KeyBindings>),
+    (countdown, name_entry, state): (Res<Countdown>, Res<NameEntry>, Res<AppState>),
+    (replay_mode, mut recorder): (Res<ReplayMode>, ResMut<ReplayRecorder>),
+    mut held_before: Local<bool>,
+    mut boost: ResMut<Boost>,
+) {
+    if *state != AppState::Playing {
+        return;
+    }
+    let held = match &*replay_mode {
+        ReplayMode::Record(_) => {
+            let held = bindings.pressed(&keyboard_input, Action::Boost) && !name_entry.active();
+            if held != *held_before {
+                let frame = recorder.0.frames.len().saturating_sub(1) as u32;
+                recorder.0.boosts.push(frame);
+            }
+            held
+        }
+        ReplayMode::Playback { replay, frame } => replay.boost_held(frame.saturating_sub(1) as u32),
+    };
+    *held_before = held;
+    boost.active = held && boost.stamina > 0.0 && !countdown.active();
+    boost.stamina = if boost.active {
+        (boost.stamina - BOOST_DRAIN * clock.delta_seconds).max(0.0)
+    } else if !held {
+        (boost.stamina + BOOST_REFILL * clock.delta_seconds).min(1.0)
+    } else {
+        boost.stamina
+    };
+}
+
+pub fn stamina_bar(boost: Res<Boost>, mut bars: Query<With<StaminaBar, &mut Style>>) {
+    for mut style in bars.iter_mut() {
+        let width = Val::Px(STAMINA_BAR_WIDTH * boost.stamina);
+        if style.size.width != width {
+            style.size.width = width;
+        }
+    }
+}
+
+/// Derives the move interval from the base interval and the slow motion and
+/// speed boost effects, so neither they nor the boost can ever leave the
+/// timer permanently altered. All of them scale the interval, so they compose
+/// with each other and with the `SpeedUp` on difficulties that have it.
+pub fn slow_motion(
+    (base_interval, difficulty, boost): (Res<BaseMoveInterval>, Res<Difficulty>, Res<Boost>),
+    (speed_up, run_stats): (Res<SpeedUp>, Res<RunStats>),
+    effects: Res<ActiveEffects>,
+    mut snake_timer: ResMut<SnakeMoveTimer>,
+) {
+    let base = if difficulty.speeds_up() {
+        speed_up.interval(base_interval.0, run_stats.food_eaten)
+    } else {
+        base_interval.0
+    };
+    let factor = |power_up, factor| {
+        if effects.active(power_up) {
+            factor
+        } else {
+            1.0
+        }
+    };
+    let slow = factor(PowerUp::SlowMotion, SLOW_MOTION_FACTOR);
+    let fast = factor(PowerUp::SpeedBoost, SPEED_BOOST_FACTOR);
+    let boosted = if boost.active { BOOST_FACTOR } else { 1.0 };
+    snake_timer.0.duration = base * slow * fast * boosted;
+}
+
+/// Shows the leaderboard on the game over screen of a recorded run, opening
+/// initials entry first when the run's score makes the table. Leaving the
+/// screen hides the table again. Runs with a second snake never make the
+/// table.
+#[allow(clippy::too_many_arguments)]
+pub fn start_name_entry(
+    state: Res<AppState>,
+    mut state_before: Local<AppState>,
+    (replay_mode, recorder, mode): (Res<ReplayMode>, Res<ReplayRecorder>, Res<GameMode>),
+    leaderboard: Res<Leaderboard>,
+    mut name_entry: ResMut<NameEntry>,
+    mut view: ResMut<LeaderboardView>,
+    heads: Query<(&Player, &SnakeSegments)>,
+) {
+    let before = std::mem::replace(&mut *state_before, *state);
+    if before == AppState::GameOver && *state != AppState::GameOver {
+        view.0 = false;
+    }
+    if before == AppState::GameOver || *state != AppState::GameOver || mode.two_snakes() {
+        return;
+    }
+    let finished = &recorder.0;
+    if let ReplayMode::Record(_) = *replay_mode {
+        if leaderboard.qualifies(finished.score, finished.difficulty) {
+            *name_entry = NameEntry {
+                pending: Some((
+                    finished.score,
+                    player_one_length(heads.iter()),
+                    finished.difficulty,
+                )),
+                letters: *b"AAA",
+                slot: 0,
+            };
+        } else {
+            view.0 = true;
+        }
+    }
+}
+
+/// Left and Right change the current letter, Enter moves on to the next one.
+/// Confirming the last letter saves the entry and shows the leaderboard.
+pub fn name_entry(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut name_entry: ResMut<NameEntry>,
+    (mut leaderboard, profiles): (ResMut<Leaderboard>, Res<Profiles>),
+    mut view: ResMut<LeaderboardView>,
+    mut texts: Query<With<NameEntryText, &mut Text>>,
+) {
+    let (score, length, difficulty) = match name_entry.pending {
+        Some(pending) => pending,
+        None => return,
+    };
+    let slot = name_entry.slot;
+    let letter = &mut name_entry.letters[slot];
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        *letter = if *letter == b'A' { b'Z' } else { *letter - 1 };
+    } else if keyboard_input.just_pressed(KeyCode::Right) {
+        *letter = if *letter == b'Z' { b'A' } else { *letter + 1 };
+    } else if keyboard_input.just_pressed(KeyCode::Return) {
+        name_entry.slot += 1;
+    }
+    let value = if name_entry.slot < name_entry.letters.len() {
+        let initials: String = name_entry
+            .letters
+            .iter()
+            .enumerate()
+            .map(|(i, letter)| {
+                if i <= name_entry.slot {
+                    *letter as char
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("High score {}! Name: {}", score, initials)
+    } else {
+        let initials = String::from_utf8_lossy(&name_entry.letters).into_owned();
+        leaderboard.insert(Entry {
+            player: profiles.player(),
+            ..Entry::new(initials, score, length, difficulty)
+        });
+        if let Some(path) = Leaderboard::path() {
+            if let Err(err) = leaderboard.save(&path) {
+                eprintln!("could not save leaderboard {}: {}", path.display(), err);
+            }
+        }
+        *name_entry = NameEntry::default();
+        view.0 = true;
+        String::new()
+    };
+    for mut text in texts.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+/// Shows the leaderboard of the current difficulty while toggled on with L.
+pub fn leaderboard_view(
+    (keyboard_input, state): (Res<Input<KeyCode>>, Res<AppState>),
+    (leaderboard, difficulty): (Res<Leaderboard>, Res<Difficulty>),
+    mut view: ResMut<LeaderboardView>,
+    mut rows: Query<(&LeaderboardRow, &mut Text)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::L) && !state.typing() {
+        view.0 = !view.0;
+    }
+    let entries: Vec<&Entry> = leaderboard.top(*difficulty).collect();
+    for (row, mut text) in rows.iter_mut() {
+        let value = match row.0 {
+            _ if !view.0 => String::new(),
+            0 => format!("Leaderboard ({:?})  L to close", *difficulty),
+            rank => entries
+                .get(rank - 1)
+                .map(|entry| {
+                    format!(
+                        "{:>2}. {}  {:>6}  {:>3}  {}  {}",
+                        rank,
+                        entry.initials,
+                        entry.score,
+                        entry.length,
+                        entry.date(),
+                        entry.player
+                    )
+                })
+                .unwrap_or_default(),
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// Unlocks the achievements the current run has met, saving them and toasting
+/// each new one. Already unlocked achievements never toast again.
+#[allow(clippy::too_many_arguments)]
+pub fn unlock_achievements(
+    mut commands: Commands,
+    font: Res<UiFont>,
+    theme: Res<Theme>,
+    run_stats: Res<RunStats>,
+    mut achievements: ResMut<Achievements>,
+    toasts: Query<&Toast>,
+    heads: Query<(&Player, &SnakeSegments)>,
+) {
+    let progress = Progress {
+        food_eaten: run_stats.food_eaten,
+        length: player_one_length(heads.iter()),
+        time_survived: run_stats.time_survived,
+        fastest_interval: run_stats.fastest_interval,
+    };
+    let unlocked = achievements.unlock(&progress);
+    if unlocked.is_empty() {
+        return;
+    }
+    if let Some(path) = Achievements::path() {
+        if let Err(err) = achievements.save(&path) {
+            eprintln!("could not save achievements {}: {}", path.display(), err);
+        }
+    }
+    let first_slot = toasts.iter().map(|toast| toast.slot + 1).max().unwrap_or(0);
+    for (slot, achievement) in (first_slot..).zip(unlocked) {
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(250.0),
+                        top: Val::Px(-TOAST_HEIGHT),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: format!("Achievement: {}", achievement.title()),
+                    font: font.0.clone(),
+                    style: TextStyle {
+                        font_size: 24.0,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(Toast {
+                timer: Timer::from_seconds(TOAST_DURATION, false),
+                slot,
+            });
+    }
+}
+
+pub fn toasts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toasts: Query<(Entity, &mut Toast, &mut Style)>,
+) {
+    for (ent, mut toast, mut style) in toasts.iter_mut() {
+        toast.timer.tick(time.delta_seconds);
+        if toast.timer.finished {
+            commands.despawn(ent);
+            continue;
+        }
+        let slid_in = (toast.timer.elapsed / TOAST_SLIDE).min(1.0);
+        let target = 10.0 + toast.slot as f32 * TOAST_HEIGHT;
+        style.position.top = Val::Px(-TOAST_HEIGHT + (target + TOAST_HEIGHT) * slid_in);
+    }
+}
+
+/// Lists every achievement, locked or not, while toggled on with F4.
+pub fn achievements_view(
+    keyboard_input: Res<Input<KeyCode>>,
+    achievements: Res<Achievements>,
+    mut view: ResMut<AchievementsView>,
+    mut rows: Query<(&AchievementRow, &mut Text)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F4) {
+        view.0 = !view.0;
+    }
+    for (row, mut text) in rows.iter_mut() {
+        let value = match row.0 {
+            _ if !view.0 => String::new(),
+            0 => "Achievements  F4 to close".to_string(),
+            index => {
+                let achievement = Achievement::ALL[index - 1];
+                format!(
+                    "[{}] {}: {}",
+                    if achievements.is_unlocked(achievement) {
+                        'x'
+                    } else {
+                        ' '
+                    },
+                    achievement.title(),
+                    achievement.description()
+                )
+            }
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// In `GameMode::ShrinkingArena`, closes the outer ring of the safe area every
+/// `SHRINK_INTERVAL` seconds: its cells become walls and any food or pickup on
+/// them is removed. Since every closed cell holds a wall, spawning and
+/// wandering food treat it as occupied and stay inside the safe area. Each run
+/// starts with the whole arena open again.
+#[allow(clippy::too_many_arguments)]
+pub fn shrink_arena(
+    mut commands: Commands,
+    (clock, mode, countdown): (Res<GameClock>, Res<GameMode>, Res<Countdown>),
+    (arena, materials): (Res<Arena>, Res<Materials>),
+    (mut reader, run_start_events): (
+        Local<EventReader<RunStartEvent>>,
+        Res<Events<RunStartEvent>>,
+    ),
+    (mut bounds, mut timer): (ResMut<SafeBounds>, ResMut<ShrinkTimer>),
+    walls: Query<With<Wall, Without<Obstacle, Entity>>>,
+    food: Query<With<Food, (Entity, &Position)>>,
+    pickups: Query<(Entity, &PowerUp, &Position)>,
+) {
+    if reader.iter(&run_start_events).next().is_some() {
+        for ent in walls.iter() {
+            commands.despawn(ent);
+        }
+        *bounds = SafeBounds::full(&arena);
+        timer.0.reset();
+    }
+    if *mode != GameMode::ShrinkingArena || countdown.active() {
+        return;
+    }
+    timer.0.tick(clock.delta_seconds);
+    if !timer.0.just_finished {
+        return;
+    }
+    let shrunk = match bounds.shrunk() {
+        Some(shrunk) => shrunk,
+        None => return,
+    };
+    let ring = (bounds.min.x..=bounds.max.x)
+        .flat_map(|x| (bounds.min.y..=bounds.max.y).map(move |y| Position { x, y }))
+        .filter(|pos| !shrunk.contains(pos));
+    for position in ring {
+        commands
+            .spawn(SpriteComponents {
+                material: materials.wall_material.clone(),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(position)
+            .with(Size::square(1.0));
+    }
+    for (ent, pos) in food.iter() {
+        if !shrunk.contains(pos) {
+            commands.despawn_recursive(ent);
+        }
+    }
+    for (ent, _, pos) in pickups.iter() {
+        if !shrunk.contains(pos) {
+            commands.despawn(ent);
+        }
+    }
+    *bounds = shrunk;
+}
+
+/// Picks the difficulty of the next run with 1, 2 or 3.
+pub fn select_difficulty(
+    (keyboard_input, state): (Res<Input<KeyCode>>, Res<AppState>),
+    mut next_difficulty: ResMut<NextDifficulty>,
+    mut banners: Query<(&mut Text, &mut Banner, Option<&StatsText>)>,
+) {
+    let difficulty = if state.typing() {
+        return;
+    } else if keyboard_input.just_pressed(KeyCode::Key1) {
+        Difficulty::Easy
+    } else if keyboard_input.just_pressed(KeyCode::Key2) {
+        Difficulty::Normal
+    } else if keyboard_input.just_pressed(KeyCode::Key3) {
+        Difficulty::Hard
+    } else {
+        return;
+    };
+    next_difficulty.0 = difficulty;
+    for (mut text, mut banner, stats_text) in banners.iter_mut() {
+        if stats_text.is_none() {
+            text.value = format!("Next run: {:?}", difficulty);
+            banner.timer.reset();
+        }
+    }
+}
+
+/// Re-enacts the best run alongside the current one: after every move tick the
+/// ghost's head sits where the best run's head was after the same tick, and its
+/// body trails along the path that head took. G hides or shows it.
+pub fn ghost_snake(
+    mut commands: Commands,
+    (keyboard_input, state): (Res<Input<KeyCode>>, Res<AppState>),
+    materials: Res<Materials>,
+    (best_run, run_tick): (Res<BestRun>, Res<RunTick>),
+    mut visible: ResMut<GhostVisible>,
+    mut ghosts: Query<(Entity, &GhostSnake, &mut Position, &mut Draw)>,
+) {
+    if keyboard_input.just_pressed(KeyCode::G) && !state.typing() {
+        visible.0 = !visible.0;
+    }
+    let steps = best_run.0.as_ref().map_or(&[][..], |best| &best.steps);
+    let cells: Vec<Position> = match steps.get(run_tick.0.wrapping_sub(1) as usize) {
+        Some((_, length)) => steps[..run_tick.0 as usize]
+            .iter()
+            .rev()
+            .take(*length)
+            .map(|(head, _)| *head)
+            .collect(),
+        None => Vec::new(),
+    };
+    let mut shown = 0;
+    for (ent, ghost, mut pos, mut draw) in ghosts.iter_mut() {
+        match cells.get(ghost.0) {
+            Some(cell) => {
+                if *pos != *cell {
+                    *pos = *cell;
+                }
+                draw.is_visible = visible.0;
+                shown += 1;
+            }
+            None => {
+                commands.despawn(ent);
+            }
+        }
+    }
+    for (i, cell) in cells.iter().enumerate().skip(shown) {
+        commands
+            .spawn(SpriteComponents {
+                material: materials.ghost_snake_material.clone(),
+                draw: Draw {
+                    is_visible: visible.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .with(GhostSnake(i))
+            .with(*cell)
+            .with(Size::square(if i == 0 { 0.8 } else { 0.65 }));
+    }
+}
+
+pub trait AddSnakeStep {
+    fn add_snake_step(&mut self) -> &mut Self;
+
+    /// Adds a system to `stage` of the `FixedSchedule`, to run once per move.
+    fn add_step_system(&mut self, stage: &str, system: Box<dyn System>) -> &mut Self;
+}
+
+impl AddSnakeStep for AppBuilder {
+    /// Registers the gameplay systems: every frame the move timer ticks
+    /// first, then `fixed_update` plays the moves due, in each of which the
+    /// snakes are steered, then move, then eat, then grow, then shrink if they
+    /// went hungry.
+    fn add_snake_step(&mut self) -> &mut Self {
+        self.add_stage_before(stage::UPDATE, snake_stage::TICK)
+            .add_stage_after(snake_stage::TICK, snake_stage::FIXED_UPDATE)
+            .add_system_to_stage(snake_stage::TICK, boost.system())
+            .add_system_to_stage(snake_stage::TICK, tick_effects.system())
+            .add_system_to_stage(snake_stage::TICK, slow_motion.system())
+            .add_system_to_stage(snake_stage::TICK, snake_timer.system())
+            .add_system_to_stage(
+                snake_stage::FIXED_UPDATE,
+                fixed_update.thread_local_system(),
+            )
+            .add_step_system(snake_stage::MOVEMENT, snake_movement.system())
+            .add_step_system(snake_stage::EATING, snake_eating.system())
+            .add_step_system(snake_stage::GROWTH, hunter_chase.system())
+            .add_step_system(snake_stage::GROWTH, snake_growth.system())
+            .add_step_system(snake_stage::GROWTH, grant_power_ups.system())
+            .add_step_system(snake_stage::GROWTH, hunger.system())
+            .add_step_system(snake_stage::SHRINK, snake_shrink.system())
+            .add_step_system(snake_stage::SHRINK, shrink_potion.system())
+    }
+
+    fn add_step_system(&mut self, stage: &str, system: Box<dyn System>) -> &mut Self {
+        self.resources_mut()
+            .get_or_insert_with(FixedSchedule::default)
+            .add_system(stage, system);
+        self
+    }
+}
+
+/// The whole game: add it next to `DefaultPlugins`, which it relies on for
+/// windowing, rendering and input. Settings, the leaderboard and achievements
+/// are loaded from disk when the plugin is built, and the command line is
+/// taken from the `Options` resource if one was added before the plugin.
+pub struct SnakePlugin;
+
+impl Plugin for SnakePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let settings = Settings::path()
+            .map(|path| Settings::load(&path))
+            .unwrap_or_default();
+        let themes = Themes::new(&settings.theme);
+        let options = app
+            .resources()
+            .get::<Options>()
+            .map(|options| (*options).clone())
+            .unwrap_or_default();
+        let arena = options.arena.unwrap_or_default();
+        let layout = ArenaLayout::from_options(&options);
+        let (start, portals, obstacles) = layout.fit(&arena);
+        app.add_resource(ReplayMode::from_options(&options))
+            .add_resource(options.clone())
+            .add_resource(
+                KeyBindings::path()
+                    .map(|path| KeyBindings::load(&path))
+                    .unwrap_or_default(),
+            )
+            .init_resource::<AppState>()
+            .init_resource::<MenuCursor>()
+            .init_resource::<SettingsOrigin>()
+            .init_resource::<BestRun>()
+            .add_resource(NextDifficulty::from_options(&options, settings.difficulty))
+            .init_resource::<Difficulty>()
+            .add_resource(ClearColor(themes.current().letterbox()))
+            .add_resource(themes.current().clone())
+            .add_resource(themes)
+            .add_resource(settings.audio)
+            .add_resource(settings.shake)
+            .add_resource(settings.patterns)
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(
+                Difficulty::default().move_interval(),
+                true,
+            )))
+            .init_resource::<BaseMoveInterval>()
+            .add_resource(portals)
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .init_resource::<Boost>()
+            .init_resource::<Score>()
+            .init_resource::<RivalScores>()
+            .init_resource::<VersusPlayers>()
+            .init_resource::<Combo>()
+            .init_resource::<Lives>()
+            .init_resource::<Invulnerable>()
+            .add_resource(arena)
+            .add_resource(SafeBounds::full(&arena))
+            .init_resource::<ShrinkTimer>()
+            .init_resource::<DebugOverlay>()
+            .init_resource::<RunStats>()
+            .init_resource::<Countdown>()
+            .init_resource::<GameClock>()
+            .add_resource(
+                Leaderboard::path()
+                    .map(|path| Leaderboard::load(&path))
+                    .unwrap_or_default(),
+            )
+            .init_resource::<NameEntry>()
+            .init_resource::<LeaderboardView>()
+            .add_resource(
+                Achievements::path()
+                    .map(|path| Achievements::load(&path))
+                    .unwrap_or_default(),
+            )
+            .init_resource::<AchievementsView>()
+            .add_resource(GameMode::from_options(&options))
+            .add_resource(GameRules::from_options(&options))
+            .add_resource(obstacles)
+            .add_resource(SpeedUp::from_options(&options))
+            .add_resource(RunOverrides::from_options(&options))
+            .add_resource(start)
+            .add_resource(layout)
+            .init_resource::<RoundTimer>()
+            .add_resource(GameRng::from_options(&options))
+            .init_resource::<RunTick>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<GhostVisible>()
+            .add_event::<GrowthEvent>()
+            .add_event::<ScoreEvent>()
+            .add_event::<GameOverEvent>()
+            .add_event::<VictoryEvent>()
+            .add_event::<ShrinkEvent>()
+            .add_event::<PowerUpEvent>()
+            .add_event::<EndRunEvent>()
+            .add_event::<RunStartEvent>()
+            .add_event::<ResizeArenaEvent>()
+            .add_startup_system(setup.system())
+            .add_startup_stage("game_setup")
+            .add_startup_system_to_stage("game_setup", game_setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, app_state.system())
+            .add_system_to_stage(stage::PRE_UPDATE, game_clock.system())
+            .add_snake_step()
+            .add_plugin(InputPlugin)
+            .add_plugin(BotPlugin)
+            .add_plugin(DailyPlugin)
+            .add_plugin(ProfilePlugin)
+            .add_system(menu.system())
+            .add_system(resize_arena.system())
+            .add_system(victory.system())
+            .add_system(countdown.system())
+            .add_system(ghost_mode.system())
+            .add_system(shield_tint.system())
+            .add_system(poison_flash.system())
+            .add_system(scoring.system())
+            .add_system(combo_text.system())
+            .add_system(stamina_bar.system())
+            .add_system(effect_bars.system())
+            .add_system(multiplier_badge.system())
+            .add_system(score_popups.system())
+            .add_system(invulnerability.system())
+            .add_system(lives_text.system())
+            .add_system(score_text.system())
+            .add_system(round_timer.system())
+            .add_system(run_clock.system())
+            .add_system(banner.system())
+            .add_system(toggle_debug_overlay.system())
+            .add_system(debug_overlay.system())
+            .add_system(debug_log_game_over.system())
+            .add_plugin(FoodPlugin)
+            .add_plugin(LevelPlugin)
+            .add_plugin(OnlinePlugin)
+            .add_plugin(BotApiPlugin)
+            .add_system(game_over.system())
+            .add_system(start_name_entry.system())
+            .add_system(start_run.system())
+            .add_system(name_entry.system())
+            .add_system(leaderboard_view.system())
+            .add_system(shrink_arena.system())
+            .add_system(hunter_spawner.system())
+            .add_system(unlock_achievements.system())
+            .add_system(toasts.system())
+            .add_system(achievements_view.system())
+            .add_system(ghost_snake.system())
+            .add_system(select_difficulty.system())
+            .add_system(cycle_theme.system())
+            .add_system(persist_settings.system())
+            .add_plugin(ThemePlugin)
+            .add_plugin(RenderPlugin)
+            .add_plugin(SoundPlugin)
+            .add_plugin(FrameTimeDiagnosticsPlugin);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{DOUBLE_FOOD_GROWTH, FOOD_POINTS, GOLDEN_FOOD_GROWTH, POISON_FOOD_SHRINK};
+    use crate::input::{handle_movement, Gamepads};
+    use crate::render::{segment_taper, TAIL_SEGMENT_SIZE};
+    use bevy::asset::HandleId;
+    use std::time::Duration;
+
+    /// Spawns a body on `cells`, from the neck to the tail, and gives it to
+    /// Player 1's head, spawning a bare one if there is no head yet.
+    fn spawn_body(app: &mut App, cells: &[(i32, i32)]) -> Vec<Entity> {
+        let mut segments = SnakeSegments::default();
+        let entities = cells
+            .iter()
+            .map(|&(x, y)| {
+                let position = Position { x, y };
+                let entity = app.world.spawn((SnakeSegment, position));
+                segments.push(entity, position);
+                entity
+            })
+            .collect();
+        let head = app
+            .world
+            .query::<(Entity, &SnakeHead)>()
+            .map(|(entity, _)| entity)
+            .next();
+        match head {
+            Some(head) => app
+                .world
+                .insert(head, (Player::One, segments, Hunger::default()))
+                .unwrap(),
+            None => {
+                app.world.spawn((Player::One, segments, Hunger::default()));
+            }
+        }
+        entities
+    }
+
+    /// The head entity of `player`'s snake.
+    fn snake(app: &App, player: Player) -> Entity {
+        app.world
+            .query::<(Entity, &Player)>()
+            .find(|(_, owner)| **owner == player)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    }
+
+    /// Player 1's body.
+    fn snake_body(app: &App) -> &SnakeSegments {
+        app.world
+            .query::<(&Player, &SnakeSegments)>()
+            .find(|(player, _)| **player == Player::One)
+            .map(|(_, segments)| segments)
+            .unwrap()
+    }
+
+    fn finished_move_timer() -> SnakeMoveTimer {
+        let mut timer = Timer::new(Duration::from_millis(150), true);
+        timer.finished = true;
+        SnakeMoveTimer(timer)
+    }
+
+    fn scoring_app() -> App {
+        let mut builder = App::build();
+        builder
+            .init_resource::<GameClock>()
+            .init_resource::<Combo>()
+            .init_resource::<Score>()
+            .init_resource::<RivalScores>()
+            .init_resource::<ReplayRecorder>()
+            .add_resource(GameMode::Classic)
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .add_event::<GrowthEvent>()
+            .add_event::<ScoreEvent>()
+            .add_system(scoring.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.world.spawn((Player::One,));
+        app.world.spawn((Player::Two,));
+        app.executor.initialize(&mut app.resources);
+        app
+    }
+
+    fn advance(app: &mut App, seconds: f32, eat: bool) {
+        advance_player(app, seconds, eat, Player::One);
+    }
+
+    fn advance_player(app: &mut App, seconds: f32, eat: bool, player: Player) {
+        if eat {
+            let snake = snake(app, player);
+            app.resources
+                .get_mut::<Events<GrowthEvent>>()
+                .unwrap()
+                .send(GrowthEvent {
+                    snake,
+                    food: FoodType::Normal,
+                    amount: 1,
+                    position: Position::default(),
+                });
+        }
+        app.resources.get_mut::<GameClock>().unwrap().delta_seconds = seconds;
+        app.update();
+    }
+
+    #[test]
+    fn quick_eats_score_more_than_slow_eats() {
+        let mut quick = scoring_app();
+        advance(&mut quick, 0.1, true);
+        advance(&mut quick, 1.0, true);
+
+        let mut slow = scoring_app();
+        advance(&mut slow, 0.1, true);
+        advance(&mut slow, COMBO_WINDOW + 1.0, false);
+        advance(&mut slow, 0.1, true);
+
+        assert_eq!(quick.resources.get::<Score>().unwrap().0, FOOD_POINTS * 3);
+        assert_eq!(slow.resources.get::<Score>().unwrap().0, FOOD_POINTS * 2);
+    }
+
+    #[test]
+    fn player_two_scores_apart_only_in_versus() {
+        for &(mode, rival_points) in &[(GameMode::Versus, FOOD_POINTS), (GameMode::Coop, 0)] {
+            let mut app = scoring_app();
+            *app.resources.get_mut::<GameMode>().unwrap() = mode;
+            advance_player(&mut app, 0.1, true, Player::One);
+            advance_player(&mut app, COMBO_WINDOW + 1.0, true, Player::Two);
+            assert_eq!(
+                app.resources.get::<Score>().unwrap().0,
+                FOOD_POINTS * 2 - rival_points,
+                "{:?}",
+                mode
+            );
+            let rival_scores = app.resources.get::<RivalScores>().unwrap();
+            assert_eq!(rival_scores.0.first().copied().unwrap_or(0), rival_points);
+        }
+    }
+
+    #[test]
+    fn a_score_multiplier_scales_points_while_it_runs() {
+        let mut app = scoring_app();
+        {
+            let mut effects = app.resources.get_mut::<ActiveEffects>().unwrap();
+            let mut multiplier = app.resources.get_mut::<ActiveMultiplier>().unwrap();
+            for _ in 0..SCORE_MULTIPLIER_MAX {
+                multiplier.stack(&effects);
+                effects.grant(PowerUp::ScoreMultiplier);
+            }
+        }
+        advance(&mut app, COMBO_WINDOW + 1.0, true);
+        assert_eq!(
+            app.resources.get::<Score>().unwrap().0,
+            FOOD_POINTS * SCORE_MULTIPLIER_MAX
+        );
+
+        *app.resources.get_mut::<ActiveEffects>().unwrap() = ActiveEffects::default();
+        advance(&mut app, COMBO_WINDOW + 1.0, true);
+        assert_eq!(
+            app.resources.get::<Score>().unwrap().0,
+            FOOD_POINTS * (SCORE_MULTIPLIER_MAX + 1)
+        );
+    }
+
+    /// A full snake step with a two-cell snake at (3, 3) heading up, and food
+    /// on `food`.
+    fn step_app(food: &[(i32, i32)]) -> App {
+        let mut builder = App::build();
+        builder
+            .init_resource::<GameClock>()
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(
+                Difficulty::Normal.move_interval(),
+                true,
+            )))
+            .init_resource::<BaseMoveInterval>()
+            .init_resource::<Difficulty>()
+            .init_resource::<NameEntry>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .add_event::<GamepadEvent>()
+            .add_resource(AppState::Playing)
+            .add_resource(Countdown(expired_timer(COUNTDOWN_DURATION)))
+            .init_resource::<Arena>()
+            .init_resource::<SafeBounds>()
+            .init_resource::<Portals>()
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .add_event::<PowerUpEvent>()
+            .init_resource::<SpeedUp>()
+            .init_resource::<Boost>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<Materials>()
+            .init_resource::<ReplayMode>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<RunTick>()
+            .init_resource::<GameRng>()
+            .add_event::<GameOverEvent>()
+            .add_event::<GrowthEvent>()
+            .add_event::<VictoryEvent>()
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .add_snake_step()
+            .add_event::<ShrinkEvent>()
+            .add_resource(GameMode::Classic)
+            .add_plugin(InputPlugin);
+        let mut app = std::mem::take(&mut builder.app);
+
+        app.world.spawn((
+            SnakeHead {
+                direction: Direction::Up,
+                try_direction: Direction::Up,
+                queued_turns: VecDeque::new(),
+            },
+            Position { x: 3, y: 3 },
+        ));
+        spawn_body(&mut app, &[(3, 2)]);
+        for &(x, y) in food {
+            app.world.spawn((Food, FoodType::Normal, Position { x, y }));
+        }
+        app.executor.initialize(&mut app.resources);
+        app.resources.get_mut::<GameClock>().unwrap().delta_seconds =
+            Difficulty::Normal.move_interval();
+        app
+    }
+
+    #[test]
+    fn eating_sees_the_head_after_it_moved() {
+        let mut app = step_app(&[(3, 4)]);
+        app.update();
+
+        let events = app.resources.get::<Events<GrowthEvent>>().unwrap();
+        assert_eq!(events.get_reader().iter(&events).count(), 1);
+        assert_eq!(snake_body(&app).len(), 2);
+    }
+
+    #[test]
+    fn every_food_eaten_grows_the_snake() {
+        let mut app = step_app(&[(3, 4), (3, 5)]);
+        app.update();
+        app.update();
+        assert_eq!(snake_body(&app).len(), 3);
+
+        let mut app = growth_app(1);
+        let snake = snake(&app, Player::One);
+        for _ in 0..2 {
+            app.resources
+                .get_mut::<Events<GrowthEvent>>()
+                .unwrap()
+                .send(GrowthEvent {
+                    snake,
+                    food: FoodType::Normal,
+                    amount: 1,
+                    position: Position::default(),
+                });
+        }
+        app.update();
+        assert_eq!(snake_body(&app).len(), 3);
+    }
+
+    #[test]
+    fn filling_the_arena_wins() {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .add_resource(Arena {
+                width: 3,
+                height: 3,
+            })
+            .add_resource(SafeBounds::full(&Arena {
+                width: 3,
+                height: 3,
+            }))
+            .init_resource::<Portals>()
+            .init_resource::<ActiveEffects>()
+            .add_event::<PowerUpEvent>()
+            .init_resource::<SpeedUp>()
+            .init_resource::<Boost>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<Materials>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .add_event::<GameOverEvent>()
+            .add_event::<GrowthEvent>()
+            .add_event::<VictoryEvent>()
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .add_system(snake_movement.system())
+            .add_system(snake_eating.system())
+            .add_system(snake_growth.system());
+        let mut app = std::mem::take(&mut builder.app);
+
+        app.world.spawn((
+            SnakeHead {
+                direction: Direction::Right,
+                try_direction: Direction::Right,
+                queued_turns: VecDeque::new(),
+            },
+            Position { x: 1, y: 2 },
+        ));
+        app.world
+            .spawn((Food, FoodType::Normal, Position { x: 2, y: 2 }));
+        spawn_body(
+            &mut app,
+            &[(0, 2), (0, 1), (1, 1), (2, 1), (2, 0), (1, 0), (0, 0)],
+        );
+
+        app.executor.initialize(&mut app.resources);
+        app.update();
+
+        assert_eq!(snake_body(&app).len(), 8);
+        let events = app.resources.get::<Events<VictoryEvent>>().unwrap();
+        assert!(events.get_reader().iter(&events).next().is_some());
+        let occupied: HashSet<Position> = app.world.query::<&Position>().copied().collect();
+        assert_eq!(
+            Arena {
+                width: 3,
+                height: 3
+            }
+            .random_free_cell(&occupied, &mut thread_rng()),
+            None
+        );
+    }
+
+    #[test]
+    fn snake_threads_through_portal() {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .init_resource::<SafeBounds>()
+            .init_resource::<ActiveEffects>()
+            .add_resource(Invulnerable::default())
+            .init_resource::<RunStats>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .add_resource(Portals(vec![(
+                Position { x: 5, y: 5 },
+                Position { x: 10, y: 12 },
+            )]))
+            .add_event::<GameOverEvent>()
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .add_system(snake_movement.system());
+        let mut app = std::mem::take(&mut builder.app);
+
+        let head = app.world.spawn((
+            SnakeHead {
+                direction: Direction::Right,
+                try_direction: Direction::Right,
+                queued_turns: VecDeque::new(),
+            },
+            Position { x: 4, y: 5 },
+        ));
+        spawn_body(&mut app, &[(3, 5), (2, 5), (1, 5), (0, 5)]);
+
+        app.executor.initialize(&mut app.resources);
+        let mut game_over_reader = EventReader::<GameOverEvent>::default();
+        for _ in 0..5 {
+            app.update();
+            let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+            assert!(game_over_reader.iter(&events).next().is_none());
+        }
+
+        assert_eq!(
+            *app.world.get::<Position>(head).unwrap(),
+            Position { x: 14, y: 12 }
+        );
+        let body: Vec<Position> = app
+            .world
+            .query::<&SnakeSegments>()
+            .next()
+            .unwrap()
+            .iter()
+            .map(|e| *app.world.get::<Position>(*e).unwrap())
+            .collect();
+        assert_eq!(
+            body,
+            (10..14)
+                .rev()
+                .map(|x| Position { x, y: 12 })
+                .collect::<Vec<_>>()
+        );
+    }
+
+    fn replay_app(replay_mode: ReplayMode) -> App {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Time>()
+            .init_resource::<GameClock>()
+            .add_resource(replay_mode)
+            .init_resource::<DailyChallenge>()
+            .init_resource::<Profiles>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .init_resource::<BestRun>()
+            .init_resource::<GameRng>()
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(
+                Difficulty::Normal.move_interval(),
+                true,
+            )))
+            .init_resource::<BaseMoveInterval>()
+            .init_resource::<Difficulty>()
+            .init_resource::<NextDifficulty>()
+            .init_resource::<RunOverrides>()
+            .init_resource::<NameEntry>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .add_event::<GamepadEvent>()
+            .add_resource(AppState::Playing)
+            .init_resource::<Countdown>()
+            .init_resource::<Arena>()
+            .init_resource::<SafeBounds>()
+            .init_resource::<Portals>()
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .add_event::<PowerUpEvent>()
+            .init_resource::<SpeedUp>()
+            .init_resource::<Boost>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<Materials>()
+            .add_resource(GameMode::Classic)
+            .init_resource::<RoundTimer>()
+            .init_resource::<Score>()
+            .init_resource::<RivalScores>()
+            .init_resource::<VersusPlayers>()
+            .init_resource::<Combo>()
+            .init_resource::<Lives>()
+            .add_event::<GameOverEvent>()
+            .add_event::<GrowthEvent>()
+            .init_resource::<KeyBindings>()
+            .add_event::<VictoryEvent>()
+            .add_event::<RunStartEvent>()
+            .add_event::<EndRunEvent>()
+            .add_startup_system(game_setup.system())
+            .add_system_to_stage(stage::PRE_UPDATE, game_clock.system())
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .add_snake_step()
+            .add_event::<ShrinkEvent>()
+            .add_plugin(InputPlugin)
+            .add_system(countdown.system())
+            .add_plugin(FoodPlugin)
+            .init_resource::<SnakeStart>()
+            .init_resource::<ArenaLayout>()
+            .add_system(game_over.system())
+            .add_system(start_run.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.initialize();
+        app.resources
+            .get_mut::<Events<EndRunEvent>>()
+            .unwrap()
+            .send(EndRunEvent::Restart);
+        app
+    }
+
+    #[test]
+    fn the_first_food_of_a_run_avoids_the_new_snake() {
+        let mut app = replay_app(ReplayMode::Record(None));
+        let free = Position { x: 0, y: 0 };
+        let start = SnakeStart::default();
+        let portals: Vec<Position> = Portals::default()
+            .0
+            .iter()
+            .flat_map(|(a, b)| vec![*a, *b])
+            .collect();
+        let arena = Arena::default();
+        app.resources.get_mut::<Obstacles>().unwrap().0 = (0..arena.width as i32)
+            .flat_map(|x| (0..arena.height as i32).map(move |y| Position { x, y }))
+            .filter(|cell| {
+                *cell != free
+                    && !start.cells(&[Player::One], &arena).contains(cell)
+                    && !portals.contains(cell)
+            })
+            .collect();
+        for _ in 0..3 {
+            app.update();
+        }
+        let food: Vec<Position> = app
+            .world
+            .query::<With<Food, &Position>>()
+            .copied()
+            .collect();
+        assert_eq!(food, [free]);
+    }
+
+    #[test]
+    fn a_coop_crash_costs_a_shared_life_and_restarts_both_snakes() {
+        let mut app = replay_app(ReplayMode::Record(None));
+        *app.resources.get_mut::<GameMode>().unwrap() = GameMode::Coop;
+        app.update();
+        let heads = |app: &App| {
+            let mut players: Vec<Player> = app
+                .world
+                .query::<With<SnakeHead, &Player>>()
+                .copied()
+                .collect();
+            players.sort_by_key(|player| *player == Player::Two);
+            players
+        };
+        assert_eq!(heads(&app), [Player::One, Player::Two]);
+
+        app.resources
+            .get_mut::<Events<GameOverEvent>>()
+            .unwrap()
+            .send(GameOverEvent {
+                player: Player::Two,
+                reason: GameOverReason::HitRival,
+                position: Position::default(),
+            });
+        app.update();
+        assert_eq!(app.resources.get::<Lives>().unwrap().0, STARTING_LIVES - 1);
+        assert_eq!(*app.resources.get::<AppState>().unwrap(), AppState::Playing);
+        assert_eq!(heads(&app), [Player::One, Player::Two]);
+    }
+
+    fn occupied_cells(app: &mut App) -> Vec<(i32, i32)> {
+        let mut cells: Vec<(i32, i32)> =
+            app.world.query::<&Position>().map(|p| (p.x, p.y)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn replay_reproduces_the_recorded_run() {
+        let turns = [
+            (80, Direction::Right),
+            (110, Direction::Down),
+            (130, Direction::Left),
+        ];
+        let mut recording = replay_app(ReplayMode::Record(None));
+        for frame in 0..150 {
+            if let Some((_, direction)) = turns.iter().find(|(at, _)| *at == frame) {
+                for mut head in recording.world.query_mut::<&mut SnakeHead>() {
+                    head.try_direction = *direction;
+                }
+            }
+            recording.resources.get_mut::<Time>().unwrap().delta_seconds = 0.05;
+            recording.update();
+        }
+        let replay = recording
+            .resources
+            .get::<ReplayRecorder>()
+            .unwrap()
+            .0
+            .clone();
+        assert_eq!(replay.inputs.len(), turns.len());
+
+        let mut playback = replay_app(ReplayMode::Playback { replay, frame: 0 });
+        for _ in 0..150 {
+            playback.update();
+        }
+
+        assert_eq!(
+            occupied_cells(&mut playback),
+            occupied_cells(&mut recording)
+        );
+    }
+
+    #[test]
+    fn ghost_trails_the_best_runs_head() {
+        let steps = (4..10).map(|y| (Position { x: 3, y }, 3)).collect();
+        let mut builder = App::build();
+        builder
+            .init_resource::<Input<KeyCode>>()
+            .add_resource(AppState::Playing)
+            .init_resource::<Materials>()
+            .add_resource(BestRun(Some(Replay {
+                steps,
+                ..Default::default()
+            })))
+            .add_resource(RunTick(4))
+            .init_resource::<GhostVisible>()
+            .add_system(ghost_snake.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        app.update();
+        app.update();
+
+        let mut ghost: Vec<(usize, Position)> = app
+            .world
+            .query::<(&GhostSnake, &Position)>()
+            .map(|(ghost, pos)| (ghost.0, *pos))
+            .collect();
+        ghost.sort_by_key(|(i, _)| *i);
+        assert_eq!(
+            ghost,
+            vec![
+                (0, Position { x: 3, y: 7 }),
+                (1, Position { x: 3, y: 6 }),
+                (2, Position { x: 3, y: 5 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn segments_shade_from_neck_to_tail() {
+        let materials = Materials {
+            segment_gradient: (0..SEGMENT_GRADIENT_STEPS)
+                .map(|_| Handle::weak(HandleId::random::<ColorMaterial>()))
+                .collect(),
+            ..Default::default()
+        };
+        let shades: Vec<_> = (0..20).map(|i| materials.segment_material(i, 20)).collect();
+        assert_eq!(shades[0], materials.segment_gradient[0]);
+        assert_eq!(shades[19], *materials.segment_gradient.last().unwrap());
+        assert!(shades.windows(2).all(|pair| materials
+            .segment_gradient
+            .iter()
+            .position(|h| *h == pair[0])
+            <= materials
+                .segment_gradient
+                .iter()
+                .position(|h| *h == pair[1])));
+    }
+
+    #[test]
+    fn body_tapers_towards_the_tail() {
+        let mut builder = App::build();
+        builder.add_system(segment_taper.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let segments = spawn_body(&mut app, &[(0, 0), (1, 0), (2, 0)]);
+        for segment in &segments {
+            app.world
+                .insert_one(*segment, Size::square(SEGMENT_SIZE))
+                .unwrap();
+        }
+
+        app.executor.initialize(&mut app.resources);
+        app.update();
+
+        let widths: Vec<f32> = segments
+            .iter()
+            .map(|e| app.world.get::<Size>(*e).unwrap().width)
+            .collect();
+        assert_eq!(
+            widths,
+            vec![
+                SEGMENT_SIZE,
+                (SEGMENT_SIZE + TAIL_SEGMENT_SIZE) / 2.0,
+                TAIL_SEGMENT_SIZE
+            ]
+        );
+    }
+
+    #[test]
+    fn safe_area_stops_shrinking_at_the_minimum() {
+        let mut bounds = SafeBounds::default();
+        let mut rings = 0;
+        while let Some(shrunk) = bounds.shrunk() {
+            bounds = shrunk;
+            rings += 1;
+        }
+        assert_eq!(rings, (ARENA_WIDTH as i32 - MIN_SAFE_SIZE) / 2);
+        assert_eq!(bounds.max.x - bounds.min.x + 1, MIN_SAFE_SIZE);
+        assert!(bounds.contains(&Position { x: 10, y: 10 }));
+        assert!(!bounds.contains(&SNAKE_START));
+    }
+
+    fn growth_app(segments: usize) -> App {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Arena>()
+            .init_resource::<RunStats>()
+            .init_resource::<Materials>()
+            .add_event::<GrowthEvent>()
+            .add_event::<VictoryEvent>()
+            .add_event::<GameOverEvent>()
+            .init_resource::<Obstacles>()
+            .add_system(snake_growth.system());
+        let mut app = std::mem::take(&mut builder.app);
+        spawn_body(&mut app, &vec![(0, 0); segments]);
+        let snake = snake(&app, Player::One);
+        app.world.get_mut::<SnakeSegments>(snake).unwrap().vacated = Some(Position::default());
+        app.executor.initialize(&mut app.resources);
+        app
+    }
+
+    #[test]
+    fn a_shrink_potion_halves_the_body() {
+        for &(before, after) in &[(5, 3), (4, 2), (1, 1)] {
+            let mut builder = App::build();
+            builder
+                .add_event::<PowerUpEvent>()
+                .add_system(shrink_potion.system());
+            let mut app = std::mem::take(&mut builder.app);
+            let cells: Vec<(i32, i32)> = (0..before as i32).map(|y| (0, y)).collect();
+            let entities = spawn_body(&mut app, &cells);
+            app.executor.initialize(&mut app.resources);
+            app.resources
+                .get_mut::<Events<PowerUpEvent>>()
+                .unwrap()
+                .send(PowerUpEvent {
+                    power_up: PowerUp::ShrinkPotion,
+                    snake: snake(&app, Player::One),
+                });
+            app.update();
+
+            let segments = snake_body(&app);
+            assert_eq!(segments.len(), after);
+            assert_eq!(
+                segments.iter().copied().collect::<Vec<_>>(),
+                entities[..after]
+            );
+            if after < before {
+                assert_eq!(
+                    segments.vacated,
+                    Some(Position {
+                        x: 0,
+                        y: after as i32
+                    })
+                );
+            }
+            assert_eq!(app.world.query::<&SnakeSegment>().count(), after);
+        }
+    }
+
+    #[test]
+    fn food_types_grow_or_shrink_the_snake() {
+        assert_eq!(FoodType::Normal.growth(), 1);
+        assert_eq!(FoodType::Double.growth(), DOUBLE_FOOD_GROWTH);
+        assert_eq!(FoodType::Golden.growth(), GOLDEN_FOOD_GROWTH);
+        assert_eq!(FoodType::Poison.growth(), -POISON_FOOD_SHRINK);
+
+        for &(food, before, after) in &[
+            (FoodType::Normal, 1, 2),
+            (FoodType::Double, 1, 1 + DOUBLE_FOOD_GROWTH as usize),
+            (FoodType::Golden, 1, 1 + GOLDEN_FOOD_GROWTH as usize),
+            (FoodType::Poison, 5, 5 - POISON_FOOD_SHRINK as usize),
+            (FoodType::Poison, 3, 1),
+            (FoodType::Poison, 2, 2),
+        ] {
+            let mut app = growth_app(before);
+            let snake = snake(&app, Player::One);
+            app.resources
+                .get_mut::<Events<GrowthEvent>>()
+                .unwrap()
+                .send(GrowthEvent {
+                    snake,
+                    food,
+                    amount: food.growth(),
+                    position: Position::default(),
+                });
+            app.update();
+            assert_eq!(
+                snake_body(&app).len(),
+                after,
+                "{:?} eaten by a snake of {}",
+                food,
+                before
+            );
+            assert_eq!(
+                app.world.query::<&SnakeSegment>().count(),
+                after,
+                "{:?} eaten by a snake of {}",
+                food,
+                before
+            );
+            let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+            let poisoned = events
+                .get_reader()
+                .iter(&events)
+                .any(|death| death.reason == GameOverReason::Poisoned);
+            // Poison that would take the last segment kills instead.
+            assert_eq!(poisoned, food == FoodType::Poison && after == before);
+        }
+    }
+
+    /// Runs one move of a snake heading left whose head at `head` turns
+    /// `direction`, and returns the crash it had, if any.
+    fn crash(head: (i32, i32), direction: Direction, body: &[(i32, i32)]) -> Option<GameOverEvent> {
+        move_once(GameRules::default(), &[], head, direction, body).0
+    }
+
+    /// Like `crash`, under `rules` and with walls at `obstacles`, also
+    /// returning where the head ended up.
+    fn move_once(
+        rules: GameRules,
+        obstacles: &[(i32, i32)],
+        head: (i32, i32),
+        direction: Direction,
+        body: &[(i32, i32)],
+    ) -> (Option<GameOverEvent>, Position) {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .add_resource(rules)
+            .add_resource(Obstacles(
+                obstacles.iter().map(|&(x, y)| Position { x, y }).collect(),
+            ))
+            .init_resource::<SafeBounds>()
+            .add_resource(Portals(Vec::new()))
+            .init_resource::<ActiveEffects>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .add_event::<GameOverEvent>()
+            .add_system(snake_movement.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.world.spawn((
+            SnakeHead {
+                direction: Direction::Left,
+                try_direction: direction,
+                queued_turns: VecDeque::new(),
+            },
+            Position {
+                x: head.0,
+                y: head.1,
+            },
+        ));
+        spawn_body(&mut app, body);
+        app.executor.initialize(&mut app.resources);
+        app.update();
+        let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+        let crash = events.get_reader().iter(&events).next().copied();
+        let head = *app
+            .world
+            .query::<With<SnakeHead, &Position>>()
+            .next()
+            .unwrap();
+        (crash, head)
+    }
+
+    #[test]
+    fn obstacles_are_fatal() {
+        let (crash, _) = move_once(
+            GameRules::default(),
+            &[(4, 5)],
+            (5, 5),
+            Direction::Left,
+            &[(6, 5)],
+        );
+        assert_eq!(
+            crash,
+            Some(GameOverEvent {
+                player: Player::One,
+                reason: GameOverReason::HitWall,
+                position: Position { x: 5, y: 5 },
+            })
+        );
+        // Wrapping around the edge does not get past a wall on the other side.
+        let wrap = GameRules {
+            wrap_around: true,
+            ..Default::default()
+        };
+        let right = ARENA_WIDTH as i32 - 1;
+        let (crash, _) = move_once(wrap, &[(right, 5)], (0, 5), Direction::Left, &[(1, 5)]);
+        assert!(crash.is_some());
+    }
+
+    #[test]
+    fn a_starving_snake_loses_its_tail_then_dies() {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .add_resource(GameRules {
+                starvation: Some(2),
+                ..Default::default()
+            })
+            .add_event::<GrowthEvent>()
+            .add_event::<ShrinkEvent>()
+            .add_event::<GameOverEvent>()
+            .add_system(hunger.system())
+            .add_system_to_stage(stage::POST_UPDATE, snake_shrink.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.world.spawn((
+            SnakeHead {
+                direction: Direction::Up,
+                try_direction: Direction::Up,
+                queued_turns: VecDeque::new(),
+            },
+            Position { x: 5, y: 5 },
+        ));
+        spawn_body(&mut app, &[(5, 4), (5, 3)]);
+        app.executor.initialize(&mut app.resources);
+        let mut deaths = EventReader::<GameOverEvent>::default();
+        let snake = snake(&app, Player::One);
+        let mut tick = |app: &mut App, ate: bool| {
+            if ate {
+                app.resources
+                    .get_mut::<Events<GrowthEvent>>()
+                    .unwrap()
+                    .send(GrowthEvent {
+                        snake,
+                        food: FoodType::Normal,
+                        amount: 0,
+                        position: Position { x: 5, y: 6 },
+                    });
+            }
+            app.update();
+            let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+            let death = deaths.iter(&events).next().map(|death| death.reason);
+            (snake_body(app).len(), death)
+        };
+
+        assert_eq!(tick(&mut app, false), (2, None));
+        // Eating resets the count.
+        assert_eq!(tick(&mut app, true), (2, None));
+        assert_eq!(tick(&mut app, false), (2, None));
+        assert_eq!(tick(&mut app, false), (1, None));
+        assert_eq!(tick(&mut app, false), (1, None));
+        assert_eq!(tick(&mut app, false), (1, Some(GameOverReason::Starved)));
+    }
+
+    #[test]
+    fn the_obstacle_level_leaves_the_start_portals_and_arena_clear() {
+        let arena = Arena::default();
+        let portals = Portals::default();
+        for wall in &Obstacles::level().0 {
+            assert!(arena.contains(wall));
+            assert!(portals.exit(wall).is_none());
+            assert_ne!(wall.x, SNAKE_START.x, "{:?} is in the snake's way", wall);
+        }
+    }
+
+    #[test]
+    fn wrap_around_leaves_through_one_edge_and_enters_the_other() {
+        let wrap = GameRules {
+            wrap_around: true,
+            ..Default::default()
+        };
+        let right = ARENA_WIDTH as i32 - 1;
+        let top = ARENA_HEIGHT as i32 - 1;
+        assert_eq!(
+            move_once(wrap, &[], (0, 5), Direction::Left, &[(1, 5)]),
+            (None, Position { x: right, y: 5 })
+        );
+        assert_eq!(
+            move_once(wrap, &[], (3, top), Direction::Up, &[(4, top)]),
+            (None, Position { x: 3, y: 0 })
+        );
+        assert_eq!(
+            move_once(
+                GameRules::default(),
+                &[],
+                (0, 5),
+                Direction::Left,
+                &[(1, 5)]
+            )
+            .0
+            .map(|crash| crash.reason),
+            Some(GameOverReason::HitWall)
+        );
+    }
+
+    #[test]
+    fn the_head_may_follow_the_tail() {
+        assert_eq!(
+            crash((5, 5), Direction::Up, &[(6, 5), (6, 6), (5, 6)]),
+            None
+        );
+    }
+
+    #[test]
+    fn the_occupied_cells_follow_the_body() {
+        let cell = |x, y| Position { x, y };
+        let mut segments = SnakeSegments::default();
+        segments.push(Entity::new(1), cell(3, 2));
+        segments.push(Entity::new(2), cell(3, 1));
+        // Growth stacks a new segment on the tail.
+        segments.push(Entity::new(3), cell(3, 1));
+        assert!(segments.blocks(&cell(3, 1)));
+
+        segments.advance(cell(3, 3));
+        assert!(segments.contains(&cell(3, 1)));
+        assert!(!segments.blocks(&cell(3, 1)));
+        assert!(segments.blocks(&cell(3, 3)));
+        segments.advance(cell(4, 3));
+        assert!(!segments.contains(&cell(3, 1)));
+
+        segments.truncate(1).count();
+        assert!(segments.contains(&cell(4, 3)));
+        assert!(!segments.contains(&cell(3, 3)));
+        assert_eq!(segments.occupied.len(), 1);
+    }
+
+    #[test]
+    fn moving_into_the_body_crashes_immediately() {
+        assert_eq!(
+            crash((5, 5), Direction::Up, &[(6, 5), (6, 6), (5, 6), (5, 7)]),
+            Some(GameOverEvent {
+                player: Player::One,
+                reason: GameOverReason::HitSelf,
+                position: Position { x: 5, y: 6 },
+            })
+        );
+    }
+
+    /// A snake's head, heading and body in `versus_move`.
+    type Placed<'a> = ((i32, i32), Direction, &'a [(i32, i32)]);
+
+    /// Moves Player 1 and Player 2 once from `snakes` and returns every death.
+    fn versus_move(snakes: &[Placed]) -> Vec<GameOverEvent> {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .init_resource::<SafeBounds>()
+            .add_resource(Portals(Vec::new()))
+            .init_resource::<ActiveEffects>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .add_event::<GameOverEvent>()
+            .add_system(snake_movement.system());
+        let mut app = std::mem::take(&mut builder.app);
+        for (player, &((x, y), direction, body)) in [Player::One, Player::Two].iter().zip(snakes) {
+            let mut segments = SnakeSegments::default();
+            for &(x, y) in body {
+                let position = Position { x, y };
+                segments.push(app.world.spawn((SnakeSegment, position)), position);
+            }
+            app.world.spawn((
+                *player,
+                SnakeHead {
+                    direction,
+                    try_direction: direction,
+                    queued_turns: VecDeque::new(),
+                },
+                Position { x, y },
+                segments,
+            ));
+        }
+        app.executor.initialize(&mut app.resources);
+        app.update();
+        let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+        let deaths = events.get_reader().iter(&events).copied().collect();
+        deaths
+    }
+
+    #[test]
+    fn running_into_the_rival_crashes() {
+        // Player 1 turns into the body of Player 2, which heads on unharmed.
+        assert_eq!(
+            versus_move(&[
+                ((5, 5), Direction::Right, &[(4, 5)]),
+                ((6, 4), Direction::Down, &[(6, 5), (6, 6)]),
+            ]),
+            [GameOverEvent {
+                player: Player::One,
+                reason: GameOverReason::HitRival,
+                position: Position { x: 6, y: 5 },
+            }]
+        );
+        // Meeting head on kills both.
+        let deaths = versus_move(&[
+            ((5, 5), Direction::Right, &[(4, 5)]),
+            ((7, 5), Direction::Left, &[(8, 5)]),
+        ]);
+        let players: Vec<Player> = deaths.iter().map(|death| death.player).collect();
+        assert_eq!(players, [Player::One, Player::Two]);
+        assert!(deaths
+            .iter()
+            .all(|death| death.reason == GameOverReason::HitRival));
+        // Passing side by side is harmless.
+        assert!(versus_move(&[
+            ((5, 5), Direction::Up, &[(5, 4)]),
+            ((6, 5), Direction::Down, &[(6, 6)]),
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn versus_snakes_start_apart_and_the_survivor_wins() {
+        let arena = Arena::default();
+        let start = SnakeStart::default();
+        let players = |mode: GameMode, versus| mode.players(VersusPlayers(versus));
+        assert_eq!(start.cells(players(GameMode::Classic, 4), &arena).len(), 2);
+        assert_eq!(players(GameMode::Coop, 4).len(), 2);
+        for snakes in 2..=4 {
+            let cells = start.cells(players(GameMode::Versus, snakes), &arena);
+            assert_eq!(cells.len(), 2 * snakes);
+            assert!(cells.iter().all(|cell| arena.contains(cell)));
+            let apart: HashSet<&Position> = cells.iter().collect();
+            assert_eq!(apart.len(), cells.len());
+        }
+
+        let death = |player| GameOverEvent {
+            player,
+            reason: GameOverReason::HitRival,
+            position: Position::default(),
+        };
+        let two = &Player::ALL[..2];
+        assert_eq!(
+            versus_result(GameMode::Versus, &[death(Player::One)], two, &[3, 5]),
+            "Player 2 wins! 3 to 5"
+        );
+        assert_eq!(
+            versus_result(GameMode::Versus, &[death(Player::Two)], two, &[3, 5]),
+            "Player 1 wins! 3 to 5"
+        );
+        assert_eq!(
+            versus_result(GameMode::VersusBot, &[death(Player::One)], two, &[3, 5]),
+            "The computer wins! 3 to 5"
+        );
+        assert_eq!(
+            versus_result(
+                GameMode::Versus,
+                &[death(Player::Two), death(Player::One)],
+                two,
+                &[4, 4]
+            ),
+            "Both crashed! It's a draw! 4 to 4"
+        );
+
+        // With more snakes the best scorer of those left standing wins.
+        let four = &Player::ALL;
+        let scores = [9, 2, 6, 4];
+        assert_eq!(
+            versus_result(GameMode::Versus, &[death(Player::One)], four, &scores),
+            "Player 3 wins! 9 to 2 to 6 to 4"
+        );
+        assert_eq!(
+            versus_result(
+                GameMode::Versus,
+                &[death(Player::One), death(Player::Three)],
+                four,
+                &scores
+            ),
+            "Player 4 wins! 9 to 2 to 6 to 4"
+        );
+        assert_eq!(
+            versus_result(GameMode::Versus, &[death(Player::One)], four, &[9, 4, 1, 4]),
+            "It's a draw! 9 to 4 to 1 to 4"
+        );
+        let everyone: Vec<GameOverEvent> = four.iter().map(|player| death(*player)).collect();
+        assert_eq!(
+            versus_result(GameMode::Versus, &everyone, four, &scores),
+            "Everyone crashed! It's a draw! 9 to 2 to 6 to 4"
+        );
+    }
+
+    #[test]
+    fn the_tail_cell_stays_occupied_while_growing() {
+        // Growth stacks new segments on the cell the tail left, so the tail
+        // does not vacate its cell on the next move.
+        assert!(crash((5, 5), Direction::Up, &[(6, 5), (6, 6), (5, 6), (5, 6)]).is_some());
+    }
+
+    #[test]
+    fn arena_bounds_include_both_edges() {
+        let arena = Arena {
+            width: 4,
+            height: 3,
+        };
+        for &(x, y, inside) in &[
+            (0, 0, true),
+            (3, 2, true),
+            (-1, 0, false),
+            (0, -1, false),
+            (i32::MIN, i32::MIN, false),
+            (4, 0, false),
+            (0, 3, false),
+            (3, 3, false),
+        ] {
+            assert_eq!(arena.contains(&Position { x, y }), inside, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn a_seed_pins_down_the_game_rng_from_startup() {
+        let options = Options {
+            seed: Some(11),
+            ..Default::default()
+        };
+        let draw = || GameRng::from_options(&options).0.gen::<u64>();
+        assert_eq!(draw(), draw());
+    }
+
+    #[test]
+    fn random_cells_stay_in_the_arena() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for &(width, height) in &[(1, 1), (2, 5), (ARENA_WIDTH, ARENA_HEIGHT)] {
+            let arena = Arena { width, height };
+            for _ in 0..1000 {
+                assert!(arena.contains(&arena.random_cell(&mut rng)));
+            }
+        }
+        let arena = Arena {
+            width: 2,
+            height: 2,
+        };
+        let occupied: HashSet<Position> = [(0, 0), (1, 0), (0, 1)]
+            .iter()
+            .map(|&(x, y)| Position { x, y })
+            .collect();
+        for _ in 0..100 {
+            assert_eq!(
+                arena.random_free_cell(&occupied, &mut rng),
+                Some(Position { x: 1, y: 1 })
+            );
+        }
+    }
+
+    /// Presses `keys` one after another on frames within a single move tick
+    /// of a snake heading right, then makes three moves and returns the
+    /// directions they took.
+    fn steer(keys: &[KeyCode]) -> Vec<Direction> {
+        let mut builder = App::build();
+        builder
+            .add_resource(finished_move_timer())
+            .add_resource(AppState::Playing)
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<GamepadButton>>()
+            .init_resource::<Axis<GamepadAxis>>()
+            .init_resource::<Gamepads>()
+            .init_resource::<ReplayMode>()
+            .init_resource::<NameEntry>()
+            .init_resource::<SafeBounds>()
+            .add_resource(Portals(Vec::new()))
+            .init_resource::<ActiveEffects>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<Invulnerable>()
+            .init_resource::<RunStats>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<RunTick>()
+            .add_event::<GameOverEvent>()
+            .add_resource(GameMode::Classic)
+            .add_system(handle_movement.system())
+            .init_resource::<GameRules>()
+            .init_resource::<Obstacles>()
+            .add_stage_after(stage::UPDATE, snake_stage::FIXED_UPDATE)
+            .add_system_to_stage(
+                snake_stage::FIXED_UPDATE,
+                fixed_update.thread_local_system(),
+            )
+            .add_step_system(snake_stage::MOVEMENT, snake_movement.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.world.spawn((
+            SnakeHead {
+                direction: Direction::Right,
+                try_direction: Direction::Right,
+                queued_turns: VecDeque::new(),
+            },
+            Position { x: 5, y: 5 },
+        ));
+        spawn_body(&mut app, &[(4, 5)]);
+        app.executor.initialize(&mut app.resources);
+
+        // No time passes while the keys go down, so no move is due.
+        for key in keys {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(*key);
+            app.update();
+            let mut input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            input.release(*key);
+            input.update();
+        }
+        let mut directions = Vec::new();
+        for _ in 0..3 {
+            let mut snake_timer = app.resources.get_mut::<SnakeMoveTimer>().unwrap();
+            snake_timer.0.elapsed = snake_timer.0.duration;
+            drop(snake_timer);
+            app.update();
+            directions.push(app.world.query::<&SnakeHead>().next().unwrap().direction);
+        }
+
+        let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+        assert!(events.get_reader().iter(&events).next().is_none());
+        directions
+    }
+
+    #[test]
+    fn quick_turns_never_reverse_into_the_neck() {
+        use Direction::*;
+        assert_eq!(steer(&[KeyCode::Up, KeyCode::Down]), [Up, Up, Up]);
+        assert_eq!(steer(&[KeyCode::Left]), [Right, Right, Right]);
+        assert_eq!(steer(&[KeyCode::Up, KeyCode::Up]), [Up, Up, Up]);
+    }
+
+    #[test]
+    fn quick_turns_are_buffered() {
+        use Direction::*;
+        assert_eq!(steer(&[KeyCode::Up, KeyCode::Left]), [Up, Left, Left]);
+        assert_eq!(steer(&[KeyCode::Up, KeyCode::Right]), [Up, Right, Right]);
+        // Only `TURN_BUFFER` turns wait behind the pending one.
+        assert_eq!(
+            steer(&[KeyCode::Up, KeyCode::Left, KeyCode::Down, KeyCode::Right]),
+            [Up, Left, Down]
+        );
+    }
+
+    #[test]
+    fn hunters_step_greedily_towards_the_head() {
+        let bounds = SafeBounds::default();
+        let none = HashSet::new();
+        let at = |x, y| Position { x, y };
+        // The larger gap is closed first.
+        assert_eq!(hunter_step(at(5, 5), at(9, 6), &bounds, &none), at(6, 5));
+        assert_eq!(hunter_step(at(5, 5), at(4, 1), &bounds, &none), at(5, 4));
+        // Blocked on the preferred axis, it takes the other one.
+        let wall: HashSet<Position> = [at(6, 5)].iter().copied().collect();
+        assert_eq!(hunter_step(at(5, 5), at(9, 6), &bounds, &wall), at(5, 6));
+        // In line with the head and blocked, it waits rather than backing off.
+        assert_eq!(hunter_step(at(5, 5), at(9, 5), &bounds, &wall), at(5, 5));
+        // Already there.
+        assert_eq!(hunter_step(at(5, 5), at(5, 5), &bounds, &none), at(5, 5));
+    }
+
+    #[test]
+    fn cornered_hunters_stay_put() {
+        let bounds = SafeBounds::default();
+        let at = |x, y| Position { x, y };
+        let blocked: HashSet<Position> = [at(1, 0), at(0, 1)].iter().copied().collect();
+        assert_eq!(hunter_step(at(0, 0), at(5, 5), &bounds, &blocked), at(0, 0));
+        // The edge of the safe area counts as blocked too.
+        let shrunk = bounds.shrunk().unwrap();
+        assert_eq!(
+            hunter_step(at(1, 1), at(0, 1), &shrunk, &HashSet::new()),
+            at(1, 1)
+        );
+    }
+
+    #[test]
+    fn a_shield_takes_one_hit_and_holds_the_snake_back_a_move() {
+        let mut app = step_app(&[]);
+        app.resources
+            .insert(Obstacles(vec![Position { x: 3, y: 4 }]));
+        app.resources
+            .get_mut::<ActiveEffects>()
+            .unwrap()
+            .grant(PowerUp::Shield);
+        let crashed = |app: &App| {
+            let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+            events.get_reader().iter(&events).count() > 0
+        };
+        let head = |app: &mut App| {
+            *app.world
+                .query::<With<SnakeHead, &Position>>()
+                .next()
+                .unwrap()
+        };
+
+        app.update();
+        assert!(!crashed(&app));
+        assert_eq!(head(&mut app), Position { x: 3, y: 3 });
+        assert!(!app
+            .resources
+            .get::<ActiveEffects>()
+            .unwrap()
+            .active(PowerUp::Shield));
+        let segments = snake_body(&app);
+        assert_eq!(segments.positions, [Position { x: 3, y: 2 }]);
+
+        app.update();
+        assert!(crashed(&app));
+    }
+
+    #[test]
+    fn wall_deaths_report_the_last_cell_inside() {
+        let death = crash((0, 7), Direction::Left, &[(1, 7)]).unwrap();
+        assert_eq!(death.reason, GameOverReason::HitWall);
+        assert_eq!(death.position, Position { x: 0, y: 7 });
+        assert_eq!(death.to_string(), "You ran into the wall at (0, 7)");
+    }
+
+    #[test]
+    fn rotating_the_body_matches_shifting_it() {
+        let turns = [
+            (10, Direction::Right),
+            (20, Direction::Down),
+            (30, Direction::Left),
+            (38, Direction::Up),
+            (45, Direction::Right),
+        ];
+        let food = [(3, 6), (3, 7), (8, 13), (13, 8), (9, 3), (5, 6)];
+        let mut app = step_app(&food);
+        app.resources.insert(Portals(Vec::new()));
+
+        // The body bookkeeping as it was before `SnakeSegments` rotated: every
+        // segment shifts one place along and the tail cell is pushed back on
+        // growth.
+        let mut head = Position { x: 3, y: 3 };
+        let mut direction = Direction::Up;
+        let mut body = vec![Position { x: 3, y: 2 }];
+        let mut uneaten: HashSet<Position> = food.iter().map(|&(x, y)| Position { x, y }).collect();
+
+        for tick in 0..50 {
+            if let Some((_, turn)) = turns.iter().find(|(at, _)| *at == tick) {
+                direction = *turn;
+                for mut snake_head in app.world.query_mut::<&mut SnakeHead>() {
+                    snake_head.try_direction = *turn;
+                }
+            }
+            // The interval shortens as the snake eats, so each frame is made
+            // exactly as long as what is left of it.
+            let left = {
+                let timer = &app.resources.get::<SnakeMoveTimer>().unwrap().0;
+                timer.duration - timer.elapsed
+            };
+            app.resources.get_mut::<GameClock>().unwrap().delta_seconds = left;
+            app.update();
+
+            body.insert(0, head);
+            let vacated = body.pop().unwrap();
+            head = match direction {
+                Direction::Left => Position {
+                    x: head.x - 1,
+                    ..head
+                },
+                Direction::Right => Position {
+                    x: head.x + 1,
+                    ..head
+                },
+                Direction::Up => Position {
+                    y: head.y + 1,
+                    ..head
+                },
+                Direction::Down => Position {
+                    y: head.y - 1,
+                    ..head
+                },
+            };
+            if uneaten.remove(&head) {
+                body.push(vacated);
+            }
+
+            let segments = snake_body(&app);
+            let layout: Vec<Position> = segments
+                .iter()
+                .map(|e| *app.world.get::<Position>(*e).unwrap())
+                .collect();
+            assert_eq!(layout, body, "body after tick {}", tick);
+            assert!(segments.positions.iter().eq(body.iter()));
+            let (_, snake_head) = app.world.query::<(&SnakeHead, &Position)>().next().unwrap();
+            assert_eq!(*snake_head, head, "head after tick {}", tick);
+        }
+        assert!(uneaten.is_empty());
+        assert_eq!(body.len(), 1 + food.len());
+    }
+
+    #[test]
+    fn boost_halves_the_interval_until_stamina_runs_out() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<GameClock>()
+            .init_resource::<Input<KeyCode>>()
+            .add_resource(Countdown(expired_timer(COUNTDOWN_DURATION)))
+            .add_resource(AppState::Playing)
+            .init_resource::<NameEntry>()
+            .add_resource(ReplayMode::Record(None))
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<Boost>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<BaseMoveInterval>()
+            .init_resource::<Difficulty>()
+            .init_resource::<ActiveEffects>()
+            .init_resource::<SpeedUp>()
+            .init_resource::<RunStats>()
+            .add_resource(finished_move_timer())
+            .add_system(boost.system())
+            .add_system(slow_motion.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let base = BaseMoveInterval::default().0;
+        let interval = |app: &App| app.resources.get::<SnakeMoveTimer>().unwrap().0.duration;
+
+        app.resources
+            .get_mut::<Input<KeyCode>>()
+            .unwrap()
+            .press(KeyCode::LShift);
+        app.resources.get_mut::<GameClock>().unwrap().delta_seconds = 1.0;
+        app.update();
+        assert_eq!(interval(&app), base * BOOST_FACTOR);
+
+        // Slow motion scales the boosted interval rather than replacing it.
+        app.resources
+            .get_mut::<ActiveEffects>()
+            .unwrap()
+            .grant(PowerUp::SlowMotion);
+        app.update();
+        assert_eq!(interval(&app), base * BOOST_FACTOR * SLOW_MOTION_FACTOR);
+        *app.resources.get_mut::<ActiveEffects>().unwrap() = ActiveEffects::default();
+
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(app.resources.get::<Boost>().unwrap().stamina, 0.0);
+        assert_eq!(interval(&app), base);
+
+        app.resources
+            .get_mut::<ActiveEffects>()
+            .unwrap()
+            .grant(PowerUp::SpeedBoost);
+        app.update();
+        assert_eq!(interval(&app), base * SPEED_BOOST_FACTOR);
+        *app.resources.get_mut::<ActiveEffects>().unwrap() = ActiveEffects::default();
+
+        app.resources
+            .get_mut::<Input<KeyCode>>()
+            .unwrap()
+            .release(KeyCode::LShift);
+        app.update();
+        assert!(app.resources.get::<Boost>().unwrap().stamina > 0.0);
+        assert_eq!(interval(&app), base);
+        assert_eq!(
+            app.resources
+                .get::<ReplayRecorder>()
+                .unwrap()
+                .0
+                .boosts
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn the_interval_shortens_every_few_foods_down_to_the_floor() {
+        let speed_up = SpeedUp {
+            every: 3,
+            step: 0.02,
+            floor: 0.1,
+        };
+        let interval = |food_eaten| speed_up.interval(0.15, food_eaten);
+        assert_eq!(interval(0), 0.15);
+        assert_eq!(interval(2), 0.15);
+        assert!((interval(3) - 0.13).abs() < 1e-6);
+        assert!((interval(5) - 0.13).abs() < 1e-6);
+        assert!((interval(6) - 0.11).abs() < 1e-6);
+        assert_eq!(interval(9), 0.1);
+        assert_eq!(interval(300), 0.1);
+        assert_eq!(speed_up.interval(0.05, 300), 0.05);
+        let never = SpeedUp {
+            every: 0,
+            ..speed_up
+        };
+        assert_eq!(never.interval(0.15, 300), 0.15);
+    }
+
+    #[test]
+    fn space_and_escape_move_between_screens() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Time>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<ReplayMode>()
+            .init_resource::<NameEntry>()
+            .init_resource::<DeathAnimation>()
+            .init_resource::<AppState>()
+            .init_resource::<MenuCursor>()
+            .init_resource::<SettingsOrigin>()
+            .add_event::<EndRunEvent>()
+            .add_system(app_state.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let press = |app: &mut App, key: KeyCode| {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(key);
+            app.update();
+            let mut input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            input.release(key);
+            input.update();
+            *app.resources.get::<AppState>().unwrap()
+        };
+
+        // The menu is left through `menu`, which asks `game_over` for a run.
+        assert_eq!(press(&mut app, KeyCode::Space), AppState::Menu);
+        *app.resources.get_mut::<AppState>().unwrap() = AppState::Playing;
+        assert_eq!(press(&mut app, KeyCode::Escape), AppState::Paused);
+        assert_eq!(press(&mut app, KeyCode::Escape), AppState::Playing);
+
+        // O opens the settings from the pause screen, which `menu` leaves.
+        assert_eq!(press(&mut app, KeyCode::O), AppState::Playing);
+        assert_eq!(press(&mut app, KeyCode::Escape), AppState::Paused);
+        assert_eq!(press(&mut app, KeyCode::O), AppState::Settings);
+        assert_eq!(
+            app.resources.get::<SettingsOrigin>().unwrap().0,
+            AppState::Paused
+        );
+
+        // Leaving the game over screen is up to `game_over`, which restarts.
+        *app.resources.get_mut::<AppState>().unwrap() = AppState::GameOver;
+        assert_eq!(press(&mut app, KeyCode::Escape), AppState::GameOver);
+        assert_eq!(press(&mut app, KeyCode::Space), AppState::GameOver);
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::GameOver);
+        let events = app.resources.get::<Events<EndRunEvent>>().unwrap();
+        let restarts = events.get_reader().iter(&events).count();
+        assert_eq!(restarts, 2);
+    }
+
+    #[test]
+    fn a_long_frame_plays_every_move_it_owes() {
+        let mut snake_timer = SnakeMoveTimer(Timer::from_seconds(0.1, true));
+        snake_timer.0.elapsed = 0.25;
+        assert_eq!(snake_timer.take_due(), 2);
+        assert!(snake_timer.0.finished);
+        assert!((snake_timer.0.elapsed - 0.05).abs() < 1e-6);
+        assert_eq!(snake_timer.take_due(), 0);
+        assert!(!snake_timer.0.finished);
+
+        // After a stall only so many moves are caught up on.
+        snake_timer.0.elapsed = 10.0;
+        assert_eq!(snake_timer.take_due(), MAX_MOVES_PER_FRAME);
+        assert!(snake_timer.0.elapsed < snake_timer.0.duration);
+    }
+
+    #[test]
+    fn pausing_freezes_the_move_timer() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Time>()
+            .init_resource::<Input<KeyCode>>()
+            .add_resource(ReplayMode::Record(None))
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<NameEntry>()
+            .init_resource::<DeathAnimation>()
+            .init_resource::<KeyBindings>()
+            .add_resource(AppState::Playing)
+            .init_resource::<MenuCursor>()
+            .init_resource::<SettingsOrigin>()
+            .init_resource::<GameClock>()
+            .add_resource(Countdown(expired_timer(COUNTDOWN_DURATION)))
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(10.0, true)))
+            .add_event::<EndRunEvent>()
+            .add_system(app_state.system())
+            .add_system(game_clock.system())
+            .add_system(snake_timer.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let overlay = app.world.spawn((PauseOverlay, Draw::default()));
+        app.resources.get_mut::<Time>().unwrap().delta_seconds = 0.1;
+        let press = |app: &mut App, key: KeyCode| {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(key);
+            app.update();
+            let mut input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            input.release(key);
+            input.update();
+        };
+        let elapsed = |app: &App| app.resources.get::<SnakeMoveTimer>().unwrap().0.elapsed;
+        let visible = |app: &App| app.world.get::<Draw>(overlay).unwrap().is_visible;
+
+        app.update();
+        let before = elapsed(&app);
+        assert!(before > 0.0);
+        press(&mut app, KeyCode::P);
+        assert_eq!(*app.resources.get::<AppState>().unwrap(), AppState::Paused);
+        assert!(visible(&app));
+        app.update();
+        app.update();
+        assert_eq!(elapsed(&app), before);
+
+        press(&mut app, KeyCode::P);
+        assert_eq!(*app.resources.get::<AppState>().unwrap(), AppState::Playing);
+        assert!(!visible(&app));
+        assert!(elapsed(&app) > before);
+
+        // A stalled frame pauses the game, a merely long one does not.
+        app.resources.get_mut::<Time>().unwrap().delta_seconds = STALL_PAUSE;
+        app.update();
+        assert_eq!(*app.resources.get::<AppState>().unwrap(), AppState::Playing);
+        app.resources.get_mut::<Time>().unwrap().delta_seconds = STALL_PAUSE + 0.1;
+        app.update();
+        assert_eq!(*app.resources.get::<AppState>().unwrap(), AppState::Paused);
+    }
+
+    #[test]
+    fn a_larger_arena_brings_back_what_a_smaller_one_left_out() {
+        let options = Options {
+            obstacles: true,
+            ..Default::default()
+        };
+        let layout = ArenaLayout {
+            start: Position { x: 14, y: 14 },
+            ..ArenaLayout::from_options(&options)
+        };
+        let (start, portals, obstacles) = layout.fit(&Arena::default());
+        let mut builder = App::build();
+        builder
+            .init_resource::<Materials>()
+            .init_resource::<Arena>()
+            .add_resource(SafeBounds::full(&Arena::default()))
+            .add_resource(start)
+            .add_resource(portals)
+            .add_resource(obstacles)
+            .add_resource(layout.clone())
+            .add_event::<ResizeArenaEvent>()
+            .add_system(resize_arena.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let resize = |app: &mut App, side: u32| {
+            app.resources
+                .get_mut::<Events<ResizeArenaEvent>>()
+                .unwrap()
+                .send(ResizeArenaEvent(Arena {
+                    width: side,
+                    height: side,
+                }));
+            app.update();
+            (
+                app.resources.get::<SnakeStart>().unwrap().0,
+                app.resources.get::<Portals>().unwrap().0.len(),
+                app.resources.get::<Obstacles>().unwrap().0.len(),
+            )
+        };
+
+        let (start, portals, walls) = resize(&mut app, 10);
+        assert_eq!(start, SNAKE_START);
+        assert!(portals < layout.portals.len());
+        assert!(walls < layout.walls.len());
+        assert_eq!(
+            resize(&mut app, 20),
+            (layout.start, layout.portals.len(), layout.walls.len())
+        );
+    }
+
+    #[test]
+    fn the_speed_setting_changes_the_move_timer_at_once() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Input<KeyCode>>()
+            .add_resource(AppState::Settings)
+            .init_resource::<MenuCursor>()
+            .add_resource(SettingsOrigin(AppState::Paused))
+            .init_resource::<NextDifficulty>()
+            .init_resource::<RunOverrides>()
+            .init_resource::<BaseMoveInterval>()
+            .init_resource::<DailyChallenge>()
+            .init_resource::<DailyBest>()
+            .init_resource::<Profiles>()
+            .init_resource::<ProfileEntry>()
+            .add_resource(GameMode::Classic)
+            .init_resource::<Themes>()
+            .init_resource::<Volume>()
+            .init_resource::<ShakeIntensity>()
+            .init_resource::<Patterns>()
+            .init_resource::<Arena>()
+            .init_resource::<GameRules>()
+            .add_resource(Difficulty::Easy)
+            .init_resource::<Boost>()
+            .init_resource::<SpeedUp>()
+            .init_resource::<RunStats>()
+            .init_resource::<ActiveEffects>()
+            .add_resource(SnakeMoveTimer(Timer::from_seconds(1.0, true)))
+            .add_event::<EndRunEvent>()
+            .add_event::<ResizeArenaEvent>()
+            .add_system(menu.system())
+            .add_system(slow_motion.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        app.resources.get_mut::<MenuCursor>().unwrap().0 = SettingsItem::Speed.line();
+        let pick = |app: &mut App| {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(KeyCode::Return);
+            app.update();
+            let mut input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            input.release(KeyCode::Return);
+            input.update();
+            app.resources.get::<SnakeMoveTimer>().unwrap().0.duration
+        };
+
+        // Picked on the pause screen's settings, the speed holds from the
+        // next move and for the runs after it.
+        assert_eq!(pick(&mut app), MOVE_INTERVALS[2]);
+        assert_eq!(
+            app.resources.get::<RunOverrides>().unwrap().move_interval,
+            Some(MOVE_INTERVALS[2])
+        );
+        assert_eq!(pick(&mut app), MOVE_INTERVALS[3]);
+        assert_eq!(pick(&mut app), MOVE_INTERVALS[4]);
+        assert_eq!(pick(&mut app), MOVE_INTERVALS[0]);
+    }
+
+    #[test]
+    fn the_menu_leads_to_settings_play_and_quit() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<AppState>()
+            .init_resource::<MenuCursor>()
+            .init_resource::<SettingsOrigin>()
+            .init_resource::<NextDifficulty>()
+            .init_resource::<RunOverrides>()
+            .init_resource::<BaseMoveInterval>()
+            .init_resource::<DailyChallenge>()
+            .init_resource::<DailyBest>()
+            .init_resource::<Profiles>()
+            .init_resource::<ProfileEntry>()
+            .add_resource(GameMode::Classic)
+            .init_resource::<Themes>()
+            .init_resource::<Volume>()
+            .init_resource::<ShakeIntensity>()
+            .init_resource::<Patterns>()
+            .init_resource::<Arena>()
+            .init_resource::<GameRules>()
+            .add_event::<EndRunEvent>()
+            .add_event::<ResizeArenaEvent>()
+            .add_system(menu.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let press = |app: &mut App, key: KeyCode| {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(key);
+            app.update();
+            let mut input = app.resources.get_mut::<Input<KeyCode>>().unwrap();
+            input.release(key);
+            input.update();
+            *app.resources.get::<AppState>().unwrap()
+        };
+
+        assert_eq!(press(&mut app, KeyCode::Down), AppState::Menu);
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Settings);
+        press(&mut app, KeyCode::Return);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        let resizes = app.resources.get::<Events<ResizeArenaEvent>>().unwrap();
+        let resized: Vec<Arena> = resizes.get_reader().iter(&resizes).map(|e| e.0).collect();
+        assert_eq!(resized, vec![next_arena_size(&Arena::default())]);
+        drop(resizes);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        let wrap = GameRules::default().wrap_around;
+        assert_eq!(app.resources.get::<GameRules>().unwrap().wrap_around, !wrap);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        assert_eq!(
+            app.resources.get::<NextDifficulty>().unwrap().0,
+            Difficulty::Hard
+        );
+        assert_eq!(
+            *app.resources.get::<GameMode>().unwrap(),
+            GameMode::TimeAttack
+        );
+        assert_eq!(app.resources.get::<Themes>().unwrap().chosen(), "Dark");
+        assert_eq!(press(&mut app, KeyCode::Escape), AppState::Menu);
+
+        // Back on the menu the cursor rests on Settings; Up wraps past Play.
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Space);
+        let exits = app.resources.get::<Events<AppExit>>().unwrap();
+        assert_eq!(exits.get_reader().iter(&exits).count(), 1);
+        drop(exits);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Space);
+        let events = app.resources.get::<Events<EndRunEvent>>().unwrap();
+        let restarts = events.get_reader().iter(&events).count();
+        assert_eq!(restarts, 1);
+        drop(events);
+        assert_eq!(app.resources.get::<DailyChallenge>().unwrap().0, None);
+
+        // Daily sits just above Quit and starts today's challenge.
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Space);
+        assert_eq!(
+            app.resources.get::<DailyChallenge>().unwrap().0,
+            Some(daily::today())
+        );
+
+        // The name prompt makes the first profile and leads to the menu;
+        // further ones are made from the settings screen.
+        let type_name = |app: &mut App, keys: &[KeyCode]| {
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .press(KeyCode::LShift);
+            press(app, keys[0]);
+            app.resources
+                .get_mut::<Input<KeyCode>>()
+                .unwrap()
+                .release(KeyCode::LShift);
+            for key in &keys[1..] {
+                press(app, *key);
+            }
+        };
+        *app.resources.get_mut::<AppState>().unwrap() = AppState::Profile;
+        type_name(&mut app, &[KeyCode::A, KeyCode::D, KeyCode::A, KeyCode::T]);
+        assert_eq!(press(&mut app, KeyCode::Back), AppState::Profile);
+        assert_eq!(app.resources.get::<ProfileEntry>().unwrap().0, "Ada");
+        assert_eq!(app.resources.get::<Themes>().unwrap().chosen(), "Dark");
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Menu);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Settings);
+        for _ in 0..SettingsItem::NewPlayer.line() {
+            press(&mut app, KeyCode::Down);
+        }
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Profile);
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Profile);
+        type_name(&mut app, &[KeyCode::B, KeyCode::O]);
+        assert_eq!(press(&mut app, KeyCode::Return), AppState::Settings);
+        assert_eq!(
+            app.resources.get::<Profiles>().unwrap().current(),
+            Some("Bo")
+        );
+        press(&mut app, KeyCode::Return);
+        assert_eq!(app.resources.get::<Profiles>().unwrap().player(), "Ada");
+
+        // The volume lines below step their level down a quarter.
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        let volume = *app.resources.get::<Volume>().unwrap();
+        assert_eq!((volume.master, volume.music), (0.75, 0.25));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        assert_eq!(app.resources.get::<ShakeIntensity>().unwrap().0, 0.75);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Return);
+        assert!(app.resources.get::<Patterns>().unwrap().0);
+    }
+}
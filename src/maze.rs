@@ -0,0 +1,152 @@
+//! Random maze layouts for `GameMode::Maze`, made by recursive division.
+
+use crate::{Arena, Portals, Position};
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+
+/// Narrowest a room may get before it is no longer divided, in cells, so
+/// the snake always has space to turn around.
+pub const MAZE_MIN_ROOM: i32 = 5;
+/// Width of the gap left in every dividing wall.
+pub const MAZE_DOOR_WIDTH: i32 = 2;
+/// Open cells kept ahead of the snake's start so it does not spawn facing a
+/// wall.
+pub const MAZE_START_CLEARANCE: i32 = 3;
+/// Layouts tried before giving up on a maze and leaving the arena open.
+const MAZE_ATTEMPTS: usize = 16;
+
+/// Walls of a random maze filling `arena`. The portals, the snake's start,
+/// its neck and the cells just ahead of it stay open, and every open cell
+/// can be reached from every other.
+pub fn generate(
+    arena: &Arena,
+    portals: &Portals,
+    start: Position,
+    rng: &mut impl Rng,
+) -> Vec<Position> {
+    let mut keep_open: HashSet<Position> =
+        portals.0.iter().flat_map(|(a, b)| vec![*a, *b]).collect();
+    keep_open.extend((-1..=MAZE_START_CLEARANCE).map(|dy| Position {
+        x: start.x,
+        y: start.y + dy,
+    }));
+    for _ in 0..MAZE_ATTEMPTS {
+        let mut walls = Vec::new();
+        divide(
+            Position { x: 0, y: 0 },
+            Position {
+                x: arena.width as i32 - 1,
+                y: arena.height as i32 - 1,
+            },
+            &mut walls,
+            rng,
+        );
+        walls.retain(|cell| !keep_open.contains(cell));
+        if all_reachable(arena, &walls) {
+            return walls;
+        }
+    }
+    Vec::new()
+}
+
+/// Splits the room between the inclusive corners `min` and `max` with a wall
+/// that has a door in it, then splits both halves the same way.
+fn divide(min: Position, max: Position, walls: &mut Vec<Position>, rng: &mut impl Rng) {
+    let width = max.x - min.x + 1;
+    let height = max.y - min.y + 1;
+    let can_split_rows = height > 2 * MAZE_MIN_ROOM && width > MAZE_DOOR_WIDTH;
+    let can_split_columns = width > 2 * MAZE_MIN_ROOM && height > MAZE_DOOR_WIDTH;
+    let horizontal = match (can_split_rows, can_split_columns) {
+        (false, false) => return,
+        (true, false) => true,
+        (false, true) => false,
+        (true, true) => height > width || (height == width && rng.gen()),
+    };
+    if horizontal {
+        let y = rng.gen_range(min.y + MAZE_MIN_ROOM, max.y - MAZE_MIN_ROOM + 1);
+        let door = rng.gen_range(min.x, max.x - MAZE_DOOR_WIDTH + 2);
+        walls.extend(
+            (min.x..=max.x)
+                .filter(|x| !(door..door + MAZE_DOOR_WIDTH).contains(x))
+                .map(|x| Position { x, y }),
+        );
+        divide(min, Position { x: max.x, y: y - 1 }, walls, rng);
+        divide(Position { x: min.x, y: y + 1 }, max, walls, rng);
+    } else {
+        let x = rng.gen_range(min.x + MAZE_MIN_ROOM, max.x - MAZE_MIN_ROOM + 1);
+        let door = rng.gen_range(min.y, max.y - MAZE_DOOR_WIDTH + 2);
+        walls.extend(
+            (min.y..=max.y)
+                .filter(|y| !(door..door + MAZE_DOOR_WIDTH).contains(y))
+                .map(|y| Position { x, y }),
+        );
+        divide(min, Position { x: x - 1, y: max.y }, walls, rng);
+        divide(Position { x: x + 1, y: min.y }, max, walls, rng);
+    }
+}
+
+/// Whether every cell of `arena` that is not a wall can be reached from
+/// every other one by moving up, down, left and right.
+pub fn all_reachable(arena: &Arena, walls: &[Position]) -> bool {
+    let walls: HashSet<Position> = walls.iter().copied().collect();
+    let open: Vec<Position> = (0..arena.width as i32)
+        .flat_map(|x| (0..arena.height as i32).map(move |y| Position { x, y }))
+        .filter(|cell| !walls.contains(cell))
+        .collect();
+    let first = match open.first() {
+        Some(first) => *first,
+        None => return true,
+    };
+    let mut seen: HashSet<Position> = [first].iter().copied().collect();
+    let mut queue: VecDeque<Position> = seen.iter().copied().collect();
+    while let Some(cell) = queue.pop_front() {
+        for (dx, dy) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = Position {
+                x: cell.x + dx,
+                y: cell.y + dy,
+            };
+            if arena.contains(&next) && !walls.contains(&next) && seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    seen.len() == open.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SNAKE_START;
+    use rand::prelude::{SeedableRng, StdRng};
+
+    #[test]
+    fn mazes_leave_every_open_cell_reachable() {
+        let arena = Arena::default();
+        let portals = Portals::default();
+        for seed in 0..50 {
+            let walls = generate(
+                &arena,
+                &portals,
+                SNAKE_START,
+                &mut StdRng::seed_from_u64(seed),
+            );
+            assert!(!walls.is_empty(), "seed {} gave an open arena", seed);
+            assert!(all_reachable(&arena, &walls), "seed {}", seed);
+            assert!(walls.iter().all(|cell| arena.contains(cell)));
+            assert!(walls.iter().all(|cell| portals.exit(cell).is_none()));
+            assert!(!walls.contains(&SNAKE_START));
+        }
+    }
+
+    #[test]
+    fn reachability_notices_sealed_off_cells() {
+        let arena = Arena {
+            width: 4,
+            height: 3,
+        };
+        let column = |x| (0..3).map(move |y| Position { x, y });
+        assert!(all_reachable(&arena, &[]));
+        assert!(all_reachable(&arena, &column(3).collect::<Vec<_>>()));
+        assert!(!all_reachable(&arena, &column(1).collect::<Vec<_>>()));
+    }
+}
@@ -0,0 +1,348 @@
+//! Submitting finished runs to an online leaderboard, and showing its top
+//! scores on the game over screen. Off unless `--leaderboard-url` names a
+//! server.
+//!
+//! The server takes a `Submission` as JSON in a POST to the URL, and answers
+//! a GET to the same URL with its best `OnlineEntry`s as a JSON array, best
+//! first. Only plain `http://` is spoken, over a blocking socket on the IO
+//! task pool so the game never waits on the network.
+
+use crate::profile::Profiles;
+use crate::{
+    player_one_length, AppState, Difficulty, GameMode, Player, ReplayMode, ReplayRecorder,
+    SnakeSegments, Theme, UiFont,
+};
+use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Entries of the online table shown.
+pub const ONLINE_TOP_SIZE: usize = 10;
+
+/// How long a request may take to connect, and then to answer.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the online leaderboard lives, parsed from an `http://` URL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = String;
+
+    /// Parses `http://HOST[:PORT][/PATH]`.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("expected an http:// URL, not {:?}", s))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("bad port {:?} in {:?}", port, s))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("no host in {:?}", s));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl Endpoint {
+    /// Sends one HTTP/1.0 request, which keeps the answer unchunked, and
+    /// returns the body of a 2xx answer.
+    fn request(&self, method: &str, body: Option<&str>) -> io::Result<String> {
+        let address = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))?;
+        let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let body = body.unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no end of headers"))?;
+        let status = head.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            return Err(io::Error::other(format!(
+                "server answered {}",
+                head.lines().next().unwrap_or_default()
+            )));
+        }
+        Ok(body.to_string())
+    }
+
+    /// Posts `submission`, then fetches the table as it now stands.
+    pub fn submit(&self, submission: &Submission) -> io::Result<Vec<OnlineEntry>> {
+        self.request("POST", Some(&serde_json::to_string(submission)?))?;
+        let mut top: Vec<OnlineEntry> = serde_json::from_str(&self.request("GET", None)?)?;
+        top.truncate(ONLINE_TOP_SIZE);
+        Ok(top)
+    }
+}
+
+/// A finished run as posted to the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Submission {
+    /// The current profile's name; empty when none has been made.
+    pub name: String,
+    pub score: u32,
+    pub length: usize,
+    pub seed: u64,
+    pub difficulty: Difficulty,
+}
+
+/// A line of the online table as the server sends it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OnlineEntry {
+    #[serde(default)]
+    pub name: String,
+    pub score: u32,
+    #[serde(default)]
+    pub length: usize,
+}
+
+/// The server runs are submitted to, if any, and its table as last fetched.
+/// The table is filled in by the IO task once the server answers.
+#[derive(Default)]
+pub struct OnlineLeaderboard {
+    endpoint: Option<Endpoint>,
+    top: Arc<Mutex<Vec<OnlineEntry>>>,
+}
+
+impl OnlineLeaderboard {
+    pub fn new(endpoint: Option<Endpoint>) -> Self {
+        Self {
+            endpoint,
+            ..Default::default()
+        }
+    }
+}
+
+/// Line of the online table; 0 is the heading.
+pub struct OnlineRow(usize);
+
+/// Submits each recorded one-snake run as it ends, on the IO task pool.
+/// Failures are logged and otherwise ignored; the table shown keeps its
+/// last good contents.
+pub fn submit_score(
+    state: Res<AppState>,
+    mut state_before: Local<AppState>,
+    (replay_mode, recorder, mode): (Res<ReplayMode>, Res<ReplayRecorder>, Res<GameMode>),
+    (online, pool, profiles): (Res<OnlineLeaderboard>, Res<IoTaskPool>, Res<Profiles>),
+    heads: Query<(&Player, &SnakeSegments)>,
+) {
+    let before = std::mem::replace(&mut *state_before, *state);
+    if before == AppState::GameOver || *state != AppState::GameOver || mode.two_snakes() {
+        return;
+    }
+    let endpoint = match (&online.endpoint, &*replay_mode) {
+        (Some(endpoint), ReplayMode::Record(_)) => endpoint.clone(),
+        _ => return,
+    };
+    let submission = Submission {
+        name: profiles.player(),
+        score: recorder.0.score,
+        length: player_one_length(heads.iter()),
+        seed: recorder.0.seed,
+        difficulty: recorder.0.difficulty,
+    };
+    let top = online.top.clone();
+    pool.spawn(async move {
+        match endpoint.submit(&submission) {
+            Ok(entries) => *top.lock().unwrap() = entries,
+            Err(err) => eprintln!("could not submit score to {}: {}", endpoint.host, err),
+        }
+    })
+    .detach();
+}
+
+fn spawn_online_rows(mut commands: Commands, font: Res<UiFont>, theme: Res<Theme>) {
+    for row in 0..=ONLINE_TOP_SIZE {
+        commands
+            .spawn(TextComponents {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(190.0),
+                        top: Val::Px(456.0 + row as f32 * 24.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text {
+                    value: String::new(),
+                    font: font.0.clone(),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: theme.text(),
+                    },
+                },
+                ..Default::default()
+            })
+            .with(OnlineRow(row));
+    }
+}
+
+/// Shows the online table on the game over screen once the server has sent
+/// one.
+pub fn online_view(
+    state: Res<AppState>,
+    online: Res<OnlineLeaderboard>,
+    mut rows: Query<(&OnlineRow, &mut Text)>,
+) {
+    let top = online.top.lock().unwrap();
+    let shown = *state == AppState::GameOver && !top.is_empty();
+    for (row, mut text) in rows.iter_mut() {
+        let value = match row.0 {
+            _ if !shown => String::new(),
+            0 => "Online".to_string(),
+            rank => top
+                .get(rank - 1)
+                .map(|entry| {
+                    format!(
+                        "{:>2}. {:<12}  {:>6}  {:>3}",
+                        rank, entry.name, entry.score, entry.length
+                    )
+                })
+                .unwrap_or_default(),
+        };
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+/// Submits runs to the online leaderboard named by `--leaderboard-url`.
+pub struct OnlinePlugin;
+
+impl Plugin for OnlinePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let endpoint = app
+            .resources()
+            .get::<crate::options::Options>()
+            .and_then(|options| options.leaderboard_url.clone());
+        app.add_resource(OnlineLeaderboard::new(endpoint))
+            .add_startup_system_to_stage("game_setup", spawn_online_rows.system())
+            .add_system(submit_score.system())
+            .add_system(online_view.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn parses_http_urls() {
+        let endpoint: Endpoint = "http://scores.example:8080/snake/top".parse().unwrap();
+        assert_eq!(
+            endpoint,
+            Endpoint {
+                host: "scores.example".to_string(),
+                port: 8080,
+                path: "/snake/top".to_string(),
+            }
+        );
+        let bare: Endpoint = "http://localhost".parse().unwrap();
+        assert_eq!((bare.port, bare.path.as_str()), (80, "/"));
+        for bad in &[
+            "https://scores.example",
+            "scores.example",
+            "http://:80/",
+            "http://a:b/",
+        ] {
+            assert!(bad.parse::<Endpoint>().is_err(), "{}", bad);
+        }
+    }
+
+    /// Reads a request up to the end of the body its `Content-Length` gives.
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buffer = [0; 256];
+        loop {
+            let read = stream.read(&mut buffer).unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .and_then(|length| length.parse().ok())
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    return text;
+                }
+            }
+            assert!(read > 0, "request cut short: {:?}", text);
+        }
+    }
+
+    #[test]
+    fn submits_then_fetches_the_table() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for answer in &["{}", r#"[{"name":"ada","score":40,"length":9}]"#] {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                write!(stream, "HTTP/1.0 200 OK\r\n\r\n{}", answer).unwrap();
+            }
+            requests
+        });
+        let endpoint: Endpoint = format!("http://127.0.0.1:{}/top", port).parse().unwrap();
+        let submission = Submission {
+            name: "grace".to_string(),
+            score: 12,
+            length: 5,
+            seed: 99,
+            difficulty: Difficulty::Normal,
+        };
+
+        let top = endpoint.submit(&submission).unwrap();
+        assert_eq!(
+            top,
+            vec![OnlineEntry {
+                name: "ada".to_string(),
+                score: 40,
+                length: 9,
+            }]
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /top HTTP/1.0\r\n"));
+        assert!(requests[0].ends_with(&serde_json::to_string(&submission).unwrap()));
+        assert!(requests[1].starts_with("GET /top HTTP/1.0\r\n"));
+    }
+}
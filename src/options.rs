@@ -0,0 +1,201 @@
+//! Command-line options, parsed once at startup and turned into the
+//! resources they configure.
+
+use crate::online::Endpoint;
+use crate::{Arena, Difficulty};
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Launch a specific setup without touching the settings screen.
+#[derive(Clone, Debug, Default, PartialEq, Parser)]
+#[command(name = "bevy-snake", version, about)]
+pub struct Options {
+    /// Play back a recorded run in a loop instead of playing.
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+    /// Difficulty of the first run, overriding the saved one.
+    #[arg(long)]
+    pub difficulty: Option<Difficulty>,
+    /// Start in Time Attack mode.
+    #[arg(long, conflicts_with_all = ["shrinking_arena", "maze", "versus", "coop", "versus_bot"])]
+    pub time_attack: bool,
+    /// Start in Shrinking Arena mode.
+    #[arg(long, conflicts_with_all = ["maze", "versus", "coop", "versus_bot"])]
+    pub shrinking_arena: bool,
+    /// Start in Maze mode.
+    #[arg(long, conflicts_with_all = ["versus", "coop", "versus_bot"])]
+    pub maze: bool,
+    /// Start in Versus mode: two players on one keyboard.
+    #[arg(long, conflicts_with_all = ["coop", "versus_bot"])]
+    pub versus: bool,
+    /// Snakes in a versus round on the dedicated server, `snake-server`, from
+    /// 2 to 4. The windowed game always has two, one per half of the
+    /// keyboard.
+    #[arg(long, requires = "versus", value_parser = clap::value_parser!(u8).range(2..=4))]
+    pub players: Option<u8>,
+    /// Start in Co-op mode: two players on one keyboard sharing a score and
+    /// lives.
+    #[arg(long, conflicts_with = "versus_bot")]
+    pub coop: bool,
+    /// Start in Versus mode against a computer-driven snake.
+    #[arg(long)]
+    pub versus_bot: bool,
+    /// Leave through one edge of the arena and come back through the other.
+    #[arg(long)]
+    pub wrap_around: bool,
+    /// Add the built-in interior walls.
+    #[arg(long)]
+    pub obstacles: bool,
+    /// Lose the tail segment after this many moves without eating, and
+    /// starve with none left to lose. Defaults to 40 moves.
+    #[arg(long, value_name = "MOVES", num_args = 0..=1, default_missing_value = "40")]
+    pub starvation: Option<u32>,
+    /// Level to play, relative to `assets/`, e.g. `levels/cross.ron`.
+    #[arg(long, value_name = "FILE")]
+    pub level: Option<String>,
+    /// Arena size in cells, e.g. `30x20`.
+    #[arg(long, value_name = "WIDTHxHEIGHT")]
+    pub arena: Option<Arena>,
+    /// Move interval in milliseconds, replacing the difficulty's.
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub speed: Option<u32>,
+    /// Seed every run with this instead of a random one.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Open the window borderless and full screen.
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Foods eaten per speed-up step.
+    #[arg(long, value_name = "FOODS")]
+    pub speed_up_every: Option<u32>,
+    /// Play this many runs on the autopilot without a window, printing the
+    /// scores, then exit.
+    #[arg(long, value_name = "GAMES")]
+    pub headless: Option<u32>,
+    /// Submit finished runs to the online leaderboard at this `http://` URL
+    /// and show its top scores after each run.
+    #[arg(long, value_name = "URL")]
+    pub leaderboard_url: Option<Endpoint>,
+    /// Address the dedicated server, `snake-server`, takes players on;
+    /// 0.0.0.0:7777 by default.
+    #[arg(long, value_name = "ADDRESS")]
+    pub listen: Option<SocketAddr>,
+    /// Join the dedicated server at this address, e.g. `example.org:7777`,
+    /// instead of playing locally.
+    #[arg(long, value_name = "ADDRESS")]
+    pub connect: Option<String>,
+    /// Play versus against a peer at this address, e.g.
+    /// `192.168.1.20:7000`, which must be given this one's in turn.
+    #[arg(long, value_name = "ADDRESS", conflicts_with = "connect")]
+    pub peer: Option<SocketAddr>,
+    /// Port a `--peer` game listens on; 7000 by default.
+    #[arg(long, value_name = "PORT", requires = "peer")]
+    pub port: Option<u16>,
+    /// Let bots steer Player 1 over a WebSocket at ws://127.0.0.1:PORT,
+    /// which is sent the arena after every move.
+    #[arg(long, value_name = "PORT")]
+    pub bot_api: Option<u16>,
+    /// Seconds taken off the move interval per speed-up step.
+    #[arg(long, value_name = "SECONDS")]
+    pub speed_up_step: Option<f32>,
+    /// Shortest move interval the speed-up may reach, in seconds.
+    #[arg(long, value_name = "SECONDS")]
+    pub speed_up_floor: Option<f32>,
+}
+
+impl Options {
+    /// Parses `std::env::args()`, printing usage and exiting on bad input.
+    pub fn from_args() -> Self {
+        Self::parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::STARVATION_TICKS;
+
+    #[test]
+    fn parses_a_speedrun_setup() {
+        let options = Options::try_parse_from([
+            "bevy-snake",
+            "--arena",
+            "30x20",
+            "--speed",
+            "100",
+            "--seed",
+            "42",
+            "--difficulty",
+            "hard",
+            "--maze",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.arena,
+            Some(Arena {
+                width: 30,
+                height: 20
+            })
+        );
+        assert_eq!(options.speed, Some(100));
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.difficulty, Some(Difficulty::Hard));
+        assert!(options.maze && !options.fullscreen);
+    }
+
+    #[test]
+    fn starvation_takes_an_optional_limit() {
+        let limit = |args: &[&str]| Options::try_parse_from(args).unwrap().starvation;
+        assert_eq!(limit(&["bevy-snake"]), None);
+        assert_eq!(
+            limit(&["bevy-snake", "--starvation"]),
+            Some(STARVATION_TICKS)
+        );
+        assert_eq!(limit(&["bevy-snake", "--starvation", "10"]), Some(10));
+    }
+
+    #[test]
+    fn peer_games_take_an_address_and_port() {
+        let options =
+            Options::try_parse_from(["bevy-snake", "--peer", "10.0.0.2:7000", "--port", "7001"])
+                .unwrap();
+        assert_eq!(options.peer, Some(SocketAddr::from(([10, 0, 0, 2], 7000))));
+        assert_eq!(options.port, Some(7001));
+    }
+
+    #[test]
+    fn rejects_bad_setups() {
+        for args in &[
+            &["bevy-snake", "--arena", "30"][..],
+            &["bevy-snake", "--arena", "2x20"],
+            &["bevy-snake", "--speed", "0"],
+            &["bevy-snake", "--maze", "--time-attack"],
+            &["bevy-snake", "--versus", "--maze"],
+            &["bevy-snake", "--coop", "--versus"],
+            &["bevy-snake", "--versus-bot", "--maze"],
+            &["bevy-snake", "--difficulty", "brutal"],
+            &["bevy-snake", "--players", "4"],
+            &["bevy-snake", "--versus", "--players", "5"],
+            &["bevy-snake", "--port", "7001"],
+            &["bevy-snake", "--peer", "localhost"],
+            &[
+                "bevy-snake",
+                "--peer",
+                "10.0.0.2:7000",
+                "--connect",
+                "10.0.0.3:7777",
+            ],
+        ] {
+            assert!(Options::try_parse_from(*args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults() {
+        assert_eq!(
+            Options::try_parse_from(["bevy-snake"]).unwrap(),
+            Options::default()
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Shortest safe routes across the grid, by A* search, for the snakes the
+//! computer steers.
+
+use crate::{Direction, Position, SafeBounds};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// The grid a route is searched on: the cells inside `bounds` that are not
+/// `blocked`, with opposite edges joined when `wrap` is set.
+pub struct Grid<'a> {
+    pub bounds: &'a SafeBounds,
+    pub wrap: bool,
+    pub blocked: &'a HashSet<Position>,
+}
+
+impl Grid<'_> {
+    /// The cell one move from `from` in `direction`, unless it is off the
+    /// grid or blocked.
+    pub fn step(&self, from: Position, direction: Direction) -> Option<Position> {
+        let mut next = direction.step(from);
+        if self.wrap {
+            next = self.bounds.wrap(&next);
+        }
+        Some(next).filter(|next| self.bounds.contains(next) && !self.blocked.contains(next))
+    }
+
+    /// Fewest moves between `a` and `b` with nothing in the way, counting
+    /// the way round a joined edge when that is shorter.
+    fn distance(&self, a: Position, b: Position) -> i32 {
+        let axis = |from: i32, to: i32, size: i32| {
+            let straight = (from - to).abs();
+            if self.wrap {
+                straight.min(size - straight)
+            } else {
+                straight
+            }
+        };
+        let width = self.bounds.max.x - self.bounds.min.x + 1;
+        let height = self.bounds.max.y - self.bounds.min.y + 1;
+        axis(a.x, b.x, width) + axis(a.y, b.y, height)
+    }
+}
+
+/// The cells of a shortest route from `from` to `to` on `grid`, ending on
+/// `to` and leaving `from` itself out, or `None` when `to` cannot be
+/// reached. `from` may be blocked, as a head usually is.
+pub fn find_path(grid: &Grid, from: Position, to: Position) -> Option<Vec<Position>> {
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<Position, Position> = HashMap::new();
+    let mut cost: HashMap<Position, i32> = HashMap::new();
+    cost.insert(from, 0);
+    // Ties on the estimate go to the cell queued first, so the same search
+    // always takes the same route.
+    let mut queued = 0;
+    open.push((
+        Reverse(grid.distance(from, to)),
+        Reverse(queued),
+        (from.x, from.y),
+    ));
+    while let Some((_, _, (x, y))) = open.pop() {
+        let cell = Position { x, y };
+        if cell == to {
+            let mut path = Vec::new();
+            let mut at = to;
+            while at != from {
+                path.push(at);
+                at = came_from[&at];
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let next_cost = cost[&cell] + 1;
+        for direction in &Direction::ALL {
+            let next = match grid.step(cell, *direction) {
+                Some(next) => next,
+                None => continue,
+            };
+            if cost.get(&next).is_some_and(|known| *known <= next_cost) {
+                continue;
+            }
+            cost.insert(next, next_cost);
+            came_from.insert(next, cell);
+            queued += 1;
+            open.push((
+                Reverse(next_cost + grid.distance(next, to)),
+                Reverse(queued),
+                (next.x, next.y),
+            ));
+        }
+    }
+    None
+}
+
+/// The direction to head from `from` to reach `next`, a neighbouring cell
+/// on `grid`.
+pub fn direction_to(grid: &Grid, from: Position, next: Position) -> Option<Direction> {
+    Direction::ALL
+        .iter()
+        .copied()
+        .find(|direction| grid.step(from, *direction) == Some(next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arena;
+
+    fn cells(cells: &[(i32, i32)]) -> HashSet<Position> {
+        cells.iter().map(|&(x, y)| Position { x, y }).collect()
+    }
+
+    #[test]
+    fn routes_are_shortest_and_go_around_walls() {
+        let bounds = SafeBounds::full(&Arena {
+            width: 5,
+            height: 5,
+        });
+        let open = HashSet::new();
+        let grid = Grid {
+            bounds: &bounds,
+            wrap: false,
+            blocked: &open,
+        };
+        let from = Position { x: 0, y: 2 };
+        let to = Position { x: 4, y: 2 };
+        assert_eq!(find_path(&grid, from, to).unwrap().len(), 4);
+        assert_eq!(find_path(&grid, from, from), Some(Vec::new()));
+
+        // A wall down the middle with a gap at the top.
+        let wall = cells(&[(2, 0), (2, 1), (2, 2), (2, 3)]);
+        let grid = Grid {
+            blocked: &wall,
+            ..grid
+        };
+        let path = find_path(&grid, from, to).unwrap();
+        assert_eq!(path.len(), 8);
+        assert_eq!(path.last(), Some(&to));
+        assert!(path.iter().all(|cell| !wall.contains(cell)));
+        let mut previous = from;
+        for cell in &path {
+            assert!(direction_to(&grid, previous, *cell).is_some());
+            previous = *cell;
+        }
+
+        let sealed = cells(&[(2, 0), (2, 1), (2, 2), (2, 3), (2, 4)]);
+        let grid = Grid {
+            blocked: &sealed,
+            ..grid
+        };
+        assert_eq!(find_path(&grid, from, to), None);
+    }
+
+    #[test]
+    fn wrapped_routes_take_the_short_way_round() {
+        let bounds = SafeBounds::full(&Arena {
+            width: 8,
+            height: 3,
+        });
+        let open = HashSet::new();
+        let grid = Grid {
+            bounds: &bounds,
+            wrap: true,
+            blocked: &open,
+        };
+        let from = Position { x: 0, y: 1 };
+        let path = find_path(&grid, from, Position { x: 7, y: 1 }).unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(direction_to(&grid, from, path[0]), Some(Direction::Left));
+    }
+}
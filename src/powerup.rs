@@ -0,0 +1,344 @@
+//! Pickups that grant a timed effect. Adding one takes a `PowerUp` variant
+//! with its duration, spawn chance and colour, and a system that checks
+//! `ActiveEffects` to apply it.
+
+use crate::food::{
+    FOOD_MAGNET_CHANCE, GHOST_PICKUP_CHANCE, SCORE_MULTIPLIER_CHANCE, SHIELD_PICKUP_CHANCE,
+    SHRINK_POTION_CHANCE, SLOW_MOTION_PICKUP_CHANCE, SPEED_BOOST_PICKUP_CHANCE,
+};
+use crate::{
+    GameClock, Materials, Position, Size, FOOD_MAGNET_DURATION, GHOST_MODE_DURATION,
+    SCORE_MULTIPLIER_DURATION, SCORE_MULTIPLIER_MAX, SLOW_MOTION_DURATION, SPEED_BOOST_DURATION,
+};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// A pickup on the board, and the effect it grants when the head reaches it.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PowerUp {
+    /// The snake can pass through its own body.
+    Ghost,
+    /// The snake moves at a fraction of its speed.
+    SlowMotion,
+    /// The snake moves faster than its speed.
+    SpeedBoost,
+    /// The next wall or body the snake runs into stops it for a move
+    /// instead of killing it.
+    Shield,
+    /// Cuts the snake down to half its length on the spot.
+    ShrinkPotion,
+    /// Food scores double, or more when picked up again while it runs; see
+    /// `ActiveMultiplier`.
+    ScoreMultiplier,
+    /// Food near the head creeps towards it.
+    FoodMagnet,
+}
+
+/// Width of a full `EffectBar`, in pixels.
+pub const EFFECT_BAR_WIDTH: f32 = 60.0;
+
+impl PowerUp {
+    /// Every power-up, in the order their spawn chances are rolled.
+    pub const ALL: [Self; 7] = [
+        Self::Ghost,
+        Self::SlowMotion,
+        Self::SpeedBoost,
+        Self::Shield,
+        Self::ShrinkPotion,
+        Self::ScoreMultiplier,
+        Self::FoodMagnet,
+    ];
+
+    /// Seconds the effect lasts, `None` for one that acts at once. A shield
+    /// lasts until it is used up.
+    pub fn duration(self) -> Option<f32> {
+        match self {
+            Self::Ghost => Some(GHOST_MODE_DURATION),
+            Self::SlowMotion => Some(SLOW_MOTION_DURATION),
+            Self::SpeedBoost => Some(SPEED_BOOST_DURATION),
+            Self::Shield => Some(f32::INFINITY),
+            Self::ShrinkPotion => None,
+            Self::ScoreMultiplier => Some(SCORE_MULTIPLIER_DURATION),
+            Self::FoodMagnet => Some(FOOD_MAGNET_DURATION),
+        }
+    }
+
+    /// Chance of one spawning alongside each food, between 0.0 and 1.0.
+    pub fn spawn_chance(self) -> f32 {
+        match self {
+            Self::Ghost => GHOST_PICKUP_CHANCE,
+            Self::SlowMotion => SLOW_MOTION_PICKUP_CHANCE,
+            Self::SpeedBoost => SPEED_BOOST_PICKUP_CHANCE,
+            Self::Shield => SHIELD_PICKUP_CHANCE,
+            Self::ShrinkPotion => SHRINK_POTION_CHANCE,
+            Self::ScoreMultiplier => SCORE_MULTIPLIER_CHANCE,
+            Self::FoodMagnet => FOOD_MAGNET_CHANCE,
+        }
+    }
+
+    pub fn material(self, materials: &Materials) -> Handle<ColorMaterial> {
+        match self {
+            Self::Ghost => materials.ghost_pickup_material.clone(),
+            Self::SlowMotion => materials.slow_motion_pickup_material.clone(),
+            Self::SpeedBoost => materials.speed_boost_pickup_material.clone(),
+            Self::Shield => materials.shield_pickup_material.clone(),
+            Self::ShrinkPotion => materials.shrink_potion_material.clone(),
+            Self::ScoreMultiplier => materials.score_multiplier_material.clone(),
+            Self::FoodMagnet => materials.food_magnet_material.clone(),
+        }
+    }
+}
+
+/// HUD bar that shrinks with the time left on a power-up's effect, hidden
+/// while the effect is not running.
+pub struct EffectBar(pub PowerUp);
+
+/// HUD badge showing the score multiplier while it runs.
+pub struct MultiplierBadge;
+
+/// Sent for every power-up a head picks up.
+pub struct PowerUpEvent {
+    pub power_up: PowerUp,
+    /// Head of the snake that picked it up.
+    pub snake: Entity,
+}
+
+/// Time left on the effect of every power-up picked up this run.
+#[derive(Default, Clone)]
+pub struct ActiveEffects(HashMap<PowerUp, Timer>);
+
+impl ActiveEffects {
+    pub fn active(&self, power_up: PowerUp) -> bool {
+        self.0.get(&power_up).is_some_and(|timer| !timer.finished)
+    }
+
+    /// Seconds left on the effect of `power_up`, 0.0 when it is not running.
+    pub fn remaining(&self, power_up: PowerUp) -> f32 {
+        self.0
+            .get(&power_up)
+            .map_or(0.0, |timer| timer.duration - timer.elapsed)
+            .max(0.0)
+    }
+
+    /// Starts the effect of `power_up`, or starts it over if it is running.
+    /// Power-ups that act at once have no effect to start.
+    pub fn grant(&mut self, power_up: PowerUp) {
+        if let Some(duration) = power_up.duration() {
+            self.0
+                .insert(power_up, Timer::from_seconds(duration, false));
+        }
+    }
+
+    /// Ends the effect of `power_up`, returning whether it was running.
+    pub fn consume(&mut self, power_up: PowerUp) -> bool {
+        let active = self.active(power_up);
+        self.0.remove(&power_up);
+        active
+    }
+}
+
+/// Factor food scores by while the `ScoreMultiplier` effect runs. The first
+/// pickup doubles the score and each one picked up before it runs out adds
+/// one more, up to `SCORE_MULTIPLIER_MAX`.
+#[derive(Clone)]
+pub struct ActiveMultiplier(u32);
+
+impl Default for ActiveMultiplier {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+impl ActiveMultiplier {
+    pub fn factor(&self, effects: &ActiveEffects) -> u32 {
+        if effects.active(PowerUp::ScoreMultiplier) {
+            self.0
+        } else {
+            1
+        }
+    }
+
+    /// Raises the factor for another pickup, before its effect is granted.
+    pub fn stack(&mut self, effects: &ActiveEffects) {
+        self.0 = if effects.active(PowerUp::ScoreMultiplier) {
+            (self.0 + 1).min(SCORE_MULTIPLIER_MAX)
+        } else {
+            2
+        };
+    }
+}
+
+pub fn spawn_power_up(
+    commands: &mut Commands,
+    materials: &Materials,
+    power_up: PowerUp,
+    position: Position,
+) {
+    commands
+        .spawn(SpriteComponents {
+            material: power_up.material(materials),
+            ..Default::default()
+        })
+        .with(power_up)
+        .with(position)
+        .with(Size::square(0.6));
+}
+
+/// Runs every effect down on the game clock.
+pub fn tick_effects(clock: Res<GameClock>, mut effects: ResMut<ActiveEffects>) {
+    for timer in effects.0.values_mut() {
+        timer.tick(clock.delta_seconds);
+    }
+}
+
+/// Grants the effect of every power-up picked up.
+pub fn grant_power_ups(
+    mut reader: Local<EventReader<PowerUpEvent>>,
+    power_up_events: Res<Events<PowerUpEvent>>,
+    mut effects: ResMut<ActiveEffects>,
+    mut multiplier: ResMut<ActiveMultiplier>,
+) {
+    for PowerUpEvent { power_up, .. } in reader.iter(&power_up_events) {
+        if *power_up == PowerUp::ScoreMultiplier {
+            multiplier.stack(&effects);
+        }
+        effects.grant(*power_up);
+    }
+}
+
+pub fn multiplier_badge(
+    effects: Res<ActiveEffects>,
+    multiplier: Res<ActiveMultiplier>,
+    mut badges: Query<With<MultiplierBadge, (&mut Text, &mut Draw)>>,
+) {
+    let factor = multiplier.factor(&effects);
+    for (mut text, mut draw) in badges.iter_mut() {
+        if draw.is_visible != (factor > 1) {
+            draw.is_visible = factor > 1;
+        }
+        let value = format!("Score x{}", factor);
+        if text.value != value {
+            text.value = value;
+        }
+    }
+}
+
+pub fn effect_bars(
+    effects: Res<ActiveEffects>,
+    mut bars: Query<(&EffectBar, &mut Style, &mut Draw)>,
+) {
+    for (EffectBar(power_up), mut style, mut draw) in bars.iter_mut() {
+        let active = effects.active(*power_up);
+        if draw.is_visible != active {
+            draw.is_visible = active;
+        }
+        let duration = power_up.duration().unwrap_or(f32::INFINITY);
+        let left = effects.remaining(*power_up) / duration;
+        let width = Val::Px(EFFECT_BAR_WIDTH * left);
+        if style.size.width != width {
+            style.size.width = width;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn effects_last_their_duration_and_restart_when_granted_again() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<GameClock>()
+            .init_resource::<ActiveEffects>()
+            .init_resource::<ActiveMultiplier>()
+            .add_event::<PowerUpEvent>()
+            .add_system(tick_effects.system())
+            .add_system(grant_power_ups.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let snake = app.world.spawn(());
+        app.executor.initialize(&mut app.resources);
+        let advance = |app: &mut App, seconds, picked_up: Option<PowerUp>| {
+            if let Some(power_up) = picked_up {
+                app.resources
+                    .get_mut::<Events<PowerUpEvent>>()
+                    .unwrap()
+                    .send(PowerUpEvent { power_up, snake });
+            }
+            app.resources.get_mut::<GameClock>().unwrap().delta_seconds = seconds;
+            app.update();
+            let effects = app.resources.get::<ActiveEffects>().unwrap();
+            (
+                effects.active(PowerUp::Ghost),
+                effects.active(PowerUp::SlowMotion),
+            )
+        };
+
+        assert_eq!(advance(&mut app, 0.0, None), (false, false));
+        assert_eq!(advance(&mut app, 0.0, Some(PowerUp::Ghost)), (true, false));
+        let almost = GHOST_MODE_DURATION - 1.0;
+        assert_eq!(advance(&mut app, almost, None), (true, false));
+        assert_eq!(
+            advance(&mut app, 0.0, Some(PowerUp::SlowMotion)),
+            (true, true)
+        );
+        assert_eq!(advance(&mut app, 1.0, None), (false, true));
+        assert_eq!(advance(&mut app, 0.0, Some(PowerUp::Ghost)), (true, true));
+        assert_eq!(advance(&mut app, almost, None), (true, false));
+        assert_eq!(advance(&mut app, 1.0, None), (false, false));
+    }
+
+    #[test]
+    fn score_multipliers_stack_up_to_the_cap_while_running() {
+        let mut effects = ActiveEffects::default();
+        let mut multiplier = ActiveMultiplier::default();
+        assert_eq!(multiplier.factor(&effects), 1);
+        let mut factors = Vec::new();
+        for _ in 0..3 {
+            multiplier.stack(&effects);
+            effects.grant(PowerUp::ScoreMultiplier);
+            factors.push(multiplier.factor(&effects));
+        }
+        assert_eq!(factors, [2, 3, SCORE_MULTIPLIER_MAX]);
+
+        effects = ActiveEffects::default();
+        assert_eq!(multiplier.factor(&effects), 1);
+        multiplier.stack(&effects);
+        effects.grant(PowerUp::ScoreMultiplier);
+        assert_eq!(multiplier.factor(&effects), 2);
+    }
+
+    #[test]
+    fn effect_bars_shrink_with_the_time_left() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<ActiveEffects>()
+            .add_system(effect_bars.system());
+        let mut app = std::mem::take(&mut builder.app);
+        app.executor.initialize(&mut app.resources);
+        let bar = app.world.spawn((
+            EffectBar(PowerUp::SpeedBoost),
+            Style::default(),
+            Draw::default(),
+        ));
+        let shown = |app: &App| {
+            (
+                app.world.get::<Draw>(bar).unwrap().is_visible,
+                app.world.get::<Style>(bar).unwrap().size.width,
+            )
+        };
+
+        app.update();
+        assert_eq!(shown(&app), (false, Val::Px(0.0)));
+
+        let mut timer = Timer::from_seconds(SPEED_BOOST_DURATION, false);
+        timer.tick(SPEED_BOOST_DURATION / 4.0);
+        app.resources
+            .get_mut::<ActiveEffects>()
+            .unwrap()
+            .0
+            .insert(PowerUp::SpeedBoost, timer);
+        app.update();
+        assert_eq!(shown(&app), (true, Val::Px(EFFECT_BAR_WIDTH * 0.75)));
+    }
+}
@@ -0,0 +1,249 @@
+//! Local player profiles: the names this machine's players go by, and which
+//! of them is playing. The current name is attached to leaderboard entries,
+//! saved replays and online submissions.
+
+use crate::options::Options;
+use crate::AppState;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Longest name the prompt accepts, in characters.
+pub const MAX_NAME_LENGTH: usize = 12;
+
+/// Every local player's name, and the index of the one playing.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profiles {
+    pub names: Vec<String>,
+    pub current: usize,
+}
+
+impl Profiles {
+    /// `profiles.toml` in the platform config directory, next to the
+    /// settings.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("bevy-snake").join("profiles.toml"))
+    }
+
+    /// Reads the profiles at `path`, falling back to none when the file is
+    /// missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).unwrap_or_else(|err| {
+                eprintln!("could not read profiles {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                eprintln!("could not read profiles {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text =
+            toml::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+
+    /// The name of the player playing, if any profile has been made.
+    pub fn current(&self) -> Option<&str> {
+        self.names.get(self.current).map(String::as_str)
+    }
+
+    /// The current name, or an empty one before any profile has been made.
+    pub fn player(&self) -> String {
+        self.current().unwrap_or_default().to_string()
+    }
+
+    /// Switches to the profile called `name`, making it first if needed.
+    pub fn add(&mut self, name: &str) {
+        self.current = match self.names.iter().position(|known| known == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        };
+    }
+
+    /// Switches to the next profile, wrapping round to the first.
+    pub fn next(&mut self) {
+        if !self.names.is_empty() {
+            self.current = (self.current + 1) % self.names.len();
+        }
+    }
+}
+
+/// The keys that type letters, in alphabetical order.
+const LETTER_KEYS: [KeyCode; 26] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+];
+
+/// The keys that type digits, 0 first.
+const DIGIT_KEYS: [KeyCode; 10] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// The character `key` types at the name prompt, if any. Bevy 0.3 reports
+/// keys rather than text, so this assumes a US layout: Shift capitalizes
+/// letters and turns Minus into an underscore.
+pub fn key_char(key: KeyCode, shift: bool) -> Option<char> {
+    if let Some(letter) = LETTER_KEYS.iter().position(|letter| *letter == key) {
+        let c = (b'a' + letter as u8) as char;
+        return Some(if shift { c.to_ascii_uppercase() } else { c });
+    }
+    if let Some(digit) = DIGIT_KEYS.iter().position(|digit| *digit == key) {
+        return Some((b'0' + digit as u8) as char);
+    }
+    match key {
+        KeyCode::Space => Some(' '),
+        KeyCode::Minus if shift => Some('_'),
+        KeyCode::Minus => Some('-'),
+        _ => None,
+    }
+}
+
+/// The name being typed at the profile prompt.
+#[derive(Default)]
+pub struct ProfileEntry(pub String);
+
+impl ProfileEntry {
+    /// Adds the characters of `typed` a name may hold: letters, digits,
+    /// spaces, dashes and underscores, up to `MAX_NAME_LENGTH` in all.
+    pub fn type_chars(&mut self, typed: impl Iterator<Item = char>) {
+        for c in typed {
+            let allowed = c.is_alphanumeric() || matches!(c, ' ' | '-' | '_');
+            if allowed && self.0.chars().count() < MAX_NAME_LENGTH {
+                self.0.push(c);
+            }
+        }
+    }
+
+    /// The name typed so far, without leading or trailing spaces, or `None`
+    /// while it is blank.
+    pub fn name(&self) -> Option<&str> {
+        Some(self.0.trim()).filter(|name| !name.is_empty())
+    }
+}
+
+/// Writes the profiles file whenever a profile is made or switched to.
+pub fn persist_profiles(profiles: Res<Profiles>, mut saved: Local<Option<Profiles>>) {
+    if saved.is_none() {
+        *saved = Some(profiles.clone());
+    } else if saved.as_ref() != Some(&*profiles) {
+        if let Some(path) = Profiles::path() {
+            if let Err(err) = profiles.save(&path) {
+                eprintln!("could not save profiles {}: {}", path.display(), err);
+            }
+        }
+        *saved = Some(profiles.clone());
+    }
+}
+
+/// Loads the profiles, and opens on the name prompt instead of the menu when
+/// there are none yet. Add it after the plugin that sets up `AppState`.
+pub struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let profiles = Profiles::path()
+            .map(|path| Profiles::load(&path))
+            .unwrap_or_default();
+        let replaying = app
+            .resources()
+            .get::<Options>()
+            .is_some_and(|options| options.replay.is_some());
+        if profiles.names.is_empty() && !replaying {
+            app.add_resource(AppState::Profile);
+        }
+        app.add_resource(profiles)
+            .init_resource::<ProfileEntry>()
+            .add_system(persist_profiles.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_are_made_once_and_cycled() {
+        let mut profiles = Profiles::default();
+        assert_eq!(profiles.current(), None);
+        profiles.next();
+        profiles.add("ada");
+        profiles.add("grace");
+        assert_eq!(profiles.current(), Some("grace"));
+        profiles.add("ada");
+        assert_eq!((profiles.names.len(), profiles.current()), (2, Some("ada")));
+        profiles.next();
+        profiles.next();
+        assert_eq!(profiles.player(), "ada");
+
+        let text = toml::to_string(&profiles).unwrap();
+        assert_eq!(toml::from_str::<Profiles>(&text).unwrap(), profiles);
+    }
+
+    #[test]
+    fn names_keep_to_the_allowed_characters() {
+        let mut entry = ProfileEntry::default();
+        assert_eq!(entry.name(), None);
+        entry.type_chars("  ".chars());
+        assert_eq!(entry.name(), None);
+        entry.0.clear();
+        entry.type_chars("Ada\u{8}\r-L_ove/lace!!1234".chars());
+        assert_eq!(entry.0.chars().count(), MAX_NAME_LENGTH);
+        assert_eq!(entry.name(), Some("Ada-L_ovelac"));
+    }
+
+    #[test]
+    fn keys_type_us_layout_characters() {
+        assert_eq!(key_char(KeyCode::A, false), Some('a'));
+        assert_eq!(key_char(KeyCode::Z, true), Some('Z'));
+        assert_eq!(key_char(KeyCode::Key7, true), Some('7'));
+        assert_eq!(key_char(KeyCode::Minus, true), Some('_'));
+        assert_eq!(key_char(KeyCode::Return, false), None);
+    }
+}
@@ -0,0 +1,1127 @@
+//! Sprite layout, textures, body shading and camera effects.
+
+use crate::food::{FoodLifetime, FoodTimerBar, FoodType, FOOD_SIZE, FOOD_TIMER_BAR_HEIGHT};
+use crate::powerup::{ActiveEffects, PowerUp};
+use crate::{
+    expired_timer, Arena, Direction, GameCamera, GameOverEvent, GrowthEvent, Materials, Player,
+    Position, Size, SnakeHead, SnakeMoveTimer, SnakeSegment, SnakeSegments, SEGMENT_SIZE,
+};
+use bevy::prelude::*;
+use bevy::render::texture::TextureFormat;
+use bevy::window::WindowResized;
+use rand::prelude::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const TAIL_SEGMENT_SIZE: f32 = 0.4;
+pub const SHAKE_DURATION: f32 = 0.4;
+pub const SHAKE_INTENSITY: f32 = 8.0;
+/// Pixels the camera jitters by when the snake eats poison; a crash shakes
+/// harder.
+pub const POISON_SHAKE_INTENSITY: f32 = 3.0;
+
+/// How hard the camera shakes, as a share of the full shake; 0 turns shaking
+/// off for players who find it uncomfortable.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ShakeIntensity(pub f32);
+
+impl Default for ShakeIntensity {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Jitters the game camera by up to `intensity` pixels, decaying over the
+/// timer. `origin` is where the camera sat before the shake began.
+pub struct ScreenShake {
+    timer: Timer,
+    intensity: f32,
+    origin: Option<Vec3>,
+}
+
+impl Default for ScreenShake {
+    fn default() -> Self {
+        Self {
+            timer: expired_timer(SHAKE_DURATION),
+            intensity: 0.0,
+            origin: None,
+        }
+    }
+}
+
+/// Shakes the camera after a crash, and more gently after eating poison,
+/// scaled by the `ShakeIntensity` setting. A new shake during one restarts it
+/// from the original camera position, so offsets never accumulate, unless it
+/// is gentler than the one under way.
+pub fn screen_shake(
+    time: Res<Time>,
+    (mut reader, game_over_events): (
+        Local<EventReader<GameOverEvent>>,
+        Res<Events<GameOverEvent>>,
+    ),
+    (mut growth_reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    setting: Res<ShakeIntensity>,
+    mut shake: ResMut<ScreenShake>,
+    mut cameras: Query<With<GameCamera, &mut Transform>>,
+) {
+    let crashed = reader.iter(&game_over_events).next().is_some();
+    let poisoned = growth_reader
+        .iter(&growth_events)
+        .any(|eaten| eaten.food == FoodType::Poison);
+    let kick = match (crashed, poisoned) {
+        (true, _) => SHAKE_INTENSITY,
+        (false, true) => POISON_SHAKE_INTENSITY,
+        (false, false) => 0.0,
+    } * setting.0;
+    if kick > 0.0 && (shake.timer.finished || kick >= shake.intensity) {
+        shake.timer.reset();
+        shake.intensity = kick;
+    }
+    if shake.timer.finished && shake.origin.is_none() {
+        return;
+    }
+    shake.timer.tick(time.delta_seconds);
+    let mut rng = thread_rng();
+    for mut transform in cameras.iter_mut() {
+        let origin = *shake.origin.get_or_insert(transform.translation);
+        transform.translation = if shake.timer.finished {
+            origin
+        } else {
+            let strength = shake.intensity * (1.0 - shake.timer.elapsed / shake.timer.duration);
+            origin
+                + Vec3::new(
+                    rng.gen_range(-strength, strength),
+                    rng.gen_range(-strength, strength),
+                    0.0,
+                )
+        };
+    }
+    if shake.timer.finished {
+        shake.origin = None;
+    }
+}
+
+/// How a body segment is drawn, from the segments on either side of it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SegmentShape {
+    /// Joins the segments ahead and behind, across from each other.
+    Straight,
+    /// Joins the segments ahead and behind at a right angle.
+    Corner,
+    /// The end of the body, joined to the segment ahead only.
+    Tail,
+}
+
+/// The way from `from` to `to`, if they are neighbouring cells.
+fn toward(from: &Position, to: &Position) -> Option<Direction> {
+    Direction::ALL
+        .iter()
+        .copied()
+        .find(|direction| direction.step(*from) == *to)
+}
+
+/// `direction` turned as far as `facing(by)` turns up.
+fn turned(direction: Direction, by: Direction) -> Direction {
+    let quarter_turns = match by {
+        Direction::Up => 0,
+        Direction::Left => 1,
+        Direction::Down => 2,
+        Direction::Right => 3,
+    };
+    (0..quarter_turns).fold(direction, |direction, _| match direction {
+        Direction::Up => Direction::Left,
+        Direction::Left => Direction::Down,
+        Direction::Down => Direction::Right,
+        Direction::Right => Direction::Up,
+    })
+}
+
+/// The shape of the segment on `cell`, with the segment ahead of it on
+/// `ahead` and the one behind, unless it is the tail, on `behind`, and which
+/// way to turn the atlas tile for it with `facing`. The tiles join straight
+/// pieces up and down, corners down and right, and tails up. Segments whose
+/// neighbour is not next to them, through a portal or across a wrapped edge,
+/// are drawn as if it were straight on.
+pub fn segment_shape(
+    cell: &Position,
+    ahead: &Position,
+    behind: Option<&Position>,
+) -> (SegmentShape, Direction) {
+    let front = toward(cell, ahead);
+    let back = behind.and_then(|behind| toward(cell, behind));
+    let axis = |direction: Direction| match direction {
+        Direction::Up | Direction::Down => Direction::Up,
+        Direction::Left | Direction::Right => Direction::Left,
+    };
+    match (behind, front, back) {
+        (None, front, _) => (SegmentShape::Tail, front.unwrap_or(Direction::Up)),
+        (Some(_), Some(front), Some(back)) if front != back && front != back.opposite() => {
+            let turn = Direction::ALL
+                .iter()
+                .copied()
+                .find(|by| {
+                    let (down, right) =
+                        (turned(Direction::Down, *by), turned(Direction::Right, *by));
+                    (down == front && right == back) || (down == back && right == front)
+                })
+                .unwrap_or(Direction::Up);
+            (SegmentShape::Corner, turn)
+        }
+        (Some(_), Some(joined), _) | (Some(_), None, Some(joined)) => {
+            (SegmentShape::Straight, axis(joined))
+        }
+        (Some(_), None, None) => (SegmentShape::Straight, Direction::Up),
+    }
+}
+
+/// Shades each segment by its place in its snake's `SnakeSegments`, fading
+/// towards the tail, and shapes it as a straight piece, a corner or the tail,
+/// turned to join its neighbours. Only segments whose shade band, shape or
+/// turn changed are touched, which on a move is the new neck, the new tail and
+/// a few band edges.
+#[allow(clippy::type_complexity)]
+pub fn segment_gradient(
+    materials: Res<Materials>,
+    effects: Res<ActiveEffects>,
+    bodies: Query<(&Position, &SnakeSegments)>,
+    mut segment_sprites: Query<With<SnakeSegment, (&mut Handle<ColorMaterial>, &mut Transform)>>,
+) {
+    if effects.active(PowerUp::Ghost) {
+        return;
+    }
+    for (head, segments) in bodies.iter() {
+        let len = segments.len();
+        let cells: Vec<&Position> = std::iter::once(head)
+            .chain(segments.positions.iter())
+            .collect();
+        for (index, segment) in segments.iter().enumerate() {
+            if let Ok((mut handle, mut transform)) = segment_sprites.get_mut(*segment) {
+                let (shape, turn) = segment_shape(
+                    cells[index + 1],
+                    cells[index],
+                    cells.get(index + 2).copied(),
+                );
+                let material = match shape {
+                    SegmentShape::Straight => materials.segment_material(index, len),
+                    SegmentShape::Corner => materials.corner_material(index, len),
+                    SegmentShape::Tail => materials.tail_material.clone(),
+                };
+                if *handle != material {
+                    *handle = material;
+                }
+                let rotation = facing(turn);
+                if transform.rotation != rotation {
+                    transform.rotation = rotation;
+                }
+            }
+        }
+    }
+}
+
+/// Where the textures of the snake and food are cut from: one row of
+/// `SpriteTile`s, each drawn in white and grays so its material's color tints
+/// it and themes still apply.
+pub const SPRITE_ATLAS: &str = "sprites/atlas.png";
+
+/// Tiles of the sprite atlas, left to right.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpriteTile {
+    Head,
+    Body,
+    Corner,
+    Tail,
+    Food,
+}
+
+impl SpriteTile {
+    pub const ALL: [SpriteTile; 5] = [Self::Head, Self::Body, Self::Corner, Self::Tail, Self::Food];
+
+    /// The pattern drawn over the tile while `Patterns` are on: dots on the
+    /// head and stripes on food.
+    fn pattern(self) -> Option<Pattern> {
+        match self {
+            Self::Head => Some(Pattern::Dots),
+            Self::Food => Some(Pattern::Stripes),
+            Self::Body | Self::Corner | Self::Tail => None,
+        }
+    }
+}
+
+/// The sprite atlas as loaded, until its tiles have been handed out to the
+/// materials. The sprites are plain colored squares until then, and stay so
+/// if the atlas is missing.
+pub struct SpriteAtlas {
+    image: Handle<Texture>,
+    applied: bool,
+    /// Each tile, once cut out.
+    tiles: Vec<Handle<Texture>>,
+    /// Each tile with its pattern drawn over it, once cut out.
+    patterned: Vec<Handle<Texture>>,
+}
+
+impl SpriteAtlas {
+    pub fn load(asset_server: &AssetServer) -> Self {
+        Self {
+            image: asset_server.load(SPRITE_ATLAS),
+            applied: false,
+            tiles: Vec::new(),
+            patterned: Vec::new(),
+        }
+    }
+}
+
+/// Whether the head and food carry patterns as well as colors, so players
+/// who cannot tell the colors apart can tell them by their looks.
+#[derive(Default, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Patterns(pub bool);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pattern {
+    Stripes,
+    Dots,
+}
+
+/// Share of its brightness a pixel keeps under a pattern.
+const PATTERN_SHADE: f32 = 0.4;
+
+/// A copy of `tile` darkened in `pattern`: diagonal stripes three pixels
+/// wide, or two-pixel dots four pixels apart.
+pub fn draw_pattern(tile: &Texture, pattern: Pattern) -> Texture {
+    let mut patterned = Texture::new(tile.size, tile.data.clone(), tile.format);
+    let width = tile.size.x() as usize;
+    let pixel = tile.format.pixel_size();
+    for (i, bytes) in patterned.data.chunks_mut(pixel).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let marked = match pattern {
+            Pattern::Stripes => (x + y) / 3 % 2 == 0,
+            Pattern::Dots => x % 4 >= 2 && y % 4 >= 2,
+        };
+        if marked {
+            // Alpha, the fourth channel, is left alone.
+            for channel in bytes.iter_mut().take(3) {
+                *channel = (*channel as f32 * PATTERN_SHADE) as u8;
+            }
+        }
+    }
+    patterned
+}
+
+/// Column `tile` of `atlas` cut into `tiles` columns of equal width, or `None`
+/// if it does not divide evenly.
+pub fn cut_tile(atlas: &Texture, tile: usize, tiles: usize) -> Option<Texture> {
+    let (width, height) = (atlas.size.x() as usize, atlas.size.y() as usize);
+    if tiles == 0 || width % tiles != 0 || width * height == 0 {
+        return None;
+    }
+    let pixel = atlas.data.len() / (width * height);
+    let row = width * pixel;
+    let tile_row = row / tiles;
+    let data = atlas
+        .data
+        .chunks(row)
+        .flat_map(|line| &line[tile * tile_row..(tile + 1) * tile_row])
+        .copied()
+        .collect();
+    Some(Texture::new(
+        Vec2::new((width / tiles) as f32, height as f32),
+        data,
+        atlas.format,
+    ))
+}
+
+/// Once the sprite atlas has loaded, cuts it into its tiles and gives the
+/// heads, body pieces, tails and food their textures.
+pub fn apply_atlas(
+    mut atlas: ResMut<SpriteAtlas>,
+    materials: Res<Materials>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if atlas.applied {
+        return;
+    }
+    let tiles: Vec<Texture> = match textures.get(&atlas.image) {
+        Some(image) => SpriteTile::ALL
+            .iter()
+            .filter_map(|tile| cut_tile(image, *tile as usize, SpriteTile::ALL.len()))
+            .collect(),
+        None => return,
+    };
+    atlas.applied = true;
+    if tiles.len() != SpriteTile::ALL.len() {
+        eprintln!("could not cut {} into tiles", SPRITE_ATLAS);
+        return;
+    }
+    let patterned: Vec<Texture> = SpriteTile::ALL
+        .iter()
+        .zip(&tiles)
+        .map(|(tile, texture)| match tile.pattern() {
+            Some(pattern) => draw_pattern(texture, pattern),
+            None => Texture::new(texture.size, texture.data.clone(), texture.format),
+        })
+        .collect();
+    atlas.patterned = patterned
+        .into_iter()
+        .map(|tile| textures.add(tile))
+        .collect();
+    let tiles: Vec<Handle<Texture>> = tiles.into_iter().map(|tile| textures.add(tile)).collect();
+    atlas.tiles = tiles.clone();
+    let textured = [
+        (SpriteTile::Head, &materials.head_material),
+        (SpriteTile::Head, &materials.rival_head_material),
+        (SpriteTile::Head, &materials.shielded_head_material),
+        (SpriteTile::Tail, &materials.tail_material),
+        (SpriteTile::Food, &materials.food_material),
+        (SpriteTile::Food, &materials.double_food_material),
+        (SpriteTile::Food, &materials.golden_food_material),
+        (SpriteTile::Food, &materials.poison_food_material),
+    ];
+    let body = materials
+        .segment_gradient
+        .iter()
+        .map(|handle| (SpriteTile::Body, handle));
+    let corners = materials
+        .corner_gradient
+        .iter()
+        .map(|handle| (SpriteTile::Corner, handle));
+    for (tile, handle) in textured.iter().copied().chain(body).chain(corners) {
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.texture = Some(tiles[tile as usize].clone());
+        }
+    }
+}
+
+/// Swaps the head and food textures for their patterned ones when
+/// `Patterns` are turned on, and back when they are turned off.
+pub fn apply_patterns(
+    patterns: Res<Patterns>,
+    atlas: Res<SpriteAtlas>,
+    materials: Res<Materials>,
+    mut applied: Local<Option<Patterns>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if atlas.tiles.is_empty() || *applied == Some(*patterns) {
+        return;
+    }
+    *applied = Some(*patterns);
+    let tiles = if patterns.0 {
+        &atlas.patterned
+    } else {
+        &atlas.tiles
+    };
+    for (tile, handle) in [
+        (SpriteTile::Head, &materials.head_material),
+        (SpriteTile::Head, &materials.rival_head_material),
+        (SpriteTile::Head, &materials.shielded_head_material),
+        (SpriteTile::Food, &materials.food_material),
+        (SpriteTile::Food, &materials.double_food_material),
+        (SpriteTile::Food, &materials.golden_food_material),
+        (SpriteTile::Food, &materials.poison_food_material),
+    ] {
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.texture = Some(tiles[tile as usize].clone());
+        }
+    }
+}
+
+/// Brightness of the darker squares of the checkerboard, out of 255; the
+/// arena material's color tints both.
+const CHECKER_DARK: u8 = 170;
+
+/// Texture with one pixel per cell of a `width` by `height` arena, light and
+/// dark in turn like a checkerboard, the bottom left cell light. Rows run top
+/// to bottom, as the arena's do not.
+pub fn checkerboard(width: u32, height: u32) -> Texture {
+    let data = (0..height)
+        .rev()
+        .flat_map(|y| (0..width).map(move |x| (x + y) % 2 == 0))
+        .flat_map(|light| {
+            let shade = if light { 255 } else { CHECKER_DARK };
+            vec![shade, shade, shade, 255]
+        })
+        .collect();
+    Texture::new(
+        Vec2::new(width as f32, height as f32),
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Lays a checkerboard over the arena background, one square per cell, so
+/// distances can be judged at a glance. Drawn again when a level changes
+/// the arena's size.
+pub fn arena_checkerboard(
+    arena: Res<Arena>,
+    materials: Res<Materials>,
+    mut drawn: Local<Option<(u32, u32)>>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let size = (arena.width, arena.height);
+    if *drawn == Some(size) {
+        return;
+    }
+    if let Some(material) = color_materials.get_mut(&materials.arena_material) {
+        material.texture = Some(textures.add(checkerboard(size.0, size.1)));
+        *drawn = Some(size);
+    }
+}
+
+/// Turn that makes a sprite drawn facing up face `direction`.
+fn facing(direction: Direction) -> Quat {
+    let quarter = std::f32::consts::FRAC_PI_2;
+    Quat::from_rotation_z(match direction {
+        Direction::Up => 0.0,
+        Direction::Left => quarter,
+        Direction::Down => 2.0 * quarter,
+        Direction::Right => -quarter,
+    })
+}
+
+/// Turns each head to face the way it is going whenever a move commits a
+/// new direction, or a head is spawned; the atlas draws heads facing up.
+pub fn head_rotation(mut heads: Query<(Changed<SnakeHead>, &mut Transform)>) {
+    for (head, mut transform) in heads.iter_mut() {
+        let rotation = facing(head.direction);
+        if transform.rotation != rotation {
+            transform.rotation = rotation;
+        }
+    }
+}
+
+/// Thins the body out from `SEGMENT_SIZE` behind the head to
+/// `TAIL_SEGMENT_SIZE` at the tail. Only runs when a body changed (a move,
+/// growth or respawn), and before `size_scaling` so it sees the new sizes.
+pub fn segment_taper(
+    mut tapered: Local<Vec<(usize, Option<Entity>)>>,
+    bodies: Query<&SnakeSegments>,
+    mut sizes: Query<With<SnakeSegment, &mut Size>>,
+) {
+    let shapes: Vec<(usize, Option<Entity>)> = bodies
+        .iter()
+        .map(|segments| (segments.len(), segments.iter().next().copied()))
+        .collect();
+    if *tapered == shapes {
+        return;
+    }
+    for segments in bodies.iter() {
+        let last = segments.len().saturating_sub(1).max(1) as f32;
+        for (index, segment) in segments.iter().enumerate() {
+            if let Ok(mut size) = sizes.get_mut(*segment) {
+                let t = index as f32 / last;
+                *size = Size::square(SEGMENT_SIZE + (TAIL_SEGMENT_SIZE - SEGMENT_SIZE) * t);
+            }
+        }
+    }
+    *tapered = shapes;
+}
+
+/// Pixels across a grid cell in the primary window, worked out again only
+/// when the window is resized or the arena changes size.
+#[derive(Default)]
+pub struct CellSize {
+    pub pixels: f32,
+    /// Whether `pixels` was worked out again this frame, so every sprite has
+    /// to be laid out again.
+    pub changed: bool,
+}
+
+/// Keeps the `CellSize` up to date; runs before anything that lays sprites
+/// out.
+pub fn cell_size(
+    (windows, arena): (Res<Windows>, Res<Arena>),
+    (mut resize_reader, resize_events): (
+        Local<EventReader<WindowResized>>,
+        Res<Events<WindowResized>>,
+    ),
+    mut measured_for: Local<Option<Arena>>,
+    mut cell: ResMut<CellSize>,
+) {
+    let resized = resize_reader.iter(&resize_events).next().is_some();
+    cell.changed = false;
+    if !resized && *measured_for == Some(*arena) {
+        return;
+    }
+    if let Some(window) = windows.get_primary() {
+        cell.pixels = arena.cell_size(window);
+        cell.changed = true;
+        *measured_for = Some(*arena);
+    }
+}
+
+/// Rescales sprites whose `Size` changed, or all of them when the cell size
+/// did.
+#[allow(clippy::type_complexity)]
+pub fn size_scaling(
+    cell: Res<CellSize>,
+    mut q: QuerySet<(
+        Query<(&Size, &mut Sprite)>,
+        Query<(Changed<Size>, &mut Sprite)>,
+    )>,
+) {
+    let pixels = cell.pixels;
+    let scale = |size: &Size| Vec2::new(size.width * pixels, size.height * pixels);
+    if cell.changed {
+        for (size, mut sprite) in q.q0_mut().iter_mut() {
+            sprite.size = scale(size);
+        }
+    } else {
+        for (size, mut sprite) in q.q1_mut().iter_mut() {
+            sprite.size = scale(&size);
+        }
+    }
+}
+
+/// Moves sprites whose `Position` changed, or all of them when the cell size
+/// did. The arena is centered in the window, and grid sprites sit in front of
+/// the arena background.
+#[allow(clippy::type_complexity)]
+pub fn position_translation(
+    arena: Res<Arena>,
+    cell: Res<CellSize>,
+    mut q: QuerySet<(
+        Query<(&Position, &mut Transform)>,
+        Query<(Changed<Position>, &mut Transform)>,
+    )>,
+) {
+    let translate = |pos: &Position| arena.cell_center(pos, cell.pixels).extend(1.0);
+    if cell.changed {
+        for (pos, mut transform) in q.q0_mut().iter_mut() {
+            transform.translation = translate(pos);
+        }
+    } else {
+        for (pos, mut transform) in q.q1_mut().iter_mut() {
+            transform.translation = translate(&pos);
+        }
+    }
+}
+
+/// Where to draw a sprite `t` of the way through a move from `from` to `to`.
+/// Sprites that jumped, through a portal, across a wrapped edge or to a new
+/// snake, are drawn where they landed rather than slid across the arena.
+fn interpolate(arena: &Arena, cell: f32, from: &Position, to: &Position, t: f32) -> Vec3 {
+    let end = arena.cell_center(to, cell);
+    if (from.x - to.x).abs() + (from.y - to.y).abs() > 1 {
+        return end.extend(1.0);
+    }
+    let start = arena.cell_center(from, cell);
+    (start + (end - start) * t).extend(1.0)
+}
+
+/// Cells of each snake, head first, by head entity: as drawn on the last
+/// frame, and as they were before the move being drawn.
+#[derive(Default)]
+pub struct SnakeCells {
+    last: HashMap<Entity, Vec<Position>>,
+    before: HashMap<Entity, Vec<Position>>,
+}
+
+/// Slides the snakes from their cells before the last move to their cells
+/// now over the move interval, so they glide instead of jumping a cell per
+/// move. Only the drawing lags; `Position` stays where the game has it. The
+/// body is followed by its place behind the head rather than by entity, as a
+/// move takes the tail segment round to the front. Runs after
+/// `position_translation`, whose jumps it overrides.
+pub fn interpolate_snakes(
+    (arena, cell): (Res<Arena>, Res<CellSize>),
+    snake_timer: Res<SnakeMoveTimer>,
+    mut cells: Local<SnakeCells>,
+    heads: Query<(Entity, &SnakeSegments)>,
+    positions: Query<&Position>,
+    mut transforms: Query<&mut Transform>,
+) {
+    let timer = &snake_timer.0;
+    let t = if timer.duration > 0.0 {
+        (timer.elapsed / timer.duration).min(1.0)
+    } else {
+        1.0
+    };
+    let SnakeCells { last, before } = &mut *cells;
+    if timer.finished {
+        *before = std::mem::take(last);
+    }
+    last.clear();
+    for (head, segments) in heads.iter() {
+        let head_cell = match positions.get(head) {
+            Ok(position) => *position,
+            Err(_) => continue,
+        };
+        let now: Vec<Position> = std::iter::once(head_cell)
+            .chain(segments.positions.iter().copied())
+            .collect();
+        let body = std::iter::once(&head).chain(segments.iter());
+        let previous = before.get(&head);
+        for (index, (entity, to)) in body.zip(&now).enumerate() {
+            // Segments added by growth come out from behind the old tail.
+            let from = previous
+                .and_then(|cells| cells.get(index).or_else(|| cells.last()))
+                .unwrap_or(to);
+            if let Ok(mut transform) = transforms.get_mut(*entity) {
+                transform.translation = interpolate(&arena, cell.pixels, from, to, t);
+            }
+        }
+        last.insert(head, now);
+    }
+}
+
+/// Shrinks every `FoodTimerBar` with its food's remaining lifetime and keeps
+/// it along the top edge of the food's cell.
+pub fn food_timer_bars(
+    cell: Res<CellSize>,
+    lifetimes: Query<&FoodLifetime>,
+    mut bars: Query<With<FoodTimerBar, (&Parent, &mut Size, &mut Transform)>>,
+) {
+    let offset = Vec3::new(0.0, (1.0 - FOOD_TIMER_BAR_HEIGHT) * cell.pixels / 2.0, 0.1);
+    for (parent, mut size, mut transform) in bars.iter_mut() {
+        let width = match lifetimes.get(parent.0) {
+            Ok(lifetime) => FOOD_SIZE * lifetime.left(),
+            Err(_) => continue,
+        };
+        if size.width != width {
+            size.width = width;
+        }
+        if transform.translation != offset {
+            transform.translation = offset;
+        }
+    }
+}
+
+/// How long a dead snake takes to flash and fade away before the game over
+/// screen comes up.
+pub const DEATH_ANIMATION_DURATION: f32 = 1.0;
+/// Part of the animation spent flashing before the fading starts.
+const DEATH_FLASH_DURATION: f32 = 0.3;
+/// How long each piece takes to fade once its turn comes.
+const DEATH_FADE_DURATION: f32 = 0.2;
+/// How often the body flashes white, in seconds per flash.
+const DEATH_FLASH_PERIOD: f32 = 0.1;
+
+/// Time left on the animation of the last snake to die. The game over screen
+/// waits for it.
+pub struct DeathAnimation {
+    timer: Timer,
+}
+
+impl Default for DeathAnimation {
+    fn default() -> Self {
+        Self {
+            timer: expired_timer(DEATH_ANIMATION_DURATION),
+        }
+    }
+}
+
+impl DeathAnimation {
+    pub fn playing(&self) -> bool {
+        !self.timer.finished
+    }
+}
+
+/// A copy of a piece of a dead snake, left behind to fade out once the snake
+/// itself is despawned. `order` runs from 0 at the tail to 1 at the head.
+pub struct Corpse {
+    color: Color,
+    order: f32,
+}
+
+/// Color of a corpse piece `elapsed` seconds into the animation: it flashes
+/// white at first, then fades out, the tail first and the head last.
+fn corpse_color(color: Color, order: f32, elapsed: f32) -> Color {
+    if elapsed < DEATH_FLASH_DURATION {
+        let flash = ((elapsed / DEATH_FLASH_PERIOD) as u32).is_multiple_of(2);
+        return if flash { Color::WHITE } else { color };
+    }
+    let fading = DEATH_ANIMATION_DURATION - DEATH_FLASH_DURATION - DEATH_FADE_DURATION;
+    let start = DEATH_FLASH_DURATION + fading * order;
+    let left = 1.0 - ((elapsed - start) / DEATH_FADE_DURATION).clamp(0.0, 1.0);
+    let mut faded = color;
+    faded.set_a(color.a() * left);
+    faded
+}
+
+/// Leaves a copy of every snake that dies behind for `game_over` to despawn
+/// the real one, and plays the copies out: they flash, fade from the tail to
+/// the head and are despawned once the animation ends.
+#[allow(clippy::too_many_arguments)]
+pub fn death_animation(
+    mut commands: Commands,
+    time: Res<Time>,
+    (mut reader, game_over_events): (
+        Local<EventReader<GameOverEvent>>,
+        Res<Events<GameOverEvent>>,
+    ),
+    mut animation: ResMut<DeathAnimation>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    heads: Query<(Entity, &Player, &SnakeSegments)>,
+    pieces: Query<(&Transform, &Sprite, &Handle<ColorMaterial>)>,
+    mut corpses: Query<(Entity, &Corpse, &Handle<ColorMaterial>)>,
+) {
+    let deaths: Vec<Player> = reader
+        .iter(&game_over_events)
+        .map(|death| death.player)
+        .collect();
+    if !deaths.is_empty() {
+        for (entity, _, _) in corpses.iter_mut() {
+            commands.despawn(entity);
+        }
+        animation.timer.reset();
+    }
+    for (head, player, segments) in heads.iter() {
+        if !deaths.contains(player) {
+            continue;
+        }
+        let tail_first: Vec<Entity> = segments.iter().rev().copied().chain(Some(head)).collect();
+        let last = (tail_first.len() - 1).max(1) as f32;
+        for (i, entity) in tail_first.into_iter().enumerate() {
+            let (transform, sprite, handle) = match pieces.get(entity) {
+                Ok(piece) => piece,
+                Err(_) => continue,
+            };
+            let (color, texture) = match color_materials.get(handle) {
+                Some(material) => (material.color, material.texture.clone()),
+                None => continue,
+            };
+            commands
+                .spawn(SpriteComponents {
+                    material: color_materials.add(ColorMaterial { color, texture }),
+                    sprite: Sprite::new(sprite.size),
+                    transform: *transform,
+                    ..Default::default()
+                })
+                .with(Corpse {
+                    color,
+                    order: i as f32 / last,
+                });
+        }
+    }
+    if !animation.playing() {
+        return;
+    }
+    animation.timer.tick(time.delta_seconds);
+    for (entity, corpse, handle) in corpses.iter_mut() {
+        if animation.timer.finished {
+            commands.despawn(entity);
+        } else if let Some(material) = color_materials.get_mut(handle) {
+            material.color = corpse_color(corpse.color, corpse.order, animation.timer.elapsed);
+        }
+    }
+}
+
+/// Sprites in the burst left where food is eaten.
+pub const PARTICLE_COUNT: usize = 8;
+/// Seconds a particle lasts.
+pub const PARTICLE_LIFETIME: f32 = 0.4;
+/// Cells a particle travels from the food over its lifetime.
+const PARTICLE_SPREAD: f32 = 1.5;
+/// Side of a particle when it appears, in cells; it grows to twice that.
+const PARTICLE_SIZE: f32 = 0.25;
+
+/// One sprite of a burst, flying out from `origin` in pixels along
+/// `heading`, growing and fading as `timer` runs out.
+pub struct Particle {
+    timer: Timer,
+    origin: Vec2,
+    heading: Vec2,
+    color: Color,
+}
+
+/// Headings of a burst of `count` particles, spread evenly round a circle
+/// and turned by `turn` radians so bursts do not all look the same.
+fn burst_headings(count: usize, turn: f32) -> impl Iterator<Item = Vec2> {
+    let step = std::f32::consts::TAU / count as f32;
+    (0..count).map(move |i| {
+        let angle = turn + step * i as f32;
+        Vec2::new(angle.cos(), angle.sin())
+    })
+}
+
+/// Bursts particles the color of the food from each cell food was eaten in.
+pub fn spawn_particles(
+    mut commands: Commands,
+    (mut reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    (arena, cell, materials): (Res<Arena>, Res<CellSize>, Res<Materials>),
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let mut rng = thread_rng();
+    for eaten in reader.iter(&growth_events) {
+        let color = match color_materials.get(eaten.food.material(&materials)) {
+            Some(material) => material.color,
+            None => continue,
+        };
+        let origin = arena.cell_center(&eaten.position, cell.pixels);
+        let turn = rng.gen_range(0.0, std::f32::consts::TAU);
+        for heading in burst_headings(PARTICLE_COUNT, turn) {
+            commands
+                .spawn(SpriteComponents {
+                    material: color_materials.add(color.into()),
+                    sprite: Sprite::new(Vec2::splat(PARTICLE_SIZE * cell.pixels)),
+                    transform: Transform::from_translation(origin.extend(2.0)),
+                    ..Default::default()
+                })
+                .with(Particle {
+                    timer: Timer::from_seconds(PARTICLE_LIFETIME, false),
+                    origin,
+                    heading,
+                    color,
+                });
+        }
+    }
+}
+
+/// Flies each particle out, growing and fading, and despawns it when its
+/// time is up.
+pub fn particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    cell: Res<CellSize>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    mut particles: Query<(
+        Entity,
+        &mut Particle,
+        &mut Transform,
+        &mut Sprite,
+        &Handle<ColorMaterial>,
+    )>,
+) {
+    for (entity, mut particle, mut transform, mut sprite, handle) in particles.iter_mut() {
+        particle.timer.tick(time.delta_seconds);
+        if particle.timer.finished {
+            commands.despawn(entity);
+            continue;
+        }
+        let progress = particle.timer.elapsed / particle.timer.duration;
+        let offset = particle.heading * PARTICLE_SPREAD * cell.pixels * progress;
+        transform.translation = (particle.origin + offset).extend(2.0);
+        sprite.size = Vec2::splat(PARTICLE_SIZE * cell.pixels * (1.0 + progress));
+        if let Some(material) = color_materials.get_mut(handle) {
+            material.color = particle.color;
+            material.color.set_a(particle.color.a() * (1.0 - progress));
+        }
+    }
+}
+
+/// Places and sizes sprites on the grid and draws the snake's body, its
+/// movement between cells, the checkerboard, food timers, the bursts where food is eaten, the
+/// crash shake and the death animation.
+pub struct RenderPlugin;
+
+impl Plugin for RenderPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ScreenShake>()
+            .init_resource::<CellSize>()
+            .init_resource::<DeathAnimation>()
+            .add_system(apply_atlas.system())
+            .add_system(apply_patterns.system())
+            .add_system(arena_checkerboard.system())
+            .add_system(segment_gradient.system())
+            .add_system(head_rotation.system())
+            .add_system(screen_shake.system())
+            .add_system(death_animation.system())
+            .add_system(spawn_particles.system())
+            .add_system(particles.system())
+            .add_system(food_timer_bars.system())
+            .add_system_to_stage(stage::POST_UPDATE, cell_size.system())
+            .add_system_to_stage(stage::POST_UPDATE, position_translation.system())
+            .add_system_to_stage(stage::POST_UPDATE, interpolate_snakes.system())
+            .add_system_to_stage(stage::POST_UPDATE, segment_taper.system())
+            .add_system_to_stage(stage::POST_UPDATE, size_scaling.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameOverReason, Player};
+
+    #[test]
+    fn atlas_tiles_are_cut_column_by_column() {
+        // Two 2x2 tiles of one-byte pixels, numbered row by row.
+        let atlas = Texture::new(
+            Vec2::new(4.0, 2.0),
+            vec![0, 1, 2, 3, 4, 5, 6, 7],
+            TextureFormat::R8Unorm,
+        );
+        let right = cut_tile(&atlas, 1, 2).unwrap();
+        assert_eq!(right.size, Vec2::new(2.0, 2.0));
+        assert_eq!(right.data, vec![2, 3, 6, 7]);
+        assert!(cut_tile(&atlas, 0, 3).is_none());
+    }
+
+    #[test]
+    fn a_burst_flies_out_every_way() {
+        let headings: Vec<Vec2> = burst_headings(4, 0.0).collect();
+        assert_eq!(headings.len(), 4);
+        assert!(headings[0].abs_diff_eq(Vec2::new(1.0, 0.0), 1e-6));
+        assert!(headings[1].abs_diff_eq(Vec2::new(0.0, 1.0), 1e-6));
+        let sum = headings
+            .iter()
+            .fold(Vec2::zero(), |sum, heading| sum + *heading);
+        assert!(sum.abs_diff_eq(Vec2::zero(), 1e-6));
+    }
+
+    #[test]
+    fn the_dead_snake_flashes_then_fades_from_the_tail() {
+        let color = Color::rgb(0.2, 0.6, 0.2);
+        let alpha = |order, elapsed| corpse_color(color, order, elapsed).a();
+        assert_eq!(corpse_color(color, 1.0, 0.0), Color::WHITE);
+        assert_eq!(corpse_color(color, 1.0, DEATH_FLASH_PERIOD * 1.5), color);
+        let midway = DEATH_ANIMATION_DURATION / 2.0 + 0.1;
+        assert_eq!(alpha(0.0, midway), 0.0);
+        assert_eq!(alpha(1.0, midway), 1.0);
+        assert!(alpha(0.5, midway) > 0.0 && alpha(0.5, midway) < 1.0);
+        assert!(alpha(1.0, DEATH_ANIMATION_DURATION) < 0.01);
+    }
+
+    #[test]
+    fn patterns_darken_only_their_pixels() {
+        let white = Texture::new(
+            Vec2::new(4.0, 4.0),
+            vec![255; 4 * 4 * 4],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        let shades = |texture: Texture| -> Vec<u8> {
+            texture.data.chunks(4).map(|pixel| pixel[0]).collect()
+        };
+        let dark = (255.0 * PATTERN_SHADE) as u8;
+        let dots = shades(draw_pattern(&white, Pattern::Dots));
+        assert_eq!(dots.iter().filter(|shade| **shade == dark).count(), 4);
+        assert_eq!(&dots[8..12], &[255, 255, dark, dark]);
+        let stripes = draw_pattern(&white, Pattern::Stripes);
+        assert_eq!(stripes.data[3], 255);
+        assert_eq!(&shades(stripes)[..4], &[dark, dark, dark, 255]);
+    }
+
+    #[test]
+    fn the_checkerboard_has_a_square_per_cell() {
+        let board = checkerboard(3, 2);
+        assert_eq!(board.size, Vec2::new(3.0, 2.0));
+        let shades: Vec<u8> = board.data.chunks(4).map(|pixel| pixel[0]).collect();
+        let dark = CHECKER_DARK;
+        // The top row comes first; the bottom left cell is light.
+        assert_eq!(shades, vec![dark, 255, dark, 255, dark, 255]);
+    }
+
+    #[test]
+    fn segments_join_their_neighbours() {
+        let cell = |x, y| Position { x, y };
+        let shape = |ahead: Position, behind: Option<Position>| {
+            segment_shape(&cell(5, 5), &ahead, behind.as_ref())
+        };
+        assert_eq!(
+            shape(cell(5, 6), Some(cell(5, 4))),
+            (SegmentShape::Straight, Direction::Up)
+        );
+        assert_eq!(
+            shape(cell(4, 5), Some(cell(6, 5))),
+            (SegmentShape::Straight, Direction::Left)
+        );
+        // The corner tile joins down and right as drawn.
+        assert_eq!(
+            shape(cell(5, 4), Some(cell(6, 5))),
+            (SegmentShape::Corner, Direction::Up)
+        );
+        let (corner, turn) = shape(cell(5, 6), Some(cell(4, 5)));
+        assert_eq!(corner, SegmentShape::Corner);
+        let mut joined = [
+            turned(Direction::Down, turn),
+            turned(Direction::Right, turn),
+        ];
+        joined.sort_by_key(|direction| *direction != Direction::Up);
+        assert_eq!(joined, [Direction::Up, Direction::Left]);
+        assert_eq!(
+            shape(cell(6, 5), None),
+            (SegmentShape::Tail, Direction::Right)
+        );
+        // Across a wrapped edge the segment carries straight on.
+        assert_eq!(
+            shape(cell(5, 6), Some(cell(0, 5))),
+            (SegmentShape::Straight, Direction::Up)
+        );
+    }
+
+    #[test]
+    fn heads_face_the_way_they_go() {
+        for direction in &Direction::ALL {
+            let (dx, dy) = match direction {
+                Direction::Up => (0.0, 1.0),
+                Direction::Down => (0.0, -1.0),
+                Direction::Left => (-1.0, 0.0),
+                Direction::Right => (1.0, 0.0),
+            };
+            let ahead = facing(*direction) * Vec3::new(0.0, 1.0, 0.0);
+            assert!(
+                (ahead - Vec3::new(dx, dy, 0.0)).length() < 1e-6,
+                "{:?}",
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn moves_slide_between_neighbouring_cells_only() {
+        let arena = Arena {
+            width: 10,
+            height: 10,
+        };
+        let from = Position { x: 4, y: 5 };
+        let to = Position { x: 5, y: 5 };
+        assert_eq!(
+            interpolate(&arena, 10.0, &from, &to, 0.5),
+            Vec3::new(0.0, 5.0, 1.0)
+        );
+        assert_eq!(
+            interpolate(&arena, 10.0, &from, &to, 1.0),
+            Vec3::new(5.0, 5.0, 1.0)
+        );
+        let wrapped = Position { x: 0, y: 5 };
+        assert_eq!(
+            interpolate(&arena, 10.0, &Position { x: 9, y: 5 }, &wrapped, 0.1),
+            Vec3::new(-45.0, 5.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn screen_shake_restores_the_camera() {
+        let mut builder = App::build();
+        builder
+            .init_resource::<Time>()
+            .init_resource::<ScreenShake>()
+            .init_resource::<ShakeIntensity>()
+            .add_event::<GameOverEvent>()
+            .add_event::<GrowthEvent>()
+            .add_system(screen_shake.system());
+        let mut app = std::mem::take(&mut builder.app);
+        let origin = Vec3::new(0.0, 0.0, 999.9);
+        let camera = app
+            .world
+            .spawn((GameCamera, Transform::from_translation(origin)));
+        app.executor.initialize(&mut app.resources);
+        app.resources.get_mut::<Time>().unwrap().delta_seconds = 0.05;
+
+        for frame in 0..20 {
+            if frame % 3 == 0 {
+                app.resources
+                    .get_mut::<Events<GameOverEvent>>()
+                    .unwrap()
+                    .send(GameOverEvent {
+                        player: Player::One,
+                        reason: GameOverReason::HitSelf,
+                        position: Position::default(),
+                    });
+            }
+            app.update();
+        }
+        assert_ne!(
+            app.world.get::<Transform>(camera).unwrap().translation,
+            origin
+        );
+        for _ in 0..20 {
+            app.update();
+        }
+        assert_eq!(
+            app.world.get::<Transform>(camera).unwrap().translation,
+            origin
+        );
+    }
+}
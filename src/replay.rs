@@ -0,0 +1,168 @@
+//! Recorded runs and their on-disk format.
+
+use crate::{Difficulty, Direction, Position};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Everything needed to re-simulate a run: the seed its RNG started from, the
+/// direction changes `snake_movement` applied keyed by move tick, the frames
+/// at which the boost key went down or up, and the game clock delta of every
+/// frame, since spawn and power-up timers run on the clock rather than on move
+/// ticks.
+///
+/// The difficulty the run was played at, its final score and the head
+/// position and snake length after every move tick are kept as well, so the
+/// run can be re-enacted without simulating it.
+/// So is the name of the player who played it, empty when no profile had
+/// been made.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub player: String,
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub score: u32,
+    pub inputs: Vec<(u32, Direction)>,
+    pub boosts: Vec<u32>,
+    pub steps: Vec<(Position, usize)>,
+    pub frames: Vec<f32>,
+}
+
+impl Replay {
+    /// Whether the boost key was held on `frame`.
+    pub fn boost_held(&self, frame: u32) -> bool {
+        self.boosts
+            .iter()
+            .filter(|toggle| **toggle <= frame)
+            .count()
+            % 2
+            == 1
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+/// One `player`, `seed`, `difficulty`, `score`, `input`, `boost`, `step` or
+/// `frame` record per line. The player's name runs to the end of its line.
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.player.is_empty() {
+            writeln!(f, "player {}", self.player)?;
+        }
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "difficulty {:?}", self.difficulty)?;
+        writeln!(f, "score {}", self.score)?;
+        for (tick, direction) in &self.inputs {
+            writeln!(f, "input {} {:?}", tick, direction)?;
+        }
+        for frame in &self.boosts {
+            writeln!(f, "boost {}", frame)?;
+        }
+        for (head, length) in &self.steps {
+            writeln!(f, "step {} {} {}", head.x, head.y, length)?;
+        }
+        for delta in &self.frames {
+            writeln!(f, "frame {}", delta)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Replay {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid replay line: {:?}", line),
+            )
+        };
+        let mut replay = Replay::default();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            if let Some(player) = line.strip_prefix("player ") {
+                replay.player = player.to_string();
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["seed", seed] => replay.seed = seed.parse().map_err(|_| invalid(line))?,
+                ["difficulty", difficulty] => {
+                    replay.difficulty = difficulty.parse().map_err(|_| invalid(line))?
+                }
+                ["score", score] => replay.score = score.parse().map_err(|_| invalid(line))?,
+                ["input", tick, direction] => {
+                    let tick = tick.parse().map_err(|_| invalid(line))?;
+                    let direction = match *direction {
+                        "Left" => Direction::Left,
+                        "Up" => Direction::Up,
+                        "Right" => Direction::Right,
+                        "Down" => Direction::Down,
+                        _ => return Err(invalid(line)),
+                    };
+                    replay.inputs.push((tick, direction));
+                }
+                ["boost", frame] => replay
+                    .boosts
+                    .push(frame.parse().map_err(|_| invalid(line))?),
+                ["step", x, y, length] => {
+                    let head = Position {
+                        x: x.parse().map_err(|_| invalid(line))?,
+                        y: y.parse().map_err(|_| invalid(line))?,
+                    };
+                    replay
+                        .steps
+                        .push((head, length.parse().map_err(|_| invalid(line))?));
+                }
+                ["frame", delta] => replay
+                    .frames
+                    .push(delta.parse().map_err(|_| invalid(line))?),
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(replay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_text() {
+        let replay = Replay {
+            player: "Ada L".to_string(),
+            seed: 0xdead_beef,
+            difficulty: Difficulty::Hard,
+            score: 40,
+            inputs: vec![(3, Direction::Left), (9, Direction::Down)],
+            boosts: vec![12, 30],
+            steps: vec![(Position { x: 3, y: 4 }, 2), (Position { x: -1, y: 4 }, 3)],
+            frames: vec![0.016_666_668, 0.0, 0.25],
+        };
+        assert_eq!(replay.to_string().parse::<Replay>().unwrap(), replay);
+    }
+
+    #[test]
+    fn boost_toggles_on_and_off() {
+        let replay = Replay {
+            boosts: vec![5, 8],
+            ..Default::default()
+        };
+        let held: Vec<u32> = (0..10).filter(|frame| replay.boost_held(*frame)).collect();
+        assert_eq!(held, [5, 6, 7]);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!("seed nope".parse::<Replay>().is_err());
+        assert!("input 1 Sideways".parse::<Replay>().is_err());
+    }
+}
@@ -0,0 +1,659 @@
+//! Head-to-head over the network with rollback netcode, in the manner of
+//! GGPO and GGRS. Each peer plays its own copy of the game in a headless
+//! `Simulation`, which runs the same on both given the same inputs. A peer
+//! does not wait for the other's input for a move: it guesses that the other
+//! snake did not turn, plays on, and once the real input arrives and proves
+//! the guess wrong, restores the `Snapshot` taken before that move and plays
+//! the moves since again.
+//!
+//! Inputs travel over UDP. Every packet repeats all the inputs the peer has
+//! not acknowledged yet, so a lost packet costs nothing but a little delay.
+//!
+//! `--peer ADDRESS` plays such a game in the window: each side listens on
+//! `--port` (7000 by default) and is given the other's address, e.g.
+//! `--peer 192.168.1.20:7000` on one machine and `--peer 192.168.1.10:7000`
+//! on the other. The peers say hello before the first move, which decides
+//! who plays which snake and, without `--seed`, the seed.
+
+use crate::bindings::KeyBindings;
+use crate::client::{turns_pressed, FrameViewPlugin, NextFrame};
+use crate::headless::{Simulation, Snapshot};
+use crate::options::Options;
+use crate::server::Frame;
+use crate::{Direction, Player, MAX_MOVES_PER_FRAME};
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Port `--peer` games listen on without `--port`.
+pub const DEFAULT_PORT: u16 = 7000;
+
+/// Moves a local input waits before it steers, in `--peer` games.
+pub const INPUT_DELAY: u32 = 2;
+
+/// Moves a peer may play ahead of the last input it has from the other
+/// before it stops and waits.
+pub const MAX_PREDICTION: u32 = 8;
+
+/// Inputs a single packet carries at most.
+const MAX_PACKET_INPUTS: usize = 255;
+
+/// Bytes of a packet before its inputs: the acknowledgement, the move the
+/// first input is for, and how many inputs follow.
+const PACKET_HEADER: usize = 9;
+
+/// Bytes of a hello: the nonce that decides who plays which snake. Shorter
+/// than any packet of inputs, so the two are never mistaken.
+const HELLO_LENGTH: usize = 8;
+
+/// How often a hello is sent again while the peer has not answered.
+const HELLO_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for the peer to say hello before giving up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// One player's input for one move: the way they steered, if they did.
+type Steer = Option<Direction>;
+
+fn encode(steer: Steer) -> u8 {
+    match steer {
+        None => 0,
+        Some(direction) => {
+            1 + Direction::ALL
+                .iter()
+                .position(|known| *known == direction)
+                .unwrap() as u8
+        }
+    }
+}
+
+fn decode(byte: u8) -> Option<Steer> {
+    match byte {
+        0 => Some(None),
+        _ => Direction::ALL
+            .get(byte as usize - 1)
+            .map(|direction| Some(*direction)),
+    }
+}
+
+/// A versus game against a peer on the other end of `socket`. Both peers
+/// must be started from the same options, `--seed` included, one as
+/// `Player::One` and the other as `Player::Two`.
+pub struct RollbackSession {
+    simulation: Simulation,
+    socket: UdpSocket,
+    local: Player,
+    /// This peer's input for every move so far, and the next few: each input
+    /// steers the move the input delay after it is given, which gives it
+    /// time to reach the peer before it is needed there.
+    local_inputs: Vec<Steer>,
+    /// The peer's input for every move up to the first one still missing.
+    remote_inputs: Vec<Steer>,
+    /// The peer's input each move played so far was played with, whether
+    /// received or guessed.
+    played: Vec<Steer>,
+    /// Local inputs the peer has acknowledged receiving.
+    acknowledged: usize,
+    /// Snapshots from before each move whose peer input is still a guess.
+    snapshots: VecDeque<(u32, Snapshot)>,
+    rollbacks: u32,
+    /// The nonce this peer said hello with, if it met the other through
+    /// `meet`; sent again whenever the other says hello, in case ours was
+    /// lost.
+    hello: Option<u64>,
+}
+
+/// Says hello to the peer `socket` is connected to until it says hello
+/// back, and returns its nonce.
+fn handshake(socket: &UdpSocket, nonce: u64) -> io::Result<u64> {
+    socket.set_read_timeout(Some(HELLO_INTERVAL))?;
+    let started = Instant::now();
+    let mut buffer = [0; PACKET_HEADER + MAX_PACKET_INPUTS];
+    while started.elapsed() < HANDSHAKE_TIMEOUT {
+        match socket.send(&nonce.to_le_bytes()) {
+            Err(err) if err.kind() != io::ErrorKind::ConnectionRefused => return Err(err),
+            _ => {}
+        }
+        match socket.recv(&mut buffer) {
+            Ok(HELLO_LENGTH) => {
+                let mut bytes = [0; HELLO_LENGTH];
+                bytes.copy_from_slice(&buffer[..HELLO_LENGTH]);
+                return Ok(u64::from_le_bytes(bytes));
+            }
+            // Inputs from a peer that already has our hello; it sends them
+            // again once we start.
+            Ok(_) => {}
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionRefused
+                ) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "the peer never said hello",
+    ))
+}
+
+impl RollbackSession {
+    /// Starts a game of the versus mode `options` describe, seeded with
+    /// `--seed`, against the peer `socket` is connected to.
+    pub fn new(
+        options: &Options,
+        socket: UdpSocket,
+        local: Player,
+        input_delay: u32,
+    ) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        let options = Options {
+            versus: true,
+            ..options.clone()
+        };
+        Ok(Self {
+            simulation: Simulation::new(&options, options.seed.unwrap_or_default()),
+            socket,
+            local,
+            local_inputs: vec![None; input_delay as usize],
+            remote_inputs: Vec::new(),
+            played: Vec::new(),
+            acknowledged: 0,
+            snapshots: VecDeque::new(),
+            rollbacks: 0,
+            hello: None,
+        })
+    }
+
+    /// Says hello to the peer `socket` is connected to and starts a game of
+    /// the versus mode `options` describe against it. The peer that drew
+    /// the larger nonce plays `Player::One`, and its nonce seeds the game
+    /// unless `--seed` does.
+    pub fn meet(options: &Options, socket: UdpSocket, input_delay: u32) -> io::Result<Self> {
+        let nonce = rand::random::<u64>();
+        let theirs = handshake(&socket, nonce)?;
+        Self::greeted(options, socket, nonce, theirs, input_delay)
+    }
+
+    /// Starts the game `meet` does once the peers have said hello, this one
+    /// with `nonce` and the other with `theirs`.
+    fn greeted(
+        options: &Options,
+        socket: UdpSocket,
+        nonce: u64,
+        theirs: u64,
+        input_delay: u32,
+    ) -> io::Result<Self> {
+        let (local, seed) = match nonce.cmp(&theirs) {
+            Ordering::Greater => (Player::One, nonce),
+            Ordering::Less => (Player::Two, theirs),
+            Ordering::Equal => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "the peer said hello with our own nonce",
+                ))
+            }
+        };
+        let options = Options {
+            seed: options.seed.or(Some(seed)),
+            ..options.clone()
+        };
+        let mut session = Self::new(&options, socket, local, input_delay)?;
+        session.hello = Some(nonce);
+        Ok(session)
+    }
+
+    /// Listens on `port` for the peer at `peer` and meets it there.
+    pub fn connect(
+        options: &Options,
+        peer: SocketAddr,
+        port: u16,
+        input_delay: u32,
+    ) -> io::Result<Self> {
+        let any: IpAddr = match peer {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((any, port))?;
+        socket.connect(peer)?;
+        Self::meet(options, socket, input_delay)
+    }
+
+    /// Gives this peer's input for the move `input_delay` moves from now,
+    /// takes in whatever the peer has sent, rolling back if it proves a guess
+    /// wrong, and plays the next move. Returns whether a move was played: it
+    /// is not while this peer is too far ahead of the other, and once the
+    /// run is over.
+    pub fn advance(&mut self, steer: Steer) -> io::Result<bool> {
+        self.sync()?;
+        if self.simulation.game_over() || self.frame() >= self.confirmed() + MAX_PREDICTION {
+            // Sent again in case the last packets were lost.
+            self.send()?;
+            return Ok(false);
+        }
+        self.local_inputs.push(steer);
+        self.send()?;
+        self.play_move();
+        Ok(true)
+    }
+
+    /// Takes in what the peer has sent and answers it, without playing a
+    /// move; call it while waiting on the peer, e.g. after the run ends.
+    pub fn sync(&mut self) -> io::Result<()> {
+        let mut buffer = [0; PACKET_HEADER + MAX_PACKET_INPUTS];
+        let mut received = false;
+        loop {
+            match self.socket.recv(&mut buffer) {
+                // The peer never heard our hello.
+                Ok(HELLO_LENGTH) => self.say_hello()?,
+                Ok(length) => received |= self.receive(&buffer[..length]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // The peer's port is not open yet.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => break,
+                Err(err) => return Err(err),
+            }
+        }
+        self.roll_back();
+        if received {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    /// Moves played so far, guessed ones included.
+    pub fn frame(&self) -> u32 {
+        self.played.len() as u32
+    }
+
+    /// Moves played with the peer's real input; they will not be rolled
+    /// back.
+    pub fn confirmed(&self) -> u32 {
+        self.remote_inputs.len().min(self.played.len()) as u32
+    }
+
+    /// Times a wrong guess has been rolled back.
+    pub fn rollbacks(&self) -> u32 {
+        self.rollbacks
+    }
+
+    pub fn local(&self) -> Player {
+        self.local
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    fn say_hello(&self) -> io::Result<()> {
+        match self.hello {
+            Some(nonce) => self.socket.send(&nonce.to_le_bytes()).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads one packet, keeping the inputs that extend those already had.
+    /// Returns whether it was well formed.
+    fn receive(&mut self, packet: &[u8]) -> bool {
+        if packet.len() < PACKET_HEADER {
+            return false;
+        }
+        let number = |at: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&packet[at..at + 4]);
+            u32::from_le_bytes(bytes)
+        };
+        let (acknowledged, start) = (number(0) as usize, number(4) as usize);
+        let inputs = &packet[PACKET_HEADER..];
+        if inputs.len() != packet[8] as usize {
+            return false;
+        }
+        self.acknowledged = self
+            .acknowledged
+            .max(acknowledged.min(self.local_inputs.len()));
+        for (at, byte) in (start..).zip(inputs) {
+            match decode(*byte) {
+                Some(steer) if at == self.remote_inputs.len() => self.remote_inputs.push(steer),
+                Some(_) => {}
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Sends every local input the peer has not acknowledged, and how many
+    /// of its inputs this peer has.
+    fn send(&mut self) -> io::Result<()> {
+        let unacknowledged = &self.local_inputs[self.acknowledged..];
+        let inputs = &unacknowledged[..unacknowledged.len().min(MAX_PACKET_INPUTS)];
+        let mut packet = Vec::with_capacity(PACKET_HEADER + inputs.len());
+        packet.extend_from_slice(&(self.remote_inputs.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(self.acknowledged as u32).to_le_bytes());
+        packet.push(inputs.len() as u8);
+        packet.extend(inputs.iter().map(|steer| encode(*steer)));
+        match self.socket.send(&packet) {
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            sent => sent.map(|_| ()),
+        }
+    }
+
+    /// Goes back to the first move played with a wrong guess, if any, and
+    /// plays every move since again with what is now known.
+    fn roll_back(&mut self) {
+        let checked = self
+            .snapshots
+            .front()
+            .map_or(self.played.len(), |(frame, _)| *frame as usize);
+        let wrong = (checked..self.confirmed() as usize)
+            .find(|&frame| self.played[frame] != self.remote_inputs[frame]);
+        if let Some(wrong) = wrong {
+            let played = self.played.len();
+            while self
+                .snapshots
+                .back()
+                .is_some_and(|(frame, _)| *frame as usize > wrong)
+            {
+                self.snapshots.pop_back();
+            }
+            let (_, snapshot) = self.snapshots.pop_back().unwrap();
+            self.simulation.restore(&snapshot);
+            self.played.truncate(wrong);
+            while self.played.len() < played && !self.simulation.game_over() {
+                self.play_move();
+            }
+            self.rollbacks += 1;
+        }
+        // Moves played with real input can never be rolled back to.
+        let confirmed = self.confirmed();
+        while self
+            .snapshots
+            .front()
+            .is_some_and(|(frame, _)| *frame < confirmed)
+        {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Plays the next move with both players' inputs, guessing that the
+    /// peer did not turn if its input has not arrived.
+    fn play_move(&mut self) {
+        let frame = self.played.len();
+        let local = self.local_inputs[frame];
+        let remote = self.remote_inputs.get(frame).copied().unwrap_or(None);
+        if frame >= self.remote_inputs.len() {
+            self.snapshots
+                .push_back((frame as u32, self.simulation.snapshot()));
+        }
+        // The same player steers first on both peers.
+        let (one, two) = match self.local {
+            Player::One => (local, remote),
+            _ => (remote, local),
+        };
+        for (player, steer) in [(Player::One, one), (Player::Two, two)] {
+            if let Some(direction) = steer {
+                self.simulation.steer_player(player, direction);
+            }
+        }
+        self.played.push(remote);
+        self.simulation.step();
+    }
+}
+
+/// A `--peer` game being played in the window.
+pub struct PeerGame {
+    session: RollbackSession,
+    /// The last turn pressed, kept until the next move takes it.
+    steer: Steer,
+    /// Time since the last move.
+    behind: f32,
+    /// The move and rollback count of the last frame drawn.
+    drawn: Option<(u32, u32)>,
+    /// Whether the link to the peer broke.
+    failed: bool,
+}
+
+impl PeerGame {
+    pub fn new(session: RollbackSession) -> Self {
+        Self {
+            session,
+            steer: None,
+            behind: 0.0,
+            drawn: None,
+            failed: false,
+        }
+    }
+
+    pub fn session(&self) -> &RollbackSession {
+        &self.session
+    }
+
+    /// Plays the moves due after `delta` more seconds, as many as the peer
+    /// lets this one get ahead.
+    fn play(&mut self, delta: f32) -> io::Result<()> {
+        let interval = self.session.simulation().move_interval();
+        self.behind = (self.behind + delta).min(interval * MAX_MOVES_PER_FRAME as f32);
+        let mut played = false;
+        while self.behind >= interval && self.session.advance(self.steer)? {
+            self.steer = None;
+            self.behind -= interval;
+            played = true;
+        }
+        if !played {
+            self.session.sync()?;
+        }
+        Ok(())
+    }
+}
+
+/// Steers the local snake of the `PeerGame`, which must be added first as a
+/// thread-local resource, plays it on, and hands each new frame to
+/// `draw_frame`.
+pub fn play_peer(_world: &mut World, resources: &mut Resources) {
+    let turns = turns_pressed(
+        &resources.get::<Input<KeyCode>>().unwrap(),
+        &resources.get::<KeyBindings>().unwrap(),
+    );
+    let delta = resources.get::<Time>().unwrap().delta_seconds;
+    let mut game = resources.get_thread_local_mut::<PeerGame>().unwrap();
+    if let Some(direction) = turns.last() {
+        game.steer = Some(*direction);
+    }
+    if !game.failed {
+        if let Err(err) = game.play(delta) {
+            eprintln!("lost the peer: {}", err);
+            game.failed = true;
+        }
+    }
+    let mut next = resources.get_mut::<NextFrame>().unwrap();
+    next.closed = game.failed;
+    let now = (game.session.frame(), game.session.rollbacks());
+    if game.drawn != Some(now) {
+        game.drawn = Some(now);
+        next.frame = Some(Frame::new(game.session.simulation(), game.session.local()));
+    }
+}
+
+/// Plays the `PeerGame` in place of the `SnakePlugin`.
+pub struct PeerPlugin;
+
+impl Plugin for PeerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(FrameViewPlugin)
+            .add_system_to_stage(stage::PRE_UPDATE, play_peer.thread_local_system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_survive_the_wire() {
+        for steer in [None, Some(Direction::Left), Some(Direction::Down)] {
+            assert_eq!(decode(encode(steer)), Some(steer));
+        }
+        assert_eq!(decode(9), None);
+    }
+
+    /// Two sessions talking over loopback end up in the same game, however
+    /// often either had to guess.
+    #[test]
+    fn peers_agree_after_rolling_back() {
+        let one = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let two = UdpSocket::bind("127.0.0.1:0").unwrap();
+        one.connect(two.local_addr().unwrap()).unwrap();
+        two.connect(one.local_addr().unwrap()).unwrap();
+        let options = Options {
+            seed: Some(11),
+            ..Default::default()
+        };
+        let mut sessions = [
+            RollbackSession::new(&options, one, Player::One, 0).unwrap(),
+            RollbackSession::new(&options, two, Player::Two, 0).unwrap(),
+        ];
+        // Each snake turns now and then, in a square well clear of the
+        // edges.
+        let turns = [
+            Direction::Right,
+            Direction::Up,
+            Direction::Left,
+            Direction::Down,
+        ];
+        for moves in 0..40u32 {
+            for (i, session) in sessions.iter_mut().enumerate() {
+                let steer = if moves % 4 == i as u32 {
+                    Some(turns[(moves / 4) as usize % 4])
+                } else {
+                    None
+                };
+                session.advance(steer).unwrap();
+            }
+        }
+        for _ in 0..100 {
+            if sessions
+                .iter()
+                .all(|session| session.confirmed() == session.frame())
+            {
+                break;
+            }
+            for session in &mut sessions {
+                session.sync().unwrap();
+            }
+        }
+
+        assert!(sessions.iter().any(|session| session.rollbacks() > 0));
+        let state = |session: &RollbackSession| {
+            let mut heads: Vec<(Player, crate::Position)> = session
+                .simulation()
+                .world()
+                .query::<(&Player, &crate::Position)>()
+                .map(|(player, position)| (*player, *position))
+                .collect();
+            heads.sort_by_key(|(player, _)| *player == Player::Two);
+            (
+                session.frame(),
+                session.simulation().ticks(),
+                session.simulation().score(),
+                heads,
+            )
+        };
+        assert_eq!(state(&sessions[0]), state(&sessions[1]));
+    }
+
+    /// Two windowed games that met over loopback play a whole run through
+    /// `play_peer`, steered by key presses, and draw the same ending.
+    #[test]
+    fn peers_play_a_run_through_the_game_loop() {
+        let one = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let two = UdpSocket::bind("127.0.0.1:0").unwrap();
+        one.connect(two.local_addr().unwrap()).unwrap();
+        two.connect(one.local_addr().unwrap()).unwrap();
+        let options = Options::default();
+        // Sessions cannot cross threads, so the second peer only says hello
+        // on one and starts its session once back.
+        let nonce = rand::random::<u64>();
+        let other = std::thread::spawn(move || {
+            let theirs = handshake(&two, nonce).unwrap();
+            (two, theirs)
+        });
+        let first = RollbackSession::meet(&options, one, 0).unwrap();
+        let (two, theirs) = other.join().unwrap();
+        let sessions = vec![
+            first,
+            RollbackSession::greeted(&options, two, nonce, theirs, 0).unwrap(),
+        ];
+        assert_ne!(sessions[0].local(), sessions[1].local());
+        let interval = sessions[0].simulation().move_interval();
+        let mut apps: Vec<App> = sessions
+            .into_iter()
+            .map(|session| {
+                let mut builder = App::build();
+                builder
+                    .add_resource(Time {
+                        delta_seconds: interval,
+                        ..Default::default()
+                    })
+                    .init_resource::<Input<KeyCode>>()
+                    .init_resource::<KeyBindings>()
+                    .init_resource::<NextFrame>()
+                    .add_thread_local_resource(PeerGame::new(session))
+                    .add_system(play_peer.thread_local_system());
+                let mut app = std::mem::take(&mut builder.app);
+                app.executor.initialize(&mut app.resources);
+                app
+            })
+            .collect();
+        let turns = [
+            crate::bindings::Action::TurnLeft,
+            crate::bindings::Action::TurnUp,
+        ];
+
+        let mut frames: [Option<Frame>; 2] = [None, None];
+        for update in 0..2000 {
+            for (i, app) in apps.iter_mut().enumerate() {
+                if update % 3 == i {
+                    let key = app.resources.get::<KeyBindings>().unwrap().keys(turns[i])[0];
+                    app.resources
+                        .get_mut::<Input<KeyCode>>()
+                        .unwrap()
+                        .press(key);
+                }
+                app.update();
+                *app.resources.get_mut::<Input<KeyCode>>().unwrap() = Input::default();
+                if let Some(frame) = app.resources.get_mut::<NextFrame>().unwrap().frame.take() {
+                    frames[i] = Some(frame);
+                }
+            }
+            let settled = apps.iter().all(|app| {
+                let game = app.resources.get_thread_local::<PeerGame>().unwrap();
+                game.session().simulation().game_over()
+                    && game.session().confirmed() == game.session().frame()
+            });
+            if settled {
+                break;
+            }
+        }
+
+        let games: Vec<_> = apps
+            .iter()
+            .map(|app| app.resources.get_thread_local::<PeerGame>().unwrap())
+            .collect();
+        assert!(games.iter().all(|game| !game.failed));
+        assert!(
+            games
+                .iter()
+                .map(|game| game.session().rollbacks())
+                .sum::<u32>()
+                > 0
+        );
+        assert_eq!(games[0].session().frame(), games[1].session().frame());
+        let [one, two] = frames.map(Option::unwrap);
+        assert!(one.over && two.over);
+        assert_eq!((one.width, one.height), (two.width, two.height));
+        assert_eq!((one.score, one.rival_score), (two.rival_score, two.score));
+    }
+}
@@ -0,0 +1,485 @@
+//! The dedicated server: the game runs headless on the server, the only
+//! place that decides what happens, and thin clients (see `client`) send it
+//! their turns and draw the frames it sends back. Nothing but turns is
+//! trusted from a client.
+//!
+//! Clients speak a line-based protocol over TCP. A client sends a direction,
+//! `left`, `up`, `right` or `down`, on a line of its own whenever it turns.
+//! After every move the server sends each client a `Frame` as a line of
+//! JSON, drawn from that client's side of the arena.
+//!
+//! The arena seats as many players as the mode has snakes to steer: two in
+//! co-op, two to four in versus as `--players` says, and one otherwise. A
+//! run starts once every seat is taken
+//! and the server goes back to waiting for players when it ends. Clients
+//! that connect while a run is on watch it, and the runs after it: they are
+//! sent the frames `SPECTATOR_DELAY` moves late, so a player cannot look
+//! over a spectator's shoulder for an edge, and whatever they send is
+//! ignored.
+
+use crate::env::{observe_world, Cell};
+use crate::headless::Simulation;
+use crate::options::Options;
+use crate::{
+    versus_score, AppState, Direction, GameMode, Player, RivalScores, Score, VersusPlayers,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the server listens unless `--listen` says otherwise.
+pub const DEFAULT_ADDRESS: &str = "0.0.0.0:7777";
+
+/// Moves spectators are kept behind the players.
+pub const SPECTATOR_DELAY: usize = 10;
+
+/// How long a client may take to read a frame before it is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The word a direction goes by on the wire.
+pub fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Left => "left",
+        Direction::Up => "up",
+        Direction::Right => "right",
+        Direction::Down => "down",
+    }
+}
+
+/// The direction a line from a client names, if any.
+pub fn parse_direction(line: &str) -> Option<Direction> {
+    Direction::ALL
+        .iter()
+        .copied()
+        .find(|direction| direction_name(*direction) == line.trim())
+}
+
+/// The game after a move, as one player sees it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    /// The player the frame is for, from 1, or 0 for a spectator.
+    pub player: u8,
+    pub width: u32,
+    pub height: u32,
+    /// One digit per cell, the `Cell` numbering of `Observation::encode`,
+    /// row by row from the bottom of the arena.
+    pub cells: String,
+    pub score: u32,
+    /// The best of the other players' scores in versus.
+    pub rival_score: u32,
+    /// Whether the run has ended.
+    pub over: bool,
+}
+
+impl Frame {
+    /// What `player` sees of `simulation` right now.
+    pub fn new(simulation: &Simulation, player: Player) -> Self {
+        Self::observe(simulation.world(), simulation.resources(), player)
+    }
+
+    /// What `player` sees of the game in `world` right now.
+    pub fn observe(world: &World, resources: &Resources, player: Player) -> Self {
+        let observation = observe_world(world, resources, player);
+        let (score, rival_scores) = (
+            resources.get::<Score>().unwrap(),
+            resources.get::<RivalScores>().unwrap(),
+        );
+        let rival_score = Player::ALL
+            .iter()
+            .filter(|rival| **rival != player)
+            .map(|rival| versus_score(*rival, &score, &rival_scores))
+            .max()
+            .unwrap_or(0);
+        Self {
+            player: player.number(),
+            width: observation.width,
+            height: observation.height,
+            cells: observation
+                .encode()
+                .into_iter()
+                .map(|code| (b'0' + code) as char)
+                .collect(),
+            score: versus_score(player, &score, &rival_scores),
+            rival_score,
+            over: *resources.get::<AppState>().unwrap() == AppState::GameOver,
+        }
+    }
+
+    /// What spectators see of `simulation` right now: the arena from Player
+    /// 1's side.
+    pub fn spectated(simulation: &Simulation) -> Self {
+        Self {
+            player: 0,
+            ..Self::new(simulation, Player::One)
+        }
+    }
+
+    /// The cells, row by row from the bottom, with anything unreadable left
+    /// empty.
+    pub fn cells(&self) -> Vec<Cell> {
+        self.cells
+            .bytes()
+            .map(|digit| Cell::decode(digit.wrapping_sub(b'0')).unwrap_or(Cell::Empty))
+            .collect()
+    }
+}
+
+/// A connected player: the turns their client sends, read on a thread of
+/// its own, and the stream frames go out on.
+struct Seat {
+    player: Player,
+    stream: TcpStream,
+    turns: Receiver<Direction>,
+}
+
+impl Seat {
+    fn new(player: Player, stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, turns) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let direction = match line {
+                    Ok(line) => parse_direction(&line),
+                    Err(_) => break,
+                };
+                if let Some(direction) = direction {
+                    if sender.send(direction).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            player,
+            stream,
+            turns,
+        })
+    }
+
+    /// The last turn sent since the previous move, if any. Fails once the
+    /// client has gone.
+    fn turn(&self) -> io::Result<Option<Direction>> {
+        let mut turn = None;
+        loop {
+            match self.turns.try_recv() {
+                Ok(direction) => turn = Some(direction),
+                Err(TryRecvError::Empty) => return Ok(turn),
+                Err(TryRecvError::Disconnected) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("{} left", self.player),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, simulation: &Simulation) -> io::Result<()> {
+        self.stream
+            .write_all(frame_line(&Frame::new(simulation, self.player))?.as_bytes())
+    }
+}
+
+fn frame_line(frame: &Frame) -> io::Result<String> {
+    let mut line = serde_json::to_string(frame)?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// The clients watching, and the frames they have yet to be sent.
+#[derive(Default)]
+struct Spectators {
+    streams: Vec<TcpStream>,
+    delayed: VecDeque<String>,
+}
+
+impl Spectators {
+    /// Starts sending frames to `stream`. A spectator never holds the game
+    /// up: one that cannot take a frame straight away is dropped.
+    fn join(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.streams.push(stream);
+        Ok(())
+    }
+
+    /// Queues the frame after a move, sending the one `SPECTATOR_DELAY`
+    /// moves older.
+    fn record(&mut self, simulation: &Simulation) -> io::Result<()> {
+        self.delayed
+            .push_back(frame_line(&Frame::spectated(simulation))?);
+        while self.delayed.len() > SPECTATOR_DELAY {
+            let line = self.delayed.pop_front().unwrap();
+            self.broadcast(&line);
+        }
+        Ok(())
+    }
+
+    /// Sends every queued frame, once the run is over and there is nothing
+    /// left to give away.
+    fn flush(&mut self) {
+        while let Some(line) = self.delayed.pop_front() {
+            self.broadcast(&line);
+        }
+    }
+
+    fn broadcast(&mut self, line: &str) {
+        self.streams
+            .retain_mut(|stream| stream.write_all(line.as_bytes()).is_ok());
+    }
+}
+
+/// How many players the game `options` describe takes.
+pub fn seats(options: &Options) -> usize {
+    match GameMode::from_options(options) {
+        GameMode::VersusBot => 1,
+        mode => mode.players(VersusPlayers::from_options(options)).len(),
+    }
+}
+
+/// Plays one run in real time with the players in `seats`, until it ends or
+/// a player leaves. Clients that connect on `joins` meanwhile watch.
+fn play(
+    options: &Options,
+    seed: u64,
+    seats: &mut [Seat],
+    spectators: &mut Spectators,
+    joins: &Receiver<TcpStream>,
+) -> io::Result<()> {
+    let mut simulation = Simulation::new(options, seed);
+    for seat in seats.iter_mut() {
+        seat.send(&simulation)?;
+    }
+    spectators.record(&simulation)?;
+    // Errors break out of the loop rather than return, so the spectators
+    // are sent what they are owed of an abandoned run too.
+    let result = loop {
+        let started = Instant::now();
+        for stream in joins.try_iter() {
+            if let Err(err) = spectators.join(stream) {
+                eprintln!("could not add a spectator: {}", err);
+            }
+        }
+        let steered = seats.iter().try_for_each(|seat| {
+            if let Some(direction) = seat.turn()? {
+                simulation.steer_player(seat.player, direction);
+            }
+            Ok(())
+        });
+        if let Err(err) = steered {
+            break Err(err);
+        }
+        let running = simulation.step();
+        let sent = seats
+            .iter_mut()
+            .try_for_each(|seat| seat.send(&simulation))
+            .and_then(|()| spectators.record(&simulation));
+        if let Err(err) = sent {
+            break Err(err);
+        }
+        if !running {
+            break Ok(());
+        }
+        let interval = Duration::from_secs_f32(simulation.move_interval());
+        thread::sleep(interval.saturating_sub(started.elapsed()));
+    };
+    spectators.flush();
+    result
+}
+
+/// Serves runs of the game `options` describe on `address`, one after
+/// another, for as long as the process lives.
+pub fn run(options: &Options, address: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let players = seats(options);
+    let mut seed = options.seed.unwrap_or_else(rand::random);
+    println!(
+        "listening on {}, {} player(s) a run",
+        listener.local_addr()?,
+        players
+    );
+    // Connections are taken on a thread of their own, so spectators can
+    // join during a run.
+    let (joined, joins) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if joined.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => eprintln!("could not accept a client: {}", err),
+            }
+        }
+    });
+    let mut spectators = Spectators::default();
+    loop {
+        let mut seats = Vec::with_capacity(players);
+        for player in Player::ALL.iter().take(players) {
+            let stream = joins.recv().map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "stopped accepting clients")
+            })?;
+            println!("{} joined from {}", player, stream.peer_addr()?);
+            seats.push(Seat::new(*player, stream)?);
+        }
+        match play(options, seed, &mut seats, &mut spectators, &joins) {
+            Ok(()) => println!("run over"),
+            Err(err) => println!("run abandoned: {}", err),
+        }
+        seed = seed.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions_survive_the_wire() {
+        for direction in Direction::ALL.iter() {
+            assert_eq!(
+                parse_direction(direction_name(*direction)),
+                Some(*direction)
+            );
+        }
+        assert_eq!(parse_direction("up\r"), Some(Direction::Up));
+        assert_eq!(parse_direction("sideways"), None);
+    }
+
+    /// A client that joins is sent the run as it plays out, and steers its
+    /// snake in it.
+    #[test]
+    fn a_client_steers_its_snake_on_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = Options {
+            seed: Some(3),
+            speed: Some(20),
+            ..Default::default()
+        };
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut seats = [Seat::new(Player::One, stream).unwrap()];
+            let mut spectators = Spectators::default();
+            let result = play(&options, 3, &mut seats, &mut spectators, &mpsc::channel().1);
+            (result, spectators.delayed.len())
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut frame =
+            || -> Frame { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+        let column = |frame: &Frame| {
+            frame
+                .cells()
+                .iter()
+                .position(|cell| *cell == Cell::Head)
+                .unwrap() as u32
+                % frame.width
+        };
+
+        let first = frame();
+        assert_eq!((first.player, first.over), (1, false));
+        assert!(first.cells().iter().all(|cell| *cell != Cell::Rival));
+        writeln!(stream, "left").unwrap();
+        // The turn may only reach the server a move or two later.
+        let turned = (0..5)
+            .map(|_| frame())
+            .any(|moved| column(&moved) < column(&first));
+        assert!(turned);
+
+        // The run is abandoned once the player leaves, and no frame of it
+        // is left over for the spectators of the next.
+        drop(lines);
+        drop(stream);
+        let (result, delayed) = server.join().unwrap();
+        result.unwrap_err();
+        assert_eq!(delayed, 0);
+    }
+
+    /// Four clients each get a snake of their own in a versus round on one
+    /// grid, and see the others' snakes as rivals until two of them meet.
+    #[test]
+    fn four_clients_battle_on_one_grid() {
+        let options = Options {
+            versus: true,
+            players: Some(4),
+            seed: Some(3),
+            speed: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(seats(&options), 4);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut seats: Vec<Seat> = Player::ALL
+                .iter()
+                .map(|player| Seat::new(*player, listener.accept().unwrap().0).unwrap())
+                .collect();
+            play(
+                &options,
+                3,
+                &mut seats,
+                &mut Spectators::default(),
+                &mpsc::channel().1,
+            )
+        });
+        let mut clients: Vec<_> = (0..4)
+            .map(|_| BufReader::new(TcpStream::connect(address).unwrap()).lines())
+            .collect();
+        let frame = |lines: &mut std::io::Lines<BufReader<TcpStream>>| -> Frame {
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+        };
+
+        for (number, lines) in (1..).zip(&mut clients) {
+            let first = frame(lines);
+            assert_eq!(first.player, number);
+            let count = |cell| first.cells().iter().filter(|seen| **seen == cell).count();
+            assert_eq!((count(Cell::Head), count(Cell::Rival)), (1, 6));
+        }
+        // Players 1 and 4 start in the same column heading at each other.
+        for lines in &mut clients {
+            let last = (0..100).map(|_| frame(lines)).find(|frame| frame.over);
+            assert!(last.is_some());
+        }
+        server.join().unwrap().unwrap();
+    }
+
+    /// Spectators only see a move once the players are `SPECTATOR_DELAY`
+    /// moves past it, and see the rest at the end of the run.
+    #[test]
+    fn spectators_watch_from_behind() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let watcher = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut spectators = Spectators::default();
+        spectators.join(listener.accept().unwrap().0).unwrap();
+        let mut simulation = Simulation::new(&Options::default(), 8);
+        let mut lines = BufReader::new(watcher).lines();
+
+        for _ in 0..SPECTATOR_DELAY {
+            spectators.record(&simulation).unwrap();
+            simulation.step();
+        }
+        spectators.record(&simulation).unwrap();
+        let first: Frame = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(first.player, 0);
+        assert_eq!(spectators.delayed.len(), SPECTATOR_DELAY);
+
+        spectators.flush();
+        let last = (0..SPECTATOR_DELAY)
+            .map(|_| lines.next().unwrap().unwrap())
+            .last()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Frame>(&last).unwrap(),
+            Frame::spectated(&simulation)
+        );
+    }
+}
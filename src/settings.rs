@@ -0,0 +1,99 @@
+//! User settings that survive a restart.
+
+use crate::render::{Patterns, ShakeIntensity};
+use crate::sound::Volume;
+use crate::Difficulty;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything the player can configure. Fields missing from an older file
+/// take their defaults and unknown ones are ignored, so upgrading never wipes
+/// the file.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Name of the picked `Theme`.
+    pub theme: String,
+    pub difficulty: Difficulty,
+    pub shake: ShakeIntensity,
+    pub patterns: Patterns,
+    /// Last, as TOML writes tables after plain values.
+    pub audio: Volume,
+}
+
+impl Settings {
+    /// `settings.toml` in the platform config directory, e.g.
+    /// `~/.config/bevy-snake` on Linux.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("bevy-snake").join("settings.toml"))
+    }
+
+    /// Reads the settings at `path`, falling back to the defaults when the file
+    /// is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse().unwrap_or_else(|err| {
+                eprintln!("could not read settings {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => {
+                eprintln!("could not read settings {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text =
+            toml::to_string(self).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, text)
+    }
+}
+
+impl std::str::FromStr for Settings {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_toml() {
+        let settings = Settings {
+            theme: "HighContrast".to_string(),
+            difficulty: Difficulty::Hard,
+            shake: ShakeIntensity(0.0),
+            patterns: Patterns(true),
+            audio: Volume {
+                music: 0.25,
+                muted: true,
+                ..Default::default()
+            },
+        };
+        let text = toml::to_string(&settings).unwrap();
+        assert_eq!(text.parse::<Settings>().unwrap(), settings);
+    }
+
+    #[test]
+    fn tolerates_old_and_new_files() {
+        let settings: Settings = "theme = \"Dark\"\nvolume = 0.5\n".parse().unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                theme: "Dark".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,415 @@
+//! Short sound effects: a blip for food eaten, a click for every turn and a
+//! falling tune for a lost life. The samples are loaded in `setup` next to
+//! the `Materials`, as is the music.
+//!
+//! The music is one bar played over and over. Bevy's audio can neither loop
+//! a sound nor change how fast it plays, so each bar is queued as the last
+//! one ends, and the bar chosen gets faster and busier as the snake grows.
+//!
+//! Nor can Bevy's audio play a sound more quietly, so a sound is turned down
+//! by making a quieter copy of it: the samples are MPEG-1 Layer I, whose
+//! frames scale every subband by a factor kept apart from the samples, and
+//! the copy only has those factors lowered. Muting stops the next sound from
+//! playing; one already playing plays out.
+
+use crate::bot::Bot;
+use crate::{
+    expired_timer, AppState, Direction, GameOverEvent, GrowthEvent, SnakeHead, SnakeSegments,
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How loud the game plays, each from 0.0 for silent to 1.0 for as
+/// recorded. Music and effects are both scaled by `master` as well.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Volume {
+    pub master: f32,
+    pub music: f32,
+    pub effects: f32,
+    /// Silences everything without touching the levels; M toggles it.
+    pub muted: bool,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 0.5,
+            effects: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl Volume {
+    pub fn music_gain(&self) -> f32 {
+        self.gain(self.music)
+    }
+
+    pub fn effects_gain(&self) -> f32 {
+        self.gain(self.effects)
+    }
+
+    fn gain(&self, level: f32) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            (self.master * level).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// The level after `level` on the settings screen: a quarter quieter, and
+/// back to full from silent.
+pub fn next_level(level: f32) -> f32 {
+    if level <= 0.0 {
+        1.0
+    } else {
+        ((level * 4.0).round() - 1.0).max(0.0) / 4.0
+    }
+}
+
+/// Bytes of an MPEG-1 Layer I frame header.
+const LAYER_I_HEADER: usize = 4;
+
+/// Layer I bit rates in kbit/s, by the header's bit rate index.
+const LAYER_I_BIT_RATES: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+
+/// Largest valid scale factor index; each step down is 2 dB quieter.
+const MAX_SCALE_FACTOR: u32 = 62;
+
+fn read_bits(data: &[u8], at: usize, count: usize) -> u32 {
+    (at..at + count).fold(0, |bits, bit| {
+        bits << 1 | (data[bit / 8] >> (7 - bit % 8) & 1) as u32
+    })
+}
+
+fn write_bits(data: &mut [u8], at: usize, count: usize, value: u32) {
+    for (i, bit) in (at..at + count).enumerate() {
+        let mask = 1 << (7 - bit % 8);
+        if value >> (count - 1 - i) & 1 == 1 {
+            data[bit / 8] |= mask;
+        } else {
+            data[bit / 8] &= !mask;
+        }
+    }
+}
+
+/// A copy of the MPEG-1 Layer I stream `bytes`, `gain` times as loud, to
+/// within 2 dB. `None` if the stream is in any other format, or has
+/// checksums, which the copy would fail.
+pub fn attenuate(bytes: &[u8], gain: f32) -> Option<Vec<u8>> {
+    let steps = (-3.0 * gain.max(f32::EPSILON).log2()).round().max(0.0) as u32;
+    let mut quieter = bytes.to_vec();
+    let mut frame = 0;
+    while frame < quieter.len() {
+        let data = quieter.get_mut(frame..)?;
+        if data.len() < LAYER_I_HEADER {
+            return None;
+        }
+        // Sync word, MPEG-1, Layer I, no checksum.
+        if read_bits(data, 0, 16) != 0xffff {
+            return None;
+        }
+        let bit_rate = *LAYER_I_BIT_RATES.get(read_bits(data, 16, 4) as usize)?;
+        let sample_rate = [44_100, 48_000, 32_000].get(read_bits(data, 20, 2) as usize)?;
+        let padding = read_bits(data, 22, 1);
+        let channels = if read_bits(data, 24, 2) == 0b11 { 1 } else { 2 };
+        // Joint stereo shares the upper subbands' allocations between the
+        // channels, which this does not follow.
+        if bit_rate == 0 || read_bits(data, 24, 2) == 0b01 {
+            return None;
+        }
+        let length = ((12 * bit_rate * 1000 / sample_rate + padding) * 4) as usize;
+        if data.len() < length {
+            return None;
+        }
+        let allocations = LAYER_I_HEADER * 8;
+        let mut scale_factor = allocations + 32 * channels * 4;
+        for band in 0..32 * channels {
+            if read_bits(data, allocations + band * 4, 4) != 0 {
+                let index = read_bits(data, scale_factor, 6);
+                write_bits(data, scale_factor, 6, (index + steps).min(MAX_SCALE_FACTOR));
+                scale_factor += 6;
+            }
+        }
+        frame += length;
+    }
+    Some(quieter)
+}
+
+/// A sound as loaded, and the copy of it played at the volume set.
+pub struct Sample {
+    source: Handle<AudioSource>,
+    playing: Option<Handle<AudioSource>>,
+    /// Where the quieter copy is kept, once one was made; each volume change
+    /// overwrites it rather than adding another.
+    quieter: Option<Handle<AudioSource>>,
+    /// The gain `playing` was made for, once the sound has loaded.
+    gain: Option<f32>,
+}
+
+impl Sample {
+    pub fn load(asset_server: &AssetServer, path: &str) -> Self {
+        Self {
+            source: asset_server.load(path),
+            playing: None,
+            quieter: None,
+            gain: None,
+        }
+    }
+
+    /// Makes the copy played `gain` times as loud as the sound, once it has
+    /// loaded. Sounds in a format `attenuate` does not know play as loaded.
+    fn set_gain(&mut self, gain: f32, sources: &mut Assets<AudioSource>) {
+        if self.gain == Some(gain) {
+            return;
+        }
+        let bytes = match sources.get(&self.source) {
+            Some(source) => source.bytes.clone(),
+            None => return,
+        };
+        self.playing = if gain <= 0.0 {
+            None
+        } else if gain >= 1.0 {
+            Some(self.source.clone())
+        } else {
+            match attenuate(&bytes, gain) {
+                Some(quieter) => {
+                    let source = AudioSource {
+                        bytes: quieter.into(),
+                    };
+                    let handle = match &self.quieter {
+                        Some(handle) => sources.set(handle, source),
+                        None => sources.add(source),
+                    };
+                    self.quieter = Some(handle.clone());
+                    Some(handle)
+                }
+                None => Some(self.source.clone()),
+            }
+        };
+        self.gain = Some(gain);
+    }
+
+    fn play(&self, audio: &Audio) {
+        if let Some(playing) = &self.playing {
+            audio.play(playing.clone());
+        }
+    }
+}
+
+pub struct Sounds {
+    pub eat: Sample,
+    pub turn: Sample,
+    pub death: Sample,
+}
+
+impl Sounds {
+    pub fn load(asset_server: &AssetServer) -> Self {
+        Self {
+            eat: Sample::load(asset_server, "sounds/eat.mp3"),
+            turn: Sample::load(asset_server, "sounds/turn.mp3"),
+            death: Sample::load(asset_server, "sounds/death.mp3"),
+        }
+    }
+}
+
+/// Snake lengths from which each bar of the music plays, slowest first.
+pub const MUSIC_LEVEL_LENGTHS: [usize; 4] = [0, 10, 20, 35];
+
+/// How long each bar of the music lasts, in seconds.
+const MUSIC_BAR_SECONDS: [f32; 4] = [1.6, 1.28, 1.024, 0.832];
+
+/// The bar of music for a snake `length` cells long.
+pub fn music_level(length: usize) -> usize {
+    MUSIC_LEVEL_LENGTHS
+        .iter()
+        .rposition(|shortest| length >= *shortest)
+        .unwrap_or(0)
+}
+
+/// The music's bars, and the time left of the one playing.
+pub struct Music {
+    bars: Vec<Sample>,
+    playing: Timer,
+}
+
+impl Music {
+    pub fn load(asset_server: &AssetServer) -> Self {
+        Self {
+            bars: (0..MUSIC_LEVEL_LENGTHS.len())
+                .map(|level| Sample::load(asset_server, &format!("music/level{}.mp3", level)))
+                .collect(),
+            playing: expired_timer(0.0),
+        }
+    }
+}
+
+/// Queues the next bar of music as the last one ends, picked by the length
+/// of the longest snake.
+pub fn play_music(
+    (time, audio): (Res<Time>, Res<Audio>),
+    mut music: ResMut<Music>,
+    snakes: Query<&SnakeSegments>,
+) {
+    music.playing.tick(time.delta_seconds);
+    if !music.playing.finished {
+        return;
+    }
+    let length = snakes
+        .iter()
+        .map(|segments| segments.len() + 1)
+        .max()
+        .unwrap_or(0);
+    let level = music_level(length);
+    music.bars[level].play(&audio);
+    music.playing = Timer::from_seconds(MUSIC_BAR_SECONDS[level], false);
+}
+
+/// Plays the sound of everything that happened this frame. Only the snakes
+/// players steer click when they turn; a bot turns far too often.
+pub fn play_sounds(
+    (audio, sounds): (Res<Audio>, Res<Sounds>),
+    (mut growth_reader, growth_events): (Local<EventReader<GrowthEvent>>, Res<Events<GrowthEvent>>),
+    (mut game_over_reader, game_over_events): (
+        Local<EventReader<GameOverEvent>>,
+        Res<Events<GameOverEvent>>,
+    ),
+    mut headings: Local<HashMap<Entity, Direction>>,
+    heads: Query<Without<Bot, (Entity, &SnakeHead)>>,
+) {
+    if growth_reader.iter(&growth_events).next().is_some() {
+        sounds.eat.play(&audio);
+    }
+    if game_over_reader.iter(&game_over_events).next().is_some() {
+        sounds.death.play(&audio);
+    }
+    // Despawned snakes are forgotten.
+    headings.retain(|entity, _| heads.get(*entity).is_ok());
+    let mut turned = false;
+    for (entity, head) in heads.iter() {
+        if let Some(before) = headings.insert(entity, head.direction) {
+            turned |= before != head.direction;
+        }
+    }
+    if turned {
+        sounds.turn.play(&audio);
+    }
+}
+
+/// Keeps every sound's copy at the volume set, making new copies as the
+/// sounds load or the volume changes.
+pub fn apply_volume(
+    volume: Res<Volume>,
+    mut sources: ResMut<Assets<AudioSource>>,
+    (mut sounds, mut music): (ResMut<Sounds>, ResMut<Music>),
+) {
+    let effects = volume.effects_gain();
+    let Sounds { eat, turn, death } = &mut *sounds;
+    for sample in &mut [eat, turn, death] {
+        sample.set_gain(effects, &mut sources);
+    }
+    let gain = volume.music_gain();
+    for bar in &mut music.bars {
+        bar.set_gain(gain, &mut sources);
+    }
+}
+
+/// Mutes and unmutes everything with M.
+pub fn toggle_mute(
+    (keyboard_input, state): (Res<Input<KeyCode>>, Res<AppState>),
+    mut volume: ResMut<Volume>,
+) {
+    if keyboard_input.just_pressed(KeyCode::M) && !state.typing() {
+        volume.muted = !volume.muted;
+    }
+}
+
+/// Plays the sound effects and the music at the volume set; `setup` loads
+/// them.
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(toggle_mute.system())
+            .add_system(apply_volume.system())
+            .add_system(play_sounds.system())
+            .add_system(play_music.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_music_speeds_up_as_the_snake_grows() {
+        assert_eq!(music_level(0), 0);
+        assert_eq!(music_level(3), 0);
+        assert_eq!(music_level(10), 1);
+        assert_eq!(music_level(34), 2);
+        assert_eq!(music_level(400), MUSIC_LEVEL_LENGTHS.len() - 1);
+    }
+
+    #[test]
+    fn volume_steps_down_and_mutes() {
+        assert_eq!(next_level(1.0), 0.75);
+        assert_eq!(next_level(0.25), 0.0);
+        assert_eq!(next_level(0.0), 1.0);
+        let mut volume = Volume {
+            master: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(volume.effects_gain(), 0.5);
+        volume.muted = true;
+        assert_eq!((volume.music_gain(), volume.effects_gain()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn quieter_copies_only_lower_the_scale_factors() {
+        let eat = include_bytes!("../assets/sounds/eat.mp3");
+        assert_eq!(attenuate(eat, 1.0).as_deref(), Some(&eat[..]));
+        let half = attenuate(eat, 0.5).unwrap();
+        assert_eq!(half.len(), eat.len());
+        // The first frame sounds one subband; its scale factor follows the
+        // header and the 32 allocations.
+        let at = (LAYER_I_HEADER + 16) * 8;
+        assert_eq!(read_bits(&half, at, 6), read_bits(eat, at, 6) + 3);
+        let differing = eat.iter().zip(&half).filter(|(a, b)| a != b).count();
+        assert!(differing > 0 && differing <= 2 * eat.len() / 128);
+
+        assert_eq!(attenuate(b"ID3\x03", 0.5), None);
+    }
+
+    #[test]
+    fn volume_changes_reuse_the_quieter_copy() {
+        let mut builder = App::build();
+        builder
+            .add_plugin(bevy::type_registry::TypeRegistryPlugin)
+            .add_plugin(bevy::core::CorePlugin)
+            .add_plugin(bevy::asset::AssetPlugin)
+            .add_asset::<AudioSource>();
+        let mut sources = builder
+            .resources()
+            .get_mut::<Assets<AudioSource>>()
+            .unwrap();
+        let mut sample = Sample {
+            source: sources.add(AudioSource {
+                bytes: include_bytes!("../assets/sounds/eat.mp3")[..].into(),
+            }),
+            playing: None,
+            quieter: None,
+            gain: None,
+        };
+        for gain in [0.75, 0.5, 1.0, 0.25, 0.0, 0.5] {
+            sample.set_gain(gain, &mut sources);
+        }
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sample.playing, sample.quieter);
+    }
+}
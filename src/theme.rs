@@ -0,0 +1,295 @@
+//! Color themes defined in RON files in `assets/themes`, picked on the
+//! settings screen or cycled with T. The bundled themes are built in, so the
+//! game has them even without its assets; files dropped in the folder add
+//! themes, or replace the built-in ones of the same name.
+
+use crate::SEGMENT_GRADIENT_STEPS;
+use bevy::asset::{AssetLoader, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::type_registry::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Deserializer};
+
+/// A named palette, e.g.
+///
+/// ```ron
+/// (
+///     name: "Classic",
+///     letterbox: "#000000",
+///     background: "#141414",
+///     head: "#00ff33",
+///     segment: "#4d8033",
+///     tail: "#1a3314",
+///     food: "#ff00ff",
+///     text: "#ffffff",
+/// )
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize, TypeUuid)]
+#[uuid = "9b4e2c71-3d58-4f0a-b6e7-81c5a2d94f30"]
+pub struct Theme {
+    pub name: String,
+    /// Color of the bars around the arena on non-square windows.
+    #[serde(deserialize_with = "hex_color")]
+    letterbox: Color,
+    /// Color of the light squares of the arena; the dark ones are a shade
+    /// darker.
+    #[serde(deserialize_with = "hex_color")]
+    background: Color,
+    #[serde(deserialize_with = "hex_color")]
+    head: Color,
+    #[serde(deserialize_with = "hex_color")]
+    segment: Color,
+    /// Color the body fades to towards the tail.
+    #[serde(deserialize_with = "hex_color")]
+    tail: Color,
+    #[serde(deserialize_with = "hex_color")]
+    food: Color,
+    #[serde(deserialize_with = "hex_color")]
+    text: Color,
+}
+
+/// Reads an sRGB color written `#rrggbb`, or `#rrggbbaa` with alpha.
+fn hex_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    Color::hex(hex.trim_start_matches('#'))
+        .map_err(|_| serde::de::Error::custom(format!("{} is not a color", hex)))
+}
+
+/// The themes that ship in `assets/themes`, in the order T cycles them.
+const BUNDLED: [&str; 7] = [
+    include_str!("../assets/themes/classic.theme"),
+    include_str!("../assets/themes/dark.theme"),
+    include_str!("../assets/themes/high_contrast.theme"),
+    include_str!("../assets/themes/neon.theme"),
+    include_str!("../assets/themes/retro_lcd.theme"),
+    include_str!("../assets/themes/deuteranopia.theme"),
+    include_str!("../assets/themes/protanopia.theme"),
+];
+
+impl Theme {
+    fn bundled() -> Vec<Theme> {
+        BUNDLED
+            .iter()
+            .map(|text| ron::de::from_str(text).expect("bundled theme"))
+            .collect()
+    }
+
+    pub fn letterbox(&self) -> Color {
+        self.letterbox
+    }
+
+    pub fn background(&self) -> Color {
+        self.background
+    }
+
+    pub fn head(&self) -> Color {
+        self.head
+    }
+
+    pub fn segment(&self) -> Color {
+        self.segment
+    }
+
+    /// Body color at `step` of the `SEGMENT_GRADIENT_STEPS` from segment to tail.
+    pub fn segment_shade(&self, step: usize) -> Color {
+        let t = step as f32 / (SEGMENT_GRADIENT_STEPS - 1) as f32;
+        self.segment * (1.0 - t) + self.tail * t
+    }
+
+    pub fn food(&self) -> Color {
+        self.food
+    }
+
+    pub fn text(&self) -> Color {
+        self.text
+    }
+
+    /// Translucent body color used while ghost mode is active.
+    pub fn ghost_segment(&self) -> Color {
+        let mut color = self.segment;
+        color.set_a(0.35);
+        color
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::bundled().remove(0)
+    }
+}
+
+#[derive(Default)]
+pub struct ThemeLoader;
+
+impl AssetLoader for ThemeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let theme: Theme = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(theme));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["theme"]
+    }
+}
+
+/// Every theme there is to pick from, and the name of the one picked. The
+/// `Theme` resource holds a copy of the picked one, which `cycle_theme`
+/// recolors the game with.
+pub struct Themes {
+    themes: Vec<Theme>,
+    chosen: String,
+    /// Theme files being loaded; held so they stay loaded.
+    folder: Vec<HandleUntyped>,
+}
+
+impl Default for Themes {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl Themes {
+    /// The bundled themes with `chosen` picked.
+    pub fn new(chosen: &str) -> Self {
+        Self {
+            themes: Theme::bundled(),
+            chosen: chosen.to_string(),
+            folder: Vec::new(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Theme> {
+        self.themes.iter().find(|theme| theme.name == name)
+    }
+
+    /// The theme picked, or the first while there is no theme of that name,
+    /// e.g. until the file it comes from has loaded.
+    pub fn current(&self) -> &Theme {
+        self.get(&self.chosen).unwrap_or(&self.themes[0])
+    }
+
+    pub fn chosen(&self) -> &str {
+        &self.chosen
+    }
+
+    /// Picks the theme after the current one, and the first after the last.
+    pub fn next(&mut self) {
+        let current = &self.current().name;
+        let at = self
+            .themes
+            .iter()
+            .position(|theme| theme.name == *current)
+            .unwrap_or(0);
+        self.chosen = self.themes[(at + 1) % self.themes.len()].name.clone();
+    }
+
+    /// Adds `theme`, or replaces the one of the same name.
+    fn insert(&mut self, theme: Theme) {
+        match self
+            .themes
+            .iter_mut()
+            .find(|known| known.name == theme.name)
+        {
+            Some(known) => *known = theme,
+            None => self.themes.push(theme),
+        }
+    }
+}
+
+pub fn load_themes(asset_server: Res<AssetServer>, mut themes: ResMut<Themes>) {
+    match asset_server.load_folder("themes") {
+        Ok(folder) => themes.folder = folder,
+        Err(err) => eprintln!("could not load themes: {:?}", err),
+    }
+}
+
+/// Takes in each theme file as it loads, or changes on disk.
+pub fn collect_themes(
+    mut reader: Local<EventReader<AssetEvent<Theme>>>,
+    asset_events: Res<Events<AssetEvent<Theme>>>,
+    assets: Res<Assets<Theme>>,
+    mut themes: ResMut<Themes>,
+) {
+    for event in reader.iter(&asset_events) {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                if let Some(theme) = assets.get(handle) {
+                    themes.insert(theme.clone());
+                }
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+}
+
+/// Loads the theme files. The `Themes` resource must be added first.
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<Theme>()
+            .init_asset_loader::<ThemeLoader>()
+            .add_startup_system(load_themes.system())
+            .add_system(collect_themes.system());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bundled_themes_have_names_of_their_own() {
+        let themes = Theme::bundled();
+        let names: Vec<&str> = themes.iter().map(|theme| theme.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Classic",
+                "Dark",
+                "HighContrast",
+                "Neon",
+                "RetroLcd",
+                "Deuteranopia",
+                "Protanopia"
+            ]
+        );
+        assert_eq!(themes[0].head(), Color::hex("00ff33").unwrap());
+    }
+
+    #[test]
+    fn food_stands_apart_from_the_snake_in_every_bundled_theme() {
+        for theme in Theme::bundled() {
+            assert_ne!(theme.food(), theme.head(), "{}", theme.name);
+            assert_ne!(theme.food(), theme.segment(), "{}", theme.name);
+        }
+    }
+
+    #[test]
+    fn picking_cycles_through_every_theme() {
+        let mut themes = Themes::new("Neon");
+        themes.next();
+        assert_eq!(themes.chosen(), "RetroLcd");
+        themes.next();
+        assert_eq!(themes.current().name, "Deuteranopia");
+        themes.next();
+        themes.next();
+        assert_eq!(themes.current().name, "Classic");
+        assert_eq!(Themes::new("Missing").current().name, "Classic");
+
+        themes.insert(Theme {
+            name: "Mine".to_string(),
+            ..Default::default()
+        });
+        themes.chosen = "Protanopia".to_string();
+        themes.next();
+        assert_eq!(themes.chosen(), "Mine");
+    }
+}